@@ -0,0 +1,10 @@
+#version 430 core
+
+in vec4 vert_color;
+
+out vec4 color;
+
+void main()
+{
+    color = vert_color;
+}
@@ -0,0 +1,13 @@
+#version 430 core
+layout (location = 0) in vec3 position;
+layout (location = 1) in vec4 color;
+
+uniform mat4 camera;
+
+out vec4 vert_color;
+
+void main()
+{
+    vert_color = color;
+    gl_Position = camera * vec4(position, 1.0);
+}
@@ -0,0 +1,14 @@
+#version 430 core
+
+in vec2 vert_uv;
+in vec4 vert_color;
+
+// Colors and textures are premultiplied by alpha
+uniform sampler2D gui_texture;
+
+out vec4 color;
+
+void main()
+{
+    color = vert_color * texture(gui_texture, vert_uv);
+}
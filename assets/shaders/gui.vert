@@ -0,0 +1,18 @@
+#version 430 core
+layout (location = 0) in vec2 position;
+layout (location = 1) in vec2 uv;
+layout (location = 2) in vec4 color;
+
+// Window size in GUI points, positions are in points from the top left corner
+uniform vec2 screen_size;
+
+out vec2 vert_uv;
+out vec4 vert_color;
+
+void main()
+{
+    vert_uv = uv;
+    vert_color = color;
+    vec2 ndc = position / screen_size * 2.0 - 1.0;
+    gl_Position = vec4(ndc.x, -ndc.y, 0.0, 1.0);
+}
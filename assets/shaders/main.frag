@@ -1,13 +1,23 @@
 #version 430 core
 
+in vec3 vert_position;
 in vec3 vert_normal;
 in vec4 vert_color;
 
+uniform vec3 camera_position;
+// Set per material by the render queue, 0 is plain diffuse and 1 a perfect mirror of the environment
+uniform float reflectivity;
+layout (binding = 1) uniform samplerCube environment;
+
 out vec4 color;
 
 void main()
 {
     // TODO: this should be a uniform
     vec3 lightDirection = normalize(vec3(0.8, -0.5, 0.6));
-    color = vec4(vert_color.xyz * max(dot(vert_normal, -lightDirection), 0), vert_color.w);
-}
\ No newline at end of file
+    vec3 normal = normalize(vert_normal);
+    vec3 diffuse = vert_color.xyz * max(dot(normal, -lightDirection), 0);
+
+    vec3 reflected = reflect(normalize(vert_position - camera_position), normal);
+    color = vec4(mix(diffuse, texture(environment, reflected).rgb, reflectivity), vert_color.w);
+}
@@ -6,12 +6,15 @@ layout (location = 3) in mat4 instance_transform;
 
 uniform mat4 camera;
 
+out vec3 vert_position;
 out vec3 vert_normal;
 out vec4 vert_color;
 
 void main()
 {
+    vec4 world_position = instance_transform * vec4(position, 1.0);
+    vert_position = world_position.xyz;
     vert_normal = normalize(mat3(instance_transform) * normal);
     vert_color = color;
-    gl_Position = camera * instance_transform * vec4(position, 1.0);
+    gl_Position = camera * world_position;
 }
\ No newline at end of file
@@ -0,0 +1,10 @@
+#version 430 core
+
+uniform vec4 outline_color;
+
+out vec4 color;
+
+void main()
+{
+    color = outline_color;
+}
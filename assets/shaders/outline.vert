@@ -0,0 +1,15 @@
+#version 430 core
+layout (location = 0) in vec3 position;
+layout (location = 1) in vec3 normal;
+layout (location = 3) in mat4 instance_transform;
+
+uniform mat4 camera;
+uniform float outline_width;
+
+void main()
+{
+    // Push the surface out along its normal, the part outside the original silhouette becomes the outline
+    vec4 world_position = instance_transform * vec4(position, 1.0);
+    vec3 world_normal = normalize(mat3(instance_transform) * normal);
+    gl_Position = camera * vec4(world_position.xyz + world_normal * outline_width, 1.0);
+}
@@ -0,0 +1,12 @@
+#version 430 core
+
+in vec3 vert_direction;
+
+layout (binding = 0) uniform samplerCube skybox;
+
+out vec4 color;
+
+void main()
+{
+    color = vec4(texture(skybox, vert_direction).rgb, 1.0);
+}
@@ -0,0 +1,14 @@
+#version 430 core
+layout (location = 0) in vec3 position;
+
+// Projection times the camera rotation, without the translation the sky stays infinitely far away
+uniform mat4 sky;
+
+out vec3 vert_direction;
+
+void main()
+{
+    vert_direction = position;
+    // Depth is w / w = 1, on the far plane
+    gl_Position = (sky * vec4(position, 1.0)).xyww;
+}
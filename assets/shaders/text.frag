@@ -0,0 +1,14 @@
+#version 430 core
+
+in vec2 vert_uv;
+in vec4 vert_color;
+
+// Single channel glyph atlas, the red channel is coverage
+uniform sampler2D atlas;
+
+out vec4 color;
+
+void main()
+{
+    color = vec4(vert_color.rgb, vert_color.a * texture(atlas, vert_uv).r);
+}
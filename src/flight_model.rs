@@ -0,0 +1,133 @@
+use crate::gl_utils::toolbox::Heading;
+
+/// Pilot inputs, the flight model eases towards them so the helicopter responds with some inertia
+#[derive(Debug, Clone, Copy)]
+pub struct FlightControls {
+    /// Main rotor blade pitch in [0, 1], more collective means more lift and a faster rotor
+    pub collective: f32,
+    /// Forward and sideways tilt of the rotor disc in [-1, 1], positive x tilts right and positive y tilts forward
+    pub cyclic: glm::Vec2,
+    /// Tail rotor in [-1, 1], positive turns right
+    pub pedals: f32,
+}
+
+impl FlightControls {
+    /// Inputs that keep the helicopter hovering in place once it has settled
+    pub fn hover() -> Self {
+        Self {
+            collective: FlightModel::hover_collective(),
+            cyclic: glm::zero(),
+            pedals: 0.0,
+        }
+    }
+}
+
+/// Rigid body helicopter. Attitude is not simulated through torques, the rotor disc follows the cyclic with a delay,
+/// but thrust, gravity and drag drive the linear motion
+pub struct FlightModel {
+    pub position: glm::Vec3,
+    pub velocity: glm::Vec3,
+    /// Radians, applied as yaw, then pitch, then roll in the body frame. The model's nose points along -z at yaw 0
+    pub yaw: f32,
+    pub pitch: f32,
+    pub roll: f32,
+    /// Fraction of nominal rotor speed
+    pub rotor_rpm: f32,
+    /// The helicopter can't sink below this height
+    pub ground_height: f32,
+}
+
+impl FlightModel {
+    const GRAVITY: f32 = 9.81;
+    /// Lift at full collective and nominal rotor speed, in g
+    const MAX_LIFT: f32 = 2.2;
+    /// Rotor speed at zero collective, in fractions of nominal speed
+    const IDLE_RPM: f32 = 0.5;
+    /// How quickly the rotor reaches the speed the collective asks for, per second
+    const SPOOL_RATE: f32 = 0.8;
+    const MAX_TILT: f32 = 0.45;
+    /// How quickly the attitude follows the cyclic, per second
+    const TILT_RATE: f32 = 3.0;
+    const MAX_YAW_RATE: f32 = 1.5;
+    const LINEAR_DRAG: f32 = 0.1;
+    const QUADRATIC_DRAG: f32 = 0.015;
+
+    /// Takes over from something flying with the given heading, the small pitch and roll of a path follower are close enough to body frame angles
+    pub fn new(heading: &Heading, velocity: glm::Vec3) -> Self {
+        Self {
+            position: glm::vec3(heading.x, heading.y, heading.z),
+            velocity,
+            yaw: heading.yaw,
+            pitch: heading.pitch,
+            roll: heading.roll,
+            rotor_rpm: FlightModel::target_rpm(FlightModel::hover_collective()),
+            ground_height: f32::MIN,
+        }
+    }
+
+    fn target_rpm(collective: f32) -> f32 {
+        FlightModel::IDLE_RPM + (1.0 - FlightModel::IDLE_RPM) * collective
+    }
+
+    /// Lift in g is MAX_LIFT * collective * rpm, solving for 1g with rpm at its target gives a quadratic in collective
+    pub fn hover_collective() -> f32 {
+        let a = FlightModel::MAX_LIFT * (1.0 - FlightModel::IDLE_RPM);
+        let b = FlightModel::MAX_LIFT * FlightModel::IDLE_RPM;
+        (-b + (b * b + 4.0 * a).sqrt()) / (2.0 * a)
+    }
+
+    pub fn step(&mut self, controls: &FlightControls, delta_time: f32) {
+        let collective = controls.collective.clamp(0.0, 1.0);
+        let cyclic = glm::clamp(&controls.cyclic, -1.0, 1.0);
+        let ease = |rate: f32| 1.0 - (-rate * delta_time).exp();
+
+        // Rotor speed lags behind the collective
+        self.rotor_rpm += (FlightModel::target_rpm(collective) - self.rotor_rpm) * ease(FlightModel::SPOOL_RATE);
+
+        // Negative pitch is nose down and negative roll banks right
+        let target_pitch = -cyclic.y * FlightModel::MAX_TILT;
+        let target_roll = -cyclic.x * FlightModel::MAX_TILT;
+        self.pitch += (target_pitch - self.pitch) * ease(FlightModel::TILT_RATE);
+        self.roll += (target_roll - self.roll) * ease(FlightModel::TILT_RATE);
+        self.yaw -= controls.pedals.clamp(-1.0, 1.0) * FlightModel::MAX_YAW_RATE * delta_time;
+        self.yaw = self.yaw.rem_euclid(2.0 * std::f32::consts::PI);
+
+        // Thrust points along the rotor shaft, which is the body's up axis
+        let up = glm::vec4_to_vec3(&(self.orientation() * glm::vec4(0.0, 1.0, 0.0, 0.0)));
+
+        let thrust = up * (FlightModel::MAX_LIFT * FlightModel::GRAVITY * collective * self.rotor_rpm);
+        let gravity = glm::vec3(0.0, -FlightModel::GRAVITY, 0.0);
+        let speed = glm::length(&self.velocity);
+        let drag = -self.velocity * (FlightModel::LINEAR_DRAG + FlightModel::QUADRATIC_DRAG * speed);
+
+        self.velocity += (thrust + gravity + drag) * delta_time;
+        self.position += self.velocity * delta_time;
+
+        // Landed, the ground takes whatever pushes down and friction stops sliding
+        if self.position.y < self.ground_height {
+            self.position.y = self.ground_height;
+            self.velocity.y = self.velocity.y.max(0.0);
+            self.velocity.x *= 1.0 - ease(4.0);
+            self.velocity.z *= 1.0 - ease(4.0);
+        }
+    }
+
+    fn orientation(&self) -> glm::Mat4 {
+        let orientation = glm::rotate_y(&glm::identity(), self.yaw);
+        let orientation = glm::rotate_x(&orientation, self.pitch);
+        glm::rotate_z(&orientation, self.roll)
+    }
+
+    /// SceneNode builds its rotation as Rx * Ry * Rz, which puts pitch around the world x axis, so the body frame angles are converted to that order
+    pub fn heading(&self) -> Heading {
+        let r = self.orientation();
+        Heading {
+            x: self.position.x,
+            y: self.position.y,
+            z: self.position.z,
+            yaw: r[(0, 2)].clamp(-1.0, 1.0).asin(),
+            pitch: (-r[(1, 2)]).atan2(r[(2, 2)]),
+            roll: (-r[(0, 1)]).atan2(r[(0, 0)]),
+        }
+    }
+}
@@ -0,0 +1,96 @@
+/// Fixed timestep simulation clock. Frames feed their real delta time into an accumulator
+/// which is drained in whole ticks, the leftover fraction is used to interpolate between the last two ticks when rendering
+pub struct GameLoop {
+    tick: f32,
+    accumulator: f32,
+    time_scale: f32,
+    paused: bool,
+    step_requested: bool,
+    sim_time: f32,
+    max_frame_time: f32,
+}
+
+impl GameLoop {
+    const MIN_TIME_SCALE: f32 = 1.0 / 16.0;
+    const MAX_TIME_SCALE: f32 = 4.0;
+
+    pub fn new(ticks_per_second: f32) -> Self {
+        Self {
+            tick: 1.0 / ticks_per_second,
+            accumulator: 0.0,
+            time_scale: 1.0,
+            paused: false,
+            step_requested: false,
+            sim_time: 0.0,
+            // Caps the ticks run after a long stall, otherwise catching up makes the next frame even slower
+            max_frame_time: 0.25,
+        }
+    }
+
+    /// Seconds of simulation time per tick
+    pub fn tick_length(&self) -> f32 {
+        self.tick
+    }
+
+    /// Seconds of simulation time since start, excludes time spent paused and is scaled by the time scale
+    #[allow(dead_code)]
+    pub fn sim_time(&self) -> f32 {
+        self.sim_time
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn time_scale(&self) -> f32 {
+        self.time_scale
+    }
+
+    /// Feed the real time of a frame, returns how many ticks the simulation should run this frame
+    pub fn advance(&mut self, delta_time: f32) -> usize {
+        if self.paused {
+            // Keep the interpolation where it was so the paused frame does not jitter
+            return if std::mem::take(&mut self.step_requested) {
+                self.sim_time += self.tick;
+                1
+            } else {
+                0
+            };
+        }
+
+        self.accumulator += delta_time.min(self.max_frame_time) * self.time_scale;
+
+        let mut ticks = 0;
+        while self.accumulator >= self.tick {
+            self.accumulator -= self.tick;
+            self.sim_time += self.tick;
+            ticks += 1;
+        }
+
+        ticks
+    }
+
+    /// How far between the previous and the latest tick the rendered frame is, in [0, 1)
+    pub fn alpha(&self) -> f32 {
+        self.accumulator / self.tick
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+        self.step_requested = false;
+        println!("Simulation {}", if self.paused { "paused" } else { "resumed" });
+    }
+
+    /// Run exactly one tick on the next frame, only has an effect while paused
+    pub fn step(&mut self) {
+        if self.paused {
+            self.step_requested = true;
+        }
+    }
+
+    /// Multiplies the time scale, clamped to [1/16, 4]
+    pub fn scale_time(&mut self, factor: f32) {
+        self.time_scale = (self.time_scale * factor).clamp(GameLoop::MIN_TIME_SCALE, GameLoop::MAX_TIME_SCALE);
+        println!("Time scale {}", self.time_scale);
+    }
+}
@@ -0,0 +1,370 @@
+use glutin::event::ElementState;
+
+use std::{
+    fs,
+    io,
+    str::FromStr,
+    sync::mpsc::Sender,
+    thread,
+    time::{Duration, Instant}
+};
+
+use crate::{gl_utils::parsing, input::InputEvent};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GamepadButton {
+    South,
+    East,
+    West,
+    North,
+    LeftBumper,
+    RightBumper,
+    Select,
+    Start,
+    Mode,
+    LeftStick,
+    RightStick,
+}
+
+impl GamepadButton {
+    pub const ALL: [GamepadButton; 11] = [
+        GamepadButton::South,
+        GamepadButton::East,
+        GamepadButton::West,
+        GamepadButton::North,
+        GamepadButton::LeftBumper,
+        GamepadButton::RightBumper,
+        GamepadButton::Select,
+        GamepadButton::Start,
+        GamepadButton::Mode,
+        GamepadButton::LeftStick,
+        GamepadButton::RightStick,
+    ];
+}
+
+impl FromStr for GamepadButton {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parsing::from_debug_name(&GamepadButton::ALL, s).ok_or(())
+    }
+}
+
+/// Sticks are in [-1, 1], triggers are in [0, 1]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GamepadAxis {
+    LeftStickX,
+    LeftStickY,
+    RightStickX,
+    RightStickY,
+    LeftTrigger,
+    RightTrigger,
+}
+
+impl GamepadAxis {
+    pub const ALL: [GamepadAxis; 6] = [
+        GamepadAxis::LeftStickX,
+        GamepadAxis::LeftStickY,
+        GamepadAxis::RightStickX,
+        GamepadAxis::RightStickY,
+        GamepadAxis::LeftTrigger,
+        GamepadAxis::RightTrigger,
+    ];
+}
+
+impl FromStr for GamepadAxis {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parsing::from_debug_name(&GamepadAxis::ALL, s).ok_or(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GamepadEvent {
+    Button(GamepadButton, ElementState),
+    Axis(GamepadAxis, f32),
+    Disconnected,
+}
+
+/// Source of gamepad events, next_event may block until the next event
+pub trait GamepadDevice: Send {
+    fn name(&self) -> &str;
+
+    /// Returns None when the device will not produce any more events
+    fn next_event(&mut self) -> Option<GamepadEvent>;
+}
+
+/// Polls the device on its own thread and forwards events to the render thread
+pub fn spawn_gamepad_thread(mut device: Box<dyn GamepadDevice>, tx: Sender<InputEvent>) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        println!("Using gamepad {}", device.name());
+        while let Some(event) = device.next_event() {
+            if tx.send(InputEvent::Gamepad(event)).is_err() {
+                return;
+            }
+        }
+
+        // Make sure nothing is stuck as held when the device goes away
+        if let Err(e) = tx.send(InputEvent::Gamepad(GamepadEvent::Disconnected)) {
+            eprintln!("Seems reciever has died, e: {}", e);
+        }
+    })
+}
+
+/// Virtual gamepad replaying a script, so gamepad input can be exercised without hardware.
+/// Each line of the script is '<seconds> button <GamepadButton> pressed|released' or '<seconds> axis <GamepadAxis> <value>'
+pub struct ScriptedGamepad {
+    events: Vec<(Duration, GamepadEvent)>,
+    next: usize,
+    start: Option<Instant>,
+}
+
+impl ScriptedGamepad {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let src = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Self::parse(&src)
+    }
+
+    pub fn parse(src: &str) -> Result<Self, String> {
+        let mut events = Vec::new();
+        for line in parsing::lines(src) {
+            let error = || format!("line {}: invalid gamepad script line '{}'", line.number, line.text);
+            let parts = &line.words;
+            if parts.len() != 4 {
+                return Err(error());
+            }
+
+            // Negative, NaN and infinite times have no Duration
+            let time = parts[0].parse::<f32>().ok().and_then(|t| Duration::try_from_secs_f32(t).ok()).ok_or_else(error)?;
+            let event = match parts[1] {
+                "button" => {
+                    let button = parts[2].parse::<GamepadButton>().map_err(|_| error())?;
+                    let state = match parts[3] {
+                        "pressed" => ElementState::Pressed,
+                        "released" => ElementState::Released,
+                        _ => return Err(error()),
+                    };
+                    GamepadEvent::Button(button, state)
+                },
+                "axis" => {
+                    let axis = parts[2].parse::<GamepadAxis>().map_err(|_| error())?;
+                    let value = parts[3].parse::<f32>().map_err(|_| error())?;
+                    GamepadEvent::Axis(axis, value)
+                },
+                _ => return Err(error()),
+            };
+
+            events.push((time, event));
+        }
+
+        events.sort_by_key(|(time, _)| *time);
+
+        Ok(Self {
+            events,
+            next: 0,
+            start: None,
+        })
+    }
+}
+
+impl GamepadDevice for ScriptedGamepad {
+    fn name(&self) -> &str {
+        "scripted virtual gamepad"
+    }
+
+    fn next_event(&mut self) -> Option<GamepadEvent> {
+        let start = *self.start.get_or_insert_with(Instant::now);
+        let (time, event) = *self.events.get(self.next)?;
+        self.next += 1;
+
+        let elapsed = start.elapsed();
+        if time > elapsed {
+            thread::sleep(time - elapsed);
+        }
+
+        Some(event)
+    }
+}
+
+/// Gamepad read through the linux joystick api (/dev/input/js*), assumes the common xinput style layout
+#[cfg(target_os = "linux")]
+pub struct JoystickDevice {
+    path: String,
+    file: fs::File,
+}
+
+#[cfg(target_os = "linux")]
+impl JoystickDevice {
+    const JS_EVENT_BUTTON: u8 = 0x01;
+    const JS_EVENT_AXIS: u8 = 0x02;
+    const JS_EVENT_INIT: u8 = 0x80;
+
+    pub fn open(path: &str) -> io::Result<Self> {
+        Ok(Self {
+            path: path.to_string(),
+            file: fs::File::open(path)?,
+        })
+    }
+
+    fn map_button(number: u8) -> Option<GamepadButton> {
+        match number {
+            0 => Some(GamepadButton::South),
+            1 => Some(GamepadButton::East),
+            2 => Some(GamepadButton::West),
+            3 => Some(GamepadButton::North),
+            4 => Some(GamepadButton::LeftBumper),
+            5 => Some(GamepadButton::RightBumper),
+            6 => Some(GamepadButton::Select),
+            7 => Some(GamepadButton::Start),
+            8 => Some(GamepadButton::Mode),
+            9 => Some(GamepadButton::LeftStick),
+            10 => Some(GamepadButton::RightStick),
+            _ => None,
+        }
+    }
+
+    fn map_axis(number: u8, raw: i16) -> Option<GamepadEvent> {
+        let stick = raw as f32 / i16::MAX as f32;
+        let trigger = (stick + 1.0) * 0.5;
+        let (axis, value) = match number {
+            0 => (GamepadAxis::LeftStickX, stick),
+            1 => (GamepadAxis::LeftStickY, stick),
+            2 => (GamepadAxis::LeftTrigger, trigger),
+            3 => (GamepadAxis::RightStickX, stick),
+            4 => (GamepadAxis::RightStickY, stick),
+            5 => (GamepadAxis::RightTrigger, trigger),
+            _ => return None,
+        };
+
+        Some(GamepadEvent::Axis(axis, value.clamp(-1.0, 1.0)))
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl GamepadDevice for JoystickDevice {
+    fn name(&self) -> &str {
+        &self.path
+    }
+
+    fn next_event(&mut self) -> Option<GamepadEvent> {
+        use std::io::Read;
+
+        // struct js_event { u32 time; i16 value; u8 type; u8 number; }
+        let mut raw = [0u8; 8];
+        loop {
+            if let Err(e) = self.file.read_exact(&mut raw) {
+                eprintln!("Lost gamepad {}, e: {}", self.path, e);
+                return None;
+            }
+
+            let value = i16::from_ne_bytes([raw[4], raw[5]]);
+            let event_type = raw[6] & !JoystickDevice::JS_EVENT_INIT;
+            let number = raw[7];
+
+            let event = match event_type {
+                JoystickDevice::JS_EVENT_BUTTON => JoystickDevice::map_button(number).map(|button| {
+                    let state = if value != 0 { ElementState::Pressed } else { ElementState::Released };
+                    GamepadEvent::Button(button, state)
+                }),
+                JoystickDevice::JS_EVENT_AXIS => JoystickDevice::map_axis(number, value),
+                _ => None,
+            };
+
+            if event.is_some() {
+                return event;
+            }
+        }
+    }
+}
+
+/// Finds the first connected gamepad, if any
+pub fn open_default_device() -> Option<Box<dyn GamepadDevice>> {
+    #[cfg(target_os = "linux")]
+    {
+        for i in 0..4 {
+            if let Ok(device) = JoystickDevice::open(&format!("/dev/input/js{}", i)) {
+                return Some(Box::new(device));
+            }
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::{Action, Input, InputMap};
+
+    /// Feeds the next event_count events of the script to input as one frame
+    fn frame(input: &mut Input, gamepad: &mut ScriptedGamepad, event_count: usize, delta_time: f32) {
+        input.begin_frame(delta_time);
+        for _ in 0..event_count {
+            let event = gamepad.next_event().expect("script ended early");
+            input.handle_event(&InputEvent::Gamepad(event));
+        }
+    }
+
+    #[test]
+    fn deadzone_zeroes_small_axis_values_and_rescales_the_rest() {
+        let mut gamepad = ScriptedGamepad::parse("0 axis RightStickX 0.1\n0 axis RightStickX 0.575").unwrap();
+        let mut input = Input::new(InputMap::default());
+
+        frame(&mut input, &mut gamepad, 1, 0.5);
+        assert_eq!(input.axis(Action::TurnX), 0.0);
+
+        // (0.575 - 0.15) / (1 - 0.15) = 0.5 of a fully pushed stick
+        frame(&mut input, &mut gamepad, 1, 0.5);
+        let expected = 0.5 * input.map.gamepad_axis_speed * 0.5;
+        assert!((input.axis(Action::TurnX) - expected).abs() < 1e-3);
+    }
+
+    #[test]
+    fn axis_bound_to_button_action_presses_past_threshold() {
+        let script = "0 axis LeftStickX 0.5\n0 axis LeftStickX 0.7\n0 axis LeftStickX -0.7";
+        let mut gamepad = ScriptedGamepad::parse(script).unwrap();
+        let mut input = Input::new(InputMap::default());
+
+        // 0.5 is only 0.41 after the deadzone
+        frame(&mut input, &mut gamepad, 1, 0.016);
+        assert!(!input.held(Action::MoveRight));
+
+        frame(&mut input, &mut gamepad, 1, 0.016);
+        assert!(input.pressed(Action::MoveRight));
+        assert!(input.held(Action::MoveRight));
+
+        frame(&mut input, &mut gamepad, 1, 0.016);
+        assert!(input.released(Action::MoveRight));
+        assert!(input.pressed(Action::MoveLeft));
+    }
+
+    #[test]
+    fn button_edges_last_a_single_frame() {
+        let mut gamepad = ScriptedGamepad::parse("0 button South pressed\n0 button South released").unwrap();
+        let mut input = Input::new(InputMap::default());
+
+        frame(&mut input, &mut gamepad, 1, 0.016);
+        assert!(input.pressed(Action::Select));
+        assert!(input.held(Action::Select));
+
+        frame(&mut input, &mut gamepad, 0, 0.016);
+        assert!(!input.pressed(Action::Select));
+        assert!(input.held(Action::Select));
+
+        frame(&mut input, &mut gamepad, 1, 0.016);
+        assert!(input.released(Action::Select));
+        assert!(!input.held(Action::Select));
+
+        frame(&mut input, &mut gamepad, 0, 0.016);
+        assert!(!input.released(Action::Select));
+        assert!(gamepad.next_event().is_none());
+    }
+
+    #[test]
+    fn times_without_a_duration_are_parse_errors() {
+        for time in ["-1", "NaN", "inf"] {
+            assert!(ScriptedGamepad::parse(&format!("{} button South pressed", time)).is_err());
+        }
+    }
+}
@@ -0,0 +1,336 @@
+extern crate nalgebra_glm as glm;
+
+use std::{
+    fmt,
+    fs,
+    io,
+    rc::Rc,
+    str::FromStr
+};
+
+use super::{parsing, scene_graph::SceneNode};
+
+/// The SceneNode field a track drives
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Property {
+    Position,
+    /// In radians
+    Rotation,
+    Scale,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Interpolation {
+    /// Holds the value of the last keyframe until the next one
+    Step,
+    Linear,
+    /// Hermite curve through the keyframes with Catmull-Rom tangents, the first and last keyframe use one sided tangents.
+    /// Keyframes with an explicit tangent use that instead
+    Cubic,
+}
+
+/// What happens when playback passes the end of a clip
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlaybackMode {
+    /// Holds the last value
+    Clamp,
+    Loop,
+    /// Plays backwards to the start, then forwards again
+    PingPong,
+}
+
+impl Property {
+    const ALL: [Property; 3] = [Property::Position, Property::Rotation, Property::Scale];
+}
+
+impl FromStr for Property {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parsing::from_debug_name(&Property::ALL, s).ok_or(())
+    }
+}
+
+impl Interpolation {
+    const ALL: [Interpolation; 3] = [Interpolation::Step, Interpolation::Linear, Interpolation::Cubic];
+}
+
+impl FromStr for Interpolation {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parsing::from_debug_name(&Interpolation::ALL, s).ok_or(())
+    }
+}
+
+impl PlaybackMode {
+    const ALL: [PlaybackMode; 3] = [PlaybackMode::Clamp, PlaybackMode::Loop, PlaybackMode::PingPong];
+}
+
+impl FromStr for PlaybackMode {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parsing::from_debug_name(&PlaybackMode::ALL, s).ok_or(())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Keyframe {
+    pub time: f32,
+    pub value: glm::Vec3,
+    /// Change in value per second through the keyframe, only used by cubic tracks
+    pub tangent: Option<glm::Vec3>,
+}
+
+pub struct Track {
+    pub property: Property,
+    pub interpolation: Interpolation,
+    /// Sorted by time
+    keyframes: Vec<Keyframe>,
+}
+
+impl Track {
+    pub fn new(property: Property, interpolation: Interpolation) -> Self {
+        Self {
+            property,
+            interpolation,
+            keyframes: Vec::new(),
+        }
+    }
+
+    fn keyframe(mut self, keyframe: Keyframe) -> Self {
+        let index = self.keyframes.iter().position(|k| k.time > keyframe.time).unwrap_or(self.keyframes.len());
+        self.keyframes.insert(index, keyframe);
+        self
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map_or(0.0, |k| k.time)
+    }
+
+    pub fn sample(&self, time: f32) -> Option<glm::Vec3> {
+        let keys = &self.keyframes;
+        let first = keys.first()?;
+        let last = keys.last()?;
+        if time <= first.time {
+            return Some(first.value);
+        }
+        if time >= last.time {
+            return Some(last.value);
+        }
+
+        // Index of the keyframe starting the segment time is in
+        let i = keys.iter().rposition(|k| k.time <= time)?;
+        let (k0, k1) = (&keys[i], &keys[i + 1]);
+        let span = k1.time - k0.time;
+        let t = (time - k0.time) / span;
+
+        let value = match self.interpolation {
+            Interpolation::Step => k0.value,
+            Interpolation::Linear => glm::lerp(&k0.value, &k1.value, t),
+            Interpolation::Cubic => {
+                // Tangents in value per second, scaled to the segment length for the hermite basis
+                let tangent = |j: usize| {
+                    let before = &keys[j.saturating_sub(1)];
+                    let after = &keys[(j + 1).min(keys.len() - 1)];
+                    keys[j].tangent.unwrap_or_else(|| (after.value - before.value) / (after.time - before.time))
+                };
+                let (m0, m1) = (tangent(i) * span, tangent(i + 1) * span);
+
+                let (t2, t3) = (t * t, t * t * t);
+                k0.value * (2.0 * t3 - 3.0 * t2 + 1.0)
+                    + m0 * (t3 - 2.0 * t2 + t)
+                    + k1.value * (-2.0 * t3 + 3.0 * t2)
+                    + m1 * (t3 - t2)
+            },
+        };
+
+        Some(value)
+    }
+}
+
+pub enum AnimationError {
+    Io(io::Error),
+    Parse(usize, String),
+}
+
+impl fmt::Display for AnimationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AnimationError::Io(e) => e.fmt(f),
+            AnimationError::Parse(line, content) => write!(f, "line {}: failed to parse '{}'", line, content),
+        }
+    }
+}
+
+/// A set of tracks played together, the clip lasts as long as its longest track
+pub struct AnimationClip {
+    pub mode: PlaybackMode,
+    tracks: Vec<Track>,
+    duration: f32,
+}
+
+impl AnimationClip {
+    pub fn new(mode: PlaybackMode) -> Self {
+        Self {
+            mode,
+            tracks: Vec::new(),
+            duration: 0.0,
+        }
+    }
+
+    #[must_use]
+    pub fn track(mut self, track: Track) -> Self {
+        self.duration = self.duration.max(track.duration());
+        self.tracks.push(track);
+        self
+    }
+
+    /// Clips are text files, each line is one of
+    ///   mode <Clamp|Loop|PingPong>
+    ///   track <Position|Rotation|Scale> <Step|Linear|Cubic>
+    ///   <time> <x> <y> <z> [<tangent x> <tangent y> <tangent z>]
+    /// where keyframe lines belong to the last track line before them. Lines starting with # are ignored
+    pub fn load(path: &str) -> Result<Self, AnimationError> {
+        let src = fs::read_to_string(path).map_err(AnimationError::Io)?;
+        Self::parse(&src)
+    }
+
+    pub fn parse(src: &str) -> Result<Self, AnimationError> {
+        let mut mode = PlaybackMode::Clamp;
+        let mut tracks = Vec::<Track>::new();
+
+        for line in parsing::lines(src) {
+            let error = || AnimationError::Parse(line.number, line.text.to_string());
+            match line.words.as_slice() {
+                ["mode", m] => mode = m.parse().map_err(|_| error())?,
+                ["track", property, interpolation] => tracks.push(Track::new(
+                    property.parse().map_err(|_| error())?,
+                    interpolation.parse().map_err(|_| error())?,
+                )),
+                [time, x, y, z, tangent @ ..] if tangent.is_empty() || tangent.len() == 3 => {
+                    let parse = |s: &str| s.parse::<f32>().map_err(|_| error());
+                    let keyframe = Keyframe {
+                        time: parse(time)?,
+                        value: glm::vec3(parse(x)?, parse(y)?, parse(z)?),
+                        tangent: match tangent {
+                            [x, y, z] => Some(glm::vec3(parse(x)?, parse(y)?, parse(z)?)),
+                            _ => None,
+                        },
+                    };
+                    let track = tracks.pop().ok_or_else(error)?;
+                    tracks.push(track.keyframe(keyframe));
+                },
+                _ => return Err(error()),
+            }
+        }
+
+        Ok(tracks.into_iter().fold(AnimationClip::new(mode), AnimationClip::track))
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.duration
+    }
+
+    /// Maps playback time to a time within the clip according to the playback mode
+    fn local_time(&self, time: f32) -> f32 {
+        if self.duration <= 0.0 {
+            return 0.0;
+        }
+
+        match self.mode {
+            PlaybackMode::Clamp => time.clamp(0.0, self.duration),
+            PlaybackMode::Loop => time.rem_euclid(self.duration),
+            PlaybackMode::PingPong => {
+                let t = time.rem_euclid(2.0 * self.duration);
+                if t > self.duration { 2.0 * self.duration - t } else { t }
+            },
+        }
+    }
+
+    /// Writes the animated properties at time to the node, properties without a track are left alone
+    pub fn apply(&self, time: f32, node: &mut SceneNode) {
+        let time = self.local_time(time);
+        for track in &self.tracks {
+            if let Some(value) = track.sample(time) {
+                match track.property {
+                    Property::Position => node.position = value,
+                    Property::Rotation => node.rotation = value,
+                    Property::Scale => node.scale = value,
+                }
+            }
+        }
+    }
+}
+
+/// Plays clips on a single SceneNode. Clips are shared, so many nodes can play the same clip at different times
+pub struct Animator {
+    clip: Rc<AnimationClip>,
+    queued: Option<Rc<AnimationClip>>,
+    time: f32,
+    /// Playback rate, negative plays backwards
+    pub speed: f32,
+}
+
+impl Animator {
+    pub fn new(clip: Rc<AnimationClip>) -> Self {
+        Self {
+            clip,
+            queued: None,
+            time: 0.0,
+            speed: 1.0,
+        }
+    }
+
+    /// Play clip once the current clamped clip has finished, clips that loop or ping-pong never finish
+    pub fn then(&mut self, clip: Rc<AnimationClip>) {
+        self.queued = Some(clip);
+    }
+
+    /// Flip the playback direction, a clamped clip then plays back towards where it came from
+    pub fn reverse(&mut self) {
+        self.speed = -self.speed;
+    }
+
+    pub fn update(&mut self, delta_time: f32) {
+        self.time += delta_time * self.speed;
+
+        if self.clip.mode == PlaybackMode::Clamp {
+            let overflow = self.time - self.clip.duration();
+            if overflow > 0.0 && self.speed > 0.0 {
+                if let Some(next) = self.queued.take() {
+                    // Carry the overflow so chained clips don't lose time
+                    self.clip = next;
+                    self.time = overflow;
+                    return;
+                }
+            }
+
+            // Keep time in range so reversing a finished clip starts moving right away
+            self.time = self.time.clamp(0.0, self.clip.duration());
+        }
+    }
+
+    pub fn apply(&self, node: &mut SceneNode) {
+        self.clip.apply(self.time, node);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    #[test]
+    fn rotor_spin_up_ends_at_full_speed() {
+        for (path, axis) in [("assets/animations/main_rotor_spin_up.anim", 1), ("assets/animations/tail_rotor_spin_up.anim", 0)] {
+            let clip = AnimationClip::load(path).unwrap_or_else(|e| panic!("{}: {}", path, e));
+            let track = &clip.tracks[0];
+            let (end, dt) = (clip.duration(), 1e-3);
+            let speed = (track.sample(end).unwrap()[axis] - track.sample(end - dt).unwrap()[axis]) / dt;
+            assert!((speed - 6.0 * PI).abs() < 0.05, "{} ends at {} rad/s", path, speed);
+            assert!(track.sample(dt).unwrap()[axis] / dt < 0.05, "{} does not start from rest", path);
+        }
+    }
+}
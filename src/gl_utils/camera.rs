@@ -17,18 +17,30 @@ pub struct Camera {
     orientation: glm::Quat,
     pitch: f32,
     yaw: f32,
-    move_speed: f32,
-    turn_sensitivity: f32,
+    pub move_speed: f32,
+    pub turn_sensitivity: f32,
     binded_programs: Vec<Program>
 }
 
 impl Camera {
+    /// Programs that get the camera uniform
+    pub fn programs(&self) -> &[Program] {
+        &self.binded_programs
+    }
+
     fn assign_camera_uniform(&self) {
+        let camera_transform = self.view_projection();
+        let position = self.position();
         for program in &self.binded_programs {
-            let camera_transform = self.projection * glm::quat_to_mat4(&self.orientation) * self.translation;
             if let Err(e) = program.set_uniform_matrix("camera", camera_transform.as_ptr(), gl::UniformMatrix4fv) {
                 eprintln!("Error occured while assigning camera, e: {}", e);
             }
+            // Only shaders that light with the view direction have it
+            if program.has_uniform("camera_position") {
+                if let Err(e) = program.set_uniform1("camera_position", position, |location, p| unsafe { gl::Uniform3f(location, p.x, p.y, p.z) }) {
+                    eprintln!("Error occured while assigning camera position, e: {}", e);
+                }
+            }
         }
     }
 
@@ -66,14 +78,57 @@ impl Camera {
 
         // Avoid float rounding errors
         let one_rotation = 2.0 * std::f32::consts::PI;
-        self.yaw = self.yaw % one_rotation;
-        self.pitch = self.pitch % one_rotation;
+        self.yaw %= one_rotation;
+        self.pitch %= one_rotation;
+
+        self.assign_camera_uniform();
+    }
+
+    /// Place the camera at a world position, looking in the direction given by yaw and pitch
+    pub fn set_pose(&mut self, position: &glm::Vec3, yaw: f32, pitch: f32) {
+        self.translation = glm::translation(&-position);
+        self.yaw = yaw;
+        self.pitch = pitch;
+
+        // Turning by nothing rebuilds the orientation and assigns the uniform
+        self.turn((0.0, 0.0), 0.0);
+    }
 
+    pub fn set_position(&mut self, position: &glm::Vec3) {
+        self.translation = glm::translation(&-position);
         self.assign_camera_uniform();
     }
 
+    /// Transformation from world space to clip space, what the camera uniform is set to
+    pub fn view_projection(&self) -> glm::Mat4 {
+        self.projection * glm::quat_to_mat4(&self.orientation) * self.translation
+    }
+
+    /// Like view_projection but without the translation, for things infinitely far away like the sky
+    pub fn sky_view_projection(&self) -> glm::Mat4 {
+        self.projection * glm::quat_to_mat4(&self.orientation)
+    }
+
+    /// World space ray through a point on the screen, cursor in pixels from the top left corner. Returns origin and unit direction
+    pub fn screen_ray(&self, cursor: (f64, f64), screen_size: (f64, f64)) -> (glm::Vec3, glm::Vec3) {
+        let x = (2.0 * cursor.0 / screen_size.0 - 1.0) as f32;
+        let y = (1.0 - 2.0 * cursor.1 / screen_size.1) as f32;
+
+        // Unproject the point on the near and far plane
+        let inverse = glm::inverse(&self.view_projection());
+        let unproject = |z: f32| {
+            let p = inverse * glm::vec4(x, y, z, 1.0);
+            glm::vec3(p.x, p.y, p.z) / p.w
+        };
+        let near = unproject(-1.0);
+        let far = unproject(1.0);
+
+        (near, glm::normalize(&(far - near)))
+    }
+
+    /// World space position, the translation matrix moves the world and not the camera so we negate it
     pub fn position(&self) -> glm::Vec3 {
-        glm::vec3(self.translation[12], self.translation[13], self.translation[14])
+        -glm::vec3(self.translation[12], self.translation[13], self.translation[14])
     }
 }   
 
@@ -110,17 +165,19 @@ impl CameraBuilder {
     }
 
     pub fn translation(mut self, start_pos: &glm::Vec3) -> Self {
-        self.translation = Some(glm::translate(&glm::identity::<f32, glm::U4>(), &start_pos));
+        self.translation = Some(glm::translate(&glm::identity::<f32, glm::U4>(), start_pos));
         
         self
     }
 
+    #[allow(dead_code)]
     pub fn pitch(mut self, pitch: f32) -> Self {
         self.pitch = Some(pitch);
 
         self
     }
     
+    #[allow(dead_code)]
     pub fn yaw(mut self, yaw: f32) -> Self {
         self.yaw = Some(yaw);
 
@@ -174,6 +231,8 @@ impl CameraBuilder {
             if let Err(e) = program.locate_uniform("camera") {
                 eprint!("Failed to find camera, probably loading wrong shader. err: {}", e);
             };
+            // Optional, so a missing one is not an error
+            let _ = program.locate_uniform("camera_position");
         }
 
         let mut camera = Camera {
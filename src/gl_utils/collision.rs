@@ -0,0 +1,243 @@
+extern crate nalgebra_glm as glm;
+
+use std::{collections::HashSet, hash::Hash};
+
+use super::{mesh::Mesh, scene_graph::SceneNode};
+
+/// Axis aligned bounding box
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: glm::Vec3,
+    pub max: glm::Vec3,
+}
+
+impl Aabb {
+    pub fn from_mesh(mesh: &Mesh) -> Self {
+        let (min, max) = mesh.bounds();
+        Self {
+            min,
+            max,
+        }
+    }
+
+    pub fn center(&self) -> glm::Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn half_extents(&self) -> glm::Vec3 {
+        (self.max - self.min) * 0.5
+    }
+
+    /// Smallest box around this box after a transformation. Arvo's method, the extents are projected with the absolute rotation
+    pub fn transformed(&self, transformation: &glm::Mat4) -> Self {
+        let c = self.center();
+        let center = glm::vec4_to_vec3(&(transformation * glm::vec4(c.x, c.y, c.z, 1.0)));
+        let half = self.half_extents();
+
+        let mut extents = glm::Vec3::zeros();
+        for row in 0..3 {
+            for column in 0..3 {
+                extents[row] += transformation[(row, column)].abs() * half[column];
+            }
+        }
+
+        Self {
+            min: center - extents,
+            max: center + extents,
+        }
+    }
+
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        (0..3).all(|axis| self.min[axis] <= other.max[axis] && other.min[axis] <= self.max[axis])
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sphere {
+    pub center: glm::Vec3,
+    pub radius: f32,
+}
+
+impl Sphere {
+    pub fn intersects(&self, other: &Sphere) -> bool {
+        let reach = self.radius + other.radius;
+        glm::distance2(&self.center, &other.center) <= reach * reach
+    }
+}
+
+/// Oriented bounding box
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Obb {
+    pub center: glm::Vec3,
+    /// Unit axes of the box in world space
+    pub axes: [glm::Vec3; 3],
+    pub half_extents: glm::Vec3,
+}
+
+impl Obb {
+    /// The local box carried along by a transformation, scaling is moved from the axes into the extents
+    pub fn new(local: &Aabb, transformation: &glm::Mat4) -> Self {
+        let c = local.center();
+        let half = local.half_extents();
+        let mut axes = [glm::Vec3::zeros(); 3];
+        let mut half_extents = glm::Vec3::zeros();
+        for i in 0..3 {
+            let column = glm::vec4_to_vec3(&transformation.column(i).into());
+            let length = glm::length(&column);
+            axes[i] = if length > 1e-8 { column / length } else { glm::Vec3::ith(i, 1.0) };
+            half_extents[i] = half[i] * length;
+        }
+
+        Self {
+            center: glm::vec4_to_vec3(&(transformation * glm::vec4(c.x, c.y, c.z, 1.0))),
+            axes,
+            half_extents,
+        }
+    }
+
+    /// Radius of the box projected onto a unit axis
+    fn projected_radius(&self, axis: &glm::Vec3) -> f32 {
+        (0..3).map(|i| glm::dot(&self.axes[i], axis).abs() * self.half_extents[i]).sum()
+    }
+
+    /// Separating axis test over the face normals of both boxes and the cross products of their edges
+    pub fn intersects(&self, other: &Obb) -> bool {
+        let offset = other.center - self.center;
+        let mut candidates = Vec::with_capacity(15);
+        candidates.extend_from_slice(&self.axes);
+        candidates.extend_from_slice(&other.axes);
+        for a in &self.axes {
+            for b in &other.axes {
+                candidates.push(glm::cross(a, b));
+            }
+        }
+
+        for axis in candidates {
+            // Parallel edges give no axis, the face normals already cover that case
+            let length = glm::length(&axis);
+            if length < 1e-6 {
+                continue;
+            }
+            let axis = axis / length;
+
+            let distance = glm::dot(&offset, &axis).abs();
+            if distance > self.projected_radius(&axis) + other.projected_radius(&axis) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+/// World space bounds of one SceneNode, built from the transformation of the last step
+pub struct Collider<'a> {
+    pub node: &'a SceneNode,
+    pub aabb: Aabb,
+    pub sphere: Sphere,
+    pub obb: Obb,
+}
+
+impl<'a> Collider<'a> {
+    pub fn new(node: &'a SceneNode, local: &Aabb) -> Self {
+        let transformation = &node.current_transformation_matrix;
+        let obb = Obb::new(local, transformation);
+
+        Self {
+            node,
+            aabb: local.transformed(transformation),
+            sphere: Sphere {
+                center: obb.center,
+                radius: glm::length(&obb.half_extents),
+            },
+            obb,
+        }
+    }
+}
+
+pub struct Collision<'a> {
+    pub a: &'a SceneNode,
+    pub b: &'a SceneNode,
+}
+
+/// Every overlapping pair of colliders. Sweep and prune along x over the world AABBs finds candidates,
+/// bounding spheres reject most of them cheaply and the OBB test decides. Pairs where ignore returns true are skipped
+pub fn find_collisions<'a>(colliders: &[Collider<'a>], ignore: impl Fn(&SceneNode, &SceneNode) -> bool) -> Vec<Collision<'a>> {
+    let mut order: Vec<usize> = (0..colliders.len()).collect();
+    order.sort_by(|&a, &b| colliders[a].aabb.min.x.total_cmp(&colliders[b].aabb.min.x));
+
+    let mut collisions = Vec::new();
+    let mut active = Vec::<usize>::new();
+    for &i in &order {
+        let current = &colliders[i];
+        // Boxes that end before this one starts can't touch it or anything after it
+        active.retain(|&j| colliders[j].aabb.max.x >= current.aabb.min.x);
+
+        for &j in &active {
+            let other = &colliders[j];
+            if !current.aabb.intersects(&other.aabb) || !current.sphere.intersects(&other.sphere) {
+                continue;
+            }
+            if ignore(other.node, current.node) {
+                continue;
+            }
+            if other.obb.intersects(&current.obb) {
+                collisions.push(Collision {
+                    a: other.node,
+                    b: current.node,
+                });
+            }
+        }
+
+        active.push(i);
+    }
+
+    collisions
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CollisionEvent<T> {
+    Began(T, T),
+    Ended(T, T),
+}
+
+/// Turns the colliding pairs of each step into events for when contact begins and ends.
+/// Pairs are identified by whatever the game logic uses for its objects, the order within a pair does not matter
+pub struct CollisionTracker<T> {
+    touching: HashSet<(T, T)>,
+}
+
+impl<T: Copy + Eq + Hash + Ord> Default for CollisionTracker<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Copy + Eq + Hash + Ord> CollisionTracker<T> {
+    pub fn new() -> Self {
+        Self {
+            touching: HashSet::new(),
+        }
+    }
+
+    /// Feed every pair colliding this step, pairs can be repeated
+    pub fn update(&mut self, pairs: impl IntoIterator<Item = (T, T)>) -> Vec<CollisionEvent<T>> {
+        let touching: HashSet<(T, T)> = pairs.into_iter()
+            .filter(|(a, b)| a != b)
+            .map(|(a, b)| (a.min(b), a.max(b)))
+            .collect();
+
+        let mut events: Vec<CollisionEvent<T>> = touching.difference(&self.touching)
+            .map(|&(a, b)| CollisionEvent::Began(a, b))
+            .collect();
+        events.extend(self.touching.difference(&touching).map(|&(a, b)| CollisionEvent::Ended(a, b)));
+        // Hash order is random, sorting keeps the event order the same between runs
+        events.sort_by_key(|e| match *e {
+            CollisionEvent::Began(a, b) => (0, a, b),
+            CollisionEvent::Ended(a, b) => (1, a, b),
+        });
+
+        self.touching = touching;
+        events
+    }
+}
@@ -0,0 +1,230 @@
+extern crate nalgebra_glm as glm;
+
+use gl;
+use gl::types::{GLuint, GLsizei};
+
+use super::{camera::Camera, collision::Aabb, helpers, scene_graph::SceneNode, stream_buffer::StreamBuffer};
+
+pub const RED: [f32; 4] = [1.0, 0.2, 0.2, 1.0];
+pub const GREEN: [f32; 4] = [0.2, 1.0, 0.2, 1.0];
+pub const BLUE: [f32; 4] = [0.3, 0.4, 1.0, 1.0];
+pub const YELLOW: [f32; 4] = [1.0, 0.9, 0.2, 1.0];
+pub const WHITE: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+
+/// Immediate mode line drawing for debugging. Shapes are collected during the frame and drawn in one call by draw,
+/// which also clears them. Expects a program like assets/shaders/debug.vert with position at location 0 and color at 1
+pub struct DebugDraw {
+    vao_id: GLuint,
+    buffer: StreamBuffer,
+    program_id: GLuint,
+    /// Interleaved position and color, 7 floats per vertex and 2 vertices per line
+    vertices: Vec<f32>,
+    /// Draw over everything instead of being hidden by closer geometry
+    pub always_on_top: bool,
+}
+
+impl DebugDraw {
+    const FLOATS_PER_VERTEX: usize = 7;
+    const CIRCLE_SEGMENTS: usize = 32;
+
+    pub fn new(program_id: GLuint) -> Self {
+        let mut vao_id = 0;
+        let buffer = StreamBuffer::new();
+        unsafe {
+            gl::GenVertexArrays(1, &mut vao_id);
+            gl::BindVertexArray(vao_id);
+            gl::BindBuffer(gl::ARRAY_BUFFER, buffer.id());
+
+            let stride = (DebugDraw::FLOATS_PER_VERTEX * std::mem::size_of::<f32>()) as GLsizei;
+            gl::EnableVertexAttribArray(0);
+            gl::VertexAttribPointer(0, 3, gl::FLOAT, gl::FALSE, stride, helpers::offset::<f32>(0));
+            gl::EnableVertexAttribArray(1);
+            gl::VertexAttribPointer(1, 4, gl::FLOAT, gl::FALSE, stride, helpers::offset::<f32>(3));
+
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+            gl::BindVertexArray(0);
+        }
+
+        Self {
+            vao_id,
+            buffer,
+            program_id,
+            vertices: Vec::new(),
+            always_on_top: true,
+        }
+    }
+
+    pub fn line(&mut self, from: &glm::Vec3, to: &glm::Vec3, color: [f32; 4]) {
+        for p in &[from, to] {
+            self.vertices.extend_from_slice(&[p.x, p.y, p.z]);
+            self.vertices.extend_from_slice(&color);
+        }
+    }
+
+    /// Line with a head at to
+    pub fn arrow(&mut self, from: &glm::Vec3, to: &glm::Vec3, color: [f32; 4]) {
+        self.line(from, to, color);
+
+        let direction = to - from;
+        let length = glm::length(&direction);
+        if length < 1e-6 {
+            return;
+        }
+        let direction = direction / length;
+        // Any vector not parallel to the arrow works for finding two perpendicular ones
+        let helper = if direction.y.abs() < 0.9 { glm::vec3(0.0, 1.0, 0.0) } else { glm::vec3(1.0, 0.0, 0.0) };
+        let side = glm::normalize(&glm::cross(&direction, &helper));
+        let up = glm::cross(&side, &direction);
+
+        let head = length.min(1.0) * 0.25;
+        let base = to - direction * head;
+        for offset in &[side, -side, up, -up] {
+            self.line(to, &(base + offset * head * 0.5), color);
+        }
+    }
+
+    pub fn aabb(&mut self, aabb: &Aabb, color: [f32; 4]) {
+        self.transformed_box(aabb, &glm::identity(), color);
+    }
+
+    /// A local box carried along by a transformation, draws oriented bounding boxes
+    pub fn transformed_box(&mut self, local: &Aabb, transformation: &glm::Mat4, color: [f32; 4]) {
+        let corner = |i: usize| {
+            let p = glm::vec3(
+                if i & 1 == 0 { local.min.x } else { local.max.x },
+                if i & 2 == 0 { local.min.y } else { local.max.y },
+                if i & 4 == 0 { local.min.z } else { local.max.z },
+            );
+            glm::vec4_to_vec3(&(transformation * glm::vec4(p.x, p.y, p.z, 1.0)))
+        };
+
+        // Corners that differ in exactly one bit share an edge
+        for i in 0..8 {
+            for bit in &[1, 2, 4] {
+                if i & bit == 0 {
+                    self.line(&corner(i), &corner(i | bit), color);
+                }
+            }
+        }
+    }
+
+    /// Three circles, one around each axis
+    #[allow(dead_code)]
+    pub fn sphere(&mut self, center: &glm::Vec3, radius: f32, color: [f32; 4]) {
+        let point = |axis: usize, angle: f32| {
+            let (s, c) = (angle.sin() * radius, angle.cos() * radius);
+            center + match axis {
+                0 => glm::vec3(0.0, c, s),
+                1 => glm::vec3(c, 0.0, s),
+                _ => glm::vec3(c, s, 0.0),
+            }
+        };
+
+        let step = 2.0 * std::f32::consts::PI / DebugDraw::CIRCLE_SEGMENTS as f32;
+        for axis in 0..3 {
+            for i in 0..DebugDraw::CIRCLE_SEGMENTS {
+                self.line(&point(axis, i as f32 * step), &point(axis, (i + 1) as f32 * step), color);
+            }
+        }
+    }
+
+    /// Local x, y and z axes of a transformation in red, green and blue
+    pub fn axes(&mut self, transformation: &glm::Mat4, size: f32) {
+        let origin = glm::vec4_to_vec3(&transformation.column(3).into());
+        for (axis, color) in [RED, GREEN, BLUE].iter().enumerate() {
+            let direction = glm::vec4_to_vec3(&transformation.column(axis).into());
+            self.line(&origin, &(origin + direction * size), *color);
+        }
+    }
+
+    /// Square grid in the xz plane around center, with size cells of spacing on each side
+    #[allow(dead_code)]
+    pub fn grid(&mut self, center: &glm::Vec3, size: usize, spacing: f32, color: [f32; 4]) {
+        let half = size as f32 * spacing;
+        for i in 0..=(2 * size) {
+            let offset = i as f32 * spacing - half;
+            self.line(&(center + glm::vec3(offset, 0.0, -half)), &(center + glm::vec3(offset, 0.0, half)), color);
+            self.line(&(center + glm::vec3(-half, 0.0, offset)), &(center + glm::vec3(half, 0.0, offset)), color);
+        }
+    }
+
+    /// Edges of what a camera sees, only useful when drawn from a different camera or after the camera has moved on
+    #[allow(dead_code)]
+    pub fn frustum(&mut self, camera: &Camera, color: [f32; 4]) {
+        let inverse = glm::inverse(&camera.view_projection());
+        let corner = |i: usize| {
+            let p = inverse * glm::vec4(
+                if i & 1 == 0 { -1.0 } else { 1.0 },
+                if i & 2 == 0 { -1.0 } else { 1.0 },
+                if i & 4 == 0 { -1.0 } else { 1.0 },
+                1.0,
+            );
+            glm::vec3(p.x, p.y, p.z) / p.w
+        };
+
+        for i in 0..8 {
+            for bit in &[1, 2, 4] {
+                if i & bit == 0 {
+                    self.line(&corner(i), &corner(i | bit), color);
+                }
+            }
+        }
+    }
+
+    /// Axes of every node in the graph, with a cross on the pivot of nodes that rotate around a reference point.
+    /// Uses the transformations from the last update
+    pub fn scene_graph(&mut self, node: &SceneNode, size: f32) {
+        let transformation = &node.current_transformation_matrix;
+        self.axes(transformation, size);
+
+        if node.reference_point != glm::Vec3::zeros() {
+            // The local transformation is S * T(ref) * R * T(-ref) * T(position), so ref - position in node space is the pivot
+            let local = node.reference_point - node.position;
+            let pivot = glm::vec4_to_vec3(&(transformation * glm::vec4(local.x, local.y, local.z, 1.0)));
+            let half = size * 0.25;
+            for axis in 0..3 {
+                let offset = glm::Vec3::ith(axis, half);
+                self.line(&(pivot - offset), &(pivot + offset), YELLOW);
+            }
+        }
+
+        unsafe {
+            for &child in &node.children {
+                self.scene_graph(&*child, size);
+            }
+        }
+    }
+
+    /// Draws and clears everything collected since the last call
+    pub fn draw(&mut self) {
+        let count = self.vertices.len() / DebugDraw::FLOATS_PER_VERTEX;
+        if count == 0 {
+            return;
+        }
+
+        self.buffer.upload(&self.vertices);
+        unsafe {
+            if self.always_on_top {
+                gl::Disable(gl::DEPTH_TEST);
+            }
+            gl::UseProgram(self.program_id);
+            gl::BindVertexArray(self.vao_id);
+            gl::DrawArrays(gl::LINES, 0, count as GLsizei);
+            gl::BindVertexArray(0);
+            gl::UseProgram(0);
+            if self.always_on_top {
+                gl::Enable(gl::DEPTH_TEST);
+            }
+        }
+
+        self.vertices.clear();
+    }
+}
+
+impl Drop for DebugDraw {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteVertexArrays(1, &self.vao_id);
+        }
+    }
+}
@@ -1,36 +1,239 @@
 use gl;
 use gl::types::{GLuint, GLsizei, GLintptr};
 
+use std::{cell::RefCell, rc::Rc};
+
 use super::{
-    bindable::Bindable, 
-    helpers, 
-    vertex_attributes::VerticesAttributesPair};
+    bindable::Bindable,
+    collision::Aabb,
+    helpers,
+    material::Material,
+    picking::PickMesh,
+    profiler,
+    render_queue::{DrawItem, RenderQueue},
+    vertex_attributes::{Conversion, VerticesAttributesPair}};
+
+/// Per instance state shared between a GeometricObject and all of its GeometricInstances
+#[derive(Debug)]
+pub struct InstanceState {
+    pub buffer_id: GLuint,
+    pub count: GLsizei,
+    pub capacity: usize,
+    pub visible: Vec<bool>,
+    /// CPU side copy of the instance buffer, used to restore the content when the buffer grows
+    transforms: Vec<glm::Mat4>,
+    free_slots: Vec<usize>,
+}
+
+impl InstanceState {
+    fn new(buffer_id: GLuint, instance_transforms: &[glm::Mat4]) -> Self {
+        let count = instance_transforms.len();
+        let mut state = Self {
+            buffer_id,
+            count: count as GLsizei,
+            capacity: 0,
+            visible: vec![true; count],
+            transforms: instance_transforms.to_vec(),
+            free_slots: Vec::new(),
+        };
+        state.reallocate(count.max(1));
+
+        state
+    }
+
+    /// Iterate all contiguous runs of visible instances in [first, first + count) as (first, count) pairs
+    pub fn visible_runs(&self, first: usize, count: usize) -> Vec<(usize, usize)> {
+        let end = (first + count).min(self.count as usize);
+        let mut runs = Vec::new();
+        let mut run_start: Option<usize> = None;
+        for i in first..end {
+            match (self.visible[i], run_start) {
+                (true, None) => run_start = Some(i),
+                (false, Some(start)) => {
+                    runs.push((start, i - start));
+                    run_start = None;
+                },
+                _ => { }
+            }
+        }
+
+        if let Some(start) = run_start {
+            runs.push((start, end - start));
+        }
+
+        runs
+    }
+
+    pub fn is_allocated(&self, index: usize) -> bool {
+        index < self.count as usize && !self.free_slots.contains(&index)
+    }
+
+    /// Freed slots stay hidden, their transform is stale until the slot is allocated again
+    fn set_visible(&mut self, index: usize, visible: bool) {
+        if self.is_allocated(index) {
+            self.visible[index] = visible;
+        }
+    }
+
+    /// Returns a free slot, growing the instance buffer if there are none left
+    fn allocate(&mut self) -> usize {
+        if let Some(index) = self.free_slots.pop() {
+            self.visible[index] = true;
+            return index;
+        }
+
+        let index = self.count as usize;
+        if index >= self.capacity {
+            self.reallocate(self.capacity * 2);
+        }
+
+        self.count += 1;
+        self.visible.push(true);
+        self.transforms.push(glm::identity());
+        self.upload(index);
+
+        index
+    }
+
+    fn free(&mut self, index: usize) {
+        if !self.is_allocated(index) {
+            return;
+        }
+
+        self.visible[index] = false;
+        self.free_slots.push(index);
+
+        // Free slots at the end can be dropped instead of being kept around for reuse
+        while let Some(i) = self.free_slots.iter().position(|&s| s + 1 == self.count as usize) {
+            self.free_slots.swap_remove(i);
+            self.count -= 1;
+            self.visible.pop();
+            self.transforms.pop();
+        }
+    }
+
+    fn update_transform(&mut self, index: usize, new_transform: &glm::Mat4) {
+        if index >= self.count as usize {
+            return; // ERROR
+        }
+
+        self.transforms[index] = *new_transform;
+        self.upload(index);
+    }
+
+    fn upload(&self, index: usize) {
+        update_transform(index, &self.transforms[index], self.buffer_id);
+    }
+
+    fn reserve(&mut self, capacity: usize) {
+        if capacity > self.capacity {
+            self.reallocate(capacity.next_power_of_two());
+        }
+    }
+
+    /// Orphans the current buffer storage and allocates a new one with room for capacity instances
+    fn reallocate(&mut self, capacity: usize) {
+        let mat4_size = std::mem::size_of::<glm::Mat4>();
+        unsafe {
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.buffer_id);
+            gl::BufferData(
+                gl::ARRAY_BUFFER,
+                (mat4_size * capacity) as isize,
+                std::ptr::null(),
+                gl::DYNAMIC_DRAW
+            );
+
+            if !self.transforms.is_empty() {
+                profiler::record_upload(std::mem::size_of_val(&self.transforms[..]));
+                gl::BufferSubData(
+                    gl::ARRAY_BUFFER,
+                    0,
+                    helpers::byte_size_of_array(&self.transforms),
+                    helpers::array_to_c_void(&self.transforms)
+                );
+            }
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        }
+
+        self.capacity = capacity;
+    }
+}
 
 #[derive(Debug)]
 pub struct GeometricInstance {
     pub vao_id: GLuint,
     pub program_id: u32,
+    pub material: Material,
     pub elem_id: GLuint,
-    pub instances_id: GLuint,
     pub indices_count: GLsizei,
-    pub instance_count: GLsizei,
     pub instance_index: usize,
+    pub pick_mesh: Option<Rc<PickMesh>>,
+    /// Local space bounds, only instances with bounds take part in collision detection
+    pub bounds: Option<Aabb>,
+    instances: Rc<RefCell<InstanceState>>,
     // TODO: we can store a transform here, but I suspect it can create too much duplicate data
 }
 
 impl GeometricInstance {
     pub fn update_transform(&self, new_transform: &glm::Mat4) {
-        update_transform(self.instance_index, &new_transform, self.instances_id);
+        self.instances.borrow_mut().update_transform(self.instance_index, new_transform);
     }
 
+    pub fn set_visible(&self, visible: bool) {
+        self.instances.borrow_mut().set_visible(self.instance_index, visible);
+    }
 
-    // TODO: Research options to only draw one instance
-    /// Draws this and all other instances in this group
-    pub fn draw_all(&self) {
-        draw_all(self, self.program_id, self.indices_count, self.instance_count);
+    pub fn is_visible(&self) -> bool {
+        self.instances.borrow().visible.get(self.instance_index).cloned().unwrap_or(false)
+    }
+
+    /// Releases the instance slot so that the group can reuse it
+    pub fn free(self) {
+        self.instances.borrow_mut().free(self.instance_index);
+    }
+
+    /// Draws only this instance, if it is visible
+    pub fn draw(&self) {
+        draw_range(self, self.program_id, self.indices_count, &self.instances.borrow(), self.instance_index, 1);
+    }
+
+    /// Draws only this instance with another program that takes the same vertex attributes, if it is visible
+    pub fn draw_with_program(&self, program_id: GLuint) {
+        draw_range(self, program_id, self.indices_count, &self.instances.borrow(), self.instance_index, 1);
+    }
+
+    /// Queues the visible instances of this group listed in indices, which must be sorted
+    pub fn queue_instances(&self, queue: &mut RenderQueue, indices: &[usize]) {
+        let instances = self.instances.borrow();
+        let item = DrawItem {
+            program_id: self.program_id,
+            material: self.material,
+            vao_id: self.vao_id,
+            elem_id: self.elem_id,
+            indices_count: self.indices_count,
+            first_instance: 0,
+            instance_count: 0,
+            depth: 0.0,
+        };
+        for (first, count) in index_runs(indices) {
+            queue_range(queue, item, &instances, first, count);
+        }
     }
 }
 
+/// Sorted indices as (first, count) runs of consecutive indices
+fn index_runs(indices: &[usize]) -> Vec<(usize, usize)> {
+    let mut runs: Vec<(usize, usize)> = Vec::new();
+    for &index in indices {
+        match runs.last_mut() {
+            Some((first, count)) if *first + *count == index => *count += 1,
+            _ => runs.push((index, 1)),
+        }
+    }
+
+    runs
+}
+
 impl Bindable for GeometricInstance {
     fn bind(&self) {
         unsafe {
@@ -51,10 +254,14 @@ impl Bindable for GeometricInstance {
 pub struct GeometricObject {
     pub id: GLuint, // TODO: rename vao
     program_id: u32,
+    material: Material,
     vbo_ids: Vec<GLuint>, // TODO: rename vbos
-    pub instance_count: GLsizei,
     pub indices_count: GLsizei,
-    pub buffer_count: GLsizei
+    #[allow(dead_code)]
+    pub buffer_count: GLsizei,
+    pick_mesh: Option<Rc<PickMesh>>,
+    bounds: Option<Aabb>,
+    instances: Rc<RefCell<InstanceState>>,
 }
 
 
@@ -64,9 +271,9 @@ impl Drop for GeometricObject {
             for buffer in &self.vbo_ids {
                 // TODO: as we use vector, we can't delete all at once
                 // Seems the only possible solutions is a experimental function called "leak"
-                gl::DeleteBuffers(1, buffer); 
+                gl::DeleteBuffers(1, buffer);
             }
-            gl::DeleteVertexArrays(1, self.id as *const GLuint);
+            gl::DeleteVertexArrays(1, &self.id);
         }
     }
 }
@@ -91,9 +298,10 @@ impl Bindable for GeometricObject {
 impl GeometricObject {
     pub const ELEM_INDEX: usize = 0;
     pub const INST_INDEX: usize = 1;
-    
+
     // TODO: this should read shader string and modify locations to fit with buffers
-    pub fn init<T>(program_id: u32, buffer_attrib_pairs: &Vec<VerticesAttributesPair<T>>, indices: &Vec<u32>, instance_transforms: &Vec<glm::Mat4>) -> Self  {
+    /// instance_transforms is the initial content of the instance buffer, it may be empty
+    pub fn init<T>(program_id: u32, buffer_attrib_pairs: &[VerticesAttributesPair<T>], indices: &[u32], instance_transforms: &[glm::Mat4]) -> Self  {
         let mut id: GLuint = 0;
         let buffer_count = buffer_attrib_pairs.len() + 2;
         let mut instance_location = 1; // location in shader
@@ -119,55 +327,47 @@ impl GeometricObject {
                 vbo_ids.push(buff);
 
                 gl::BufferData(
-                    gl::ARRAY_BUFFER, 
+                    gl::ARRAY_BUFFER,
                     helpers::byte_size_of_array(&vert_attrib_pair.buffer_data),
                     helpers::array_to_c_void(&vert_attrib_pair.buffer_data),
                     gl::STATIC_DRAW
                 );
 
-                // Vertex attributes
-                let size_of_type = helpers::size_of::<T>();
-                let total_components: gl::types::GLint = vert_attrib_pair.attributes.iter()
-                    .map(|a| {
-                        a.size
-                    })
-                    .sum();
-
-                let stride = total_components * size_of_type;
-                for attrib in &vert_attrib_pair.attributes {
+                // Vertex attributes, formats and offsets come from the layout
+                let stride = vert_attrib_pair.layout.stride() as GLsizei;
+                for attrib in vert_attrib_pair.layout.attributes() {
                     if instance_location <= attrib.index {
                         instance_location = attrib.index + 1;
                     }
 
+                    let offset = attrib.offset as *const core::ffi::c_void;
                     gl::EnableVertexAttribArray(attrib.index);
-                    gl::VertexAttribPointer(
-                        attrib.index,                           // index of the generic vertex attribute ("layout (location = 0)")
-                        attrib.size,                            // the number of components per generic vertex attribute
-                        vert_attrib_pair.data_type,             // data type
-                        gl::FALSE,                              // normalized (int-to-float conversion)
-                        stride,                                 // stride (byte offset between consecutive attributes)
-                        helpers::offset::<T>(attrib.offset)     // offset of the first component
-                    );
+                    match attrib.conversion {
+                        Conversion::Integer => gl::VertexAttribIPointer(attrib.index, attrib.size, attrib.format.gl_type(), stride, offset),
+                        conversion => gl::VertexAttribPointer(
+                            attrib.index,                           // index of the generic vertex attribute ("layout (location = 0)")
+                            attrib.size,                            // the number of components per generic vertex attribute
+                            attrib.format.gl_type(),                // data type
+                            if conversion == Conversion::Normalized { gl::TRUE } else { gl::FALSE }, // normalized (int-to-float conversion)
+                            stride,                                 // stride (byte offset between consecutive vertices)
+                            offset                                  // offset of the first component
+                        ),
+                    }
                 }
             }
-            
+
             // instantiate element buffer
             gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, vbo_ids[GeometricObject::ELEM_INDEX]);
             gl::BufferData(
-                gl::ELEMENT_ARRAY_BUFFER, 
+                gl::ELEMENT_ARRAY_BUFFER,
                 helpers::byte_size_of_array(indices),
                 helpers::array_to_c_void(indices),
                 gl::STATIC_DRAW
             );
 
+            // storage of the instance buffer is owned by InstanceState
             gl::BindBuffer(gl::ARRAY_BUFFER, vbo_ids[GeometricObject::INST_INDEX]);
-            gl::BufferData(
-                gl::ARRAY_BUFFER, 
-                helpers::byte_size_of_array(&instance_transforms),
-                helpers::array_to_c_void(&instance_transforms),
-                gl::DYNAMIC_DRAW
-            );
-            
+
             let mat_size = std::mem::size_of::<glm::Mat4>() as i32;
             let vec_size = std::mem::size_of::<glm::Vec4>() as u32;
             for i in 0..4 {
@@ -175,89 +375,220 @@ impl GeometricObject {
                 gl::EnableVertexAttribArray(attrib_index);
                 gl::VertexAttribPointer(
                     attrib_index,                           // currently shader expects location=1
-                    4,                            
-                    gl::FLOAT,            
-                    gl::FALSE,                              
-                    mat_size,                                
+                    4,
+                    gl::FLOAT,
+                    gl::FALSE,
+                    mat_size,
                     (i * vec_size) as *const usize as *const core::ffi::c_void
                 );
-                
+
                 gl::VertexAttribDivisor(attrib_index, 1);
             }
 
-            // Better safe than sorry :) 
+            // Better safe than sorry :)
             gl::BindBuffer(gl::ARRAY_BUFFER, 0);
             gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, 0);
             gl::BindVertexArray(0);
         }
 
+        let instances = InstanceState::new(vbo_ids[GeometricObject::INST_INDEX], instance_transforms);
+
         Self {
             id,
             program_id,
+            material: Material::default(),
             vbo_ids,
             indices_count: indices.len() as GLsizei,
-            instance_count: instance_transforms.len() as GLsizei,
-            buffer_count: buffer_count as GLsizei
+            buffer_count: buffer_count as GLsizei,
+            pick_mesh: None,
+            bounds: None,
+            instances: Rc::new(RefCell::new(instances)),
         }
     }
 
+    /// Materials are used by the RenderQueue to group draws, instances created before this call keep their material
+    #[must_use]
+    pub fn with_material(mut self, material: Material) -> Self {
+        self.material = material;
+
+        self
+    }
+
+    /// Lets SceneNode::pick hit the instances, instances created before this call are not pickable
+    #[must_use]
+    pub fn with_pick_mesh(mut self, pick_mesh: PickMesh) -> Self {
+        self.pick_mesh = Some(Rc::new(pick_mesh));
+
+        self
+    }
+
+    /// Lets the instances collide, instances created before this call have no bounds
+    #[must_use]
+    pub fn with_bounds(mut self, bounds: Aabb) -> Self {
+        self.bounds = Some(bounds);
+
+        self
+    }
+
+    pub fn instance_count(&self) -> GLsizei {
+        self.instances.borrow().count
+    }
+
+    /// Grows the instance buffer up front so that capacity instances fit without further reallocations
+    pub fn reserve_instances(&self, capacity: usize) {
+        self.instances.borrow_mut().reserve(capacity);
+    }
+
+    #[allow(dead_code)]
+    pub fn set_instance_visible(&self, index: usize, visible: bool) {
+        self.instances.borrow_mut().set_visible(index, visible);
+    }
+
+    #[allow(dead_code)]
+    pub fn is_instance_visible(&self, index: usize) -> bool {
+        self.instances.borrow().visible.get(index).cloned().unwrap_or(false)
+    }
+
+    /// Draws all visible instances
+    #[allow(dead_code)]
     pub fn draw_all(&self) {
-        draw_all(self, self.program_id, self.indices_count, self.instance_count);
+        self.draw_range(0, self.instance_count() as usize);
+    }
+
+    /// Draws instance at index, if it is visible
+    #[allow(dead_code)]
+    pub fn draw_instance(&self, index: usize) {
+        self.draw_range(index, 1);
+    }
+
+    /// Draws the visible instances in [first, first + count)
+    pub fn draw_range(&self, first: usize, count: usize) {
+        draw_range(self, self.program_id, self.indices_count, &self.instances.borrow(), first, count);
     }
 
+    /// Queues all visible instances
+    pub fn queue_all(&self, queue: &mut RenderQueue) {
+        self.queue_range(queue, 0, self.instance_count() as usize);
+    }
+
+    /// Queues the visible instances in [first, first + count)
+    pub fn queue_range(&self, queue: &mut RenderQueue, first: usize, count: usize) {
+        let item = DrawItem {
+            program_id: self.program_id,
+            material: self.material,
+            vao_id: self.id,
+            elem_id: self.vbo_ids[GeometricObject::ELEM_INDEX],
+            indices_count: self.indices_count,
+            first_instance: 0,
+            instance_count: 0,
+            depth: 0.0,
+        };
+        queue_range(queue, item, &self.instances.borrow(), first, count);
+    }
+
+    /// Creates a handle to an already allocated instance slot
     pub fn create_geometric_instance(&self, index: usize) -> Option<GeometricInstance> {
-        if (self.instance_count as usize) < index {
+        if !self.instances.borrow().is_allocated(index) {
             return None; // TODO: ERROR
         }
-        
-        Some(GeometricInstance {
+
+        Some(self.instance_handle(index))
+    }
+
+    /// Allocates an instance slot, reusing freed slots and growing the instance buffer when needed
+    pub fn allocate_geometric_instance(&self) -> GeometricInstance {
+        let index = self.instances.borrow_mut().allocate();
+        self.instance_handle(index)
+    }
+
+    #[allow(dead_code)]
+    pub fn free_instance(&self, index: usize) {
+        self.instances.borrow_mut().free(index);
+    }
+
+    #[allow(dead_code)]
+    pub fn update_transform(&self, index: usize, new_transform: &glm::Mat4) {
+        self.instances.borrow_mut().update_transform(index, new_transform);
+    }
+
+    fn instance_handle(&self, index: usize) -> GeometricInstance {
+        GeometricInstance {
             vao_id: self.id,
             program_id: self.program_id,
+            material: self.material,
             elem_id: self.vbo_ids[GeometricObject::ELEM_INDEX],
-            instances_id: self.vbo_ids[GeometricObject::INST_INDEX],
             indices_count: self.indices_count,
-            instance_count: self.instance_count,
-            instance_index: index
-        })
-    }
-
-    pub fn update_transform(&self, index: usize, new_transform: &glm::Mat4) {
-        if (self.instance_count as usize) < index {
-            return; // ERROR
+            instance_index: index,
+            pick_mesh: self.pick_mesh.clone(),
+            bounds: self.bounds,
+            instances: Rc::clone(&self.instances),
         }
-
-        update_transform(index, &new_transform, self.vbo_ids[GeometricObject::INST_INDEX]);
     }
 }
 
 fn update_transform(index: usize, new_transform: &glm::Mat4, instance_id: GLuint) {
     let mat4_size = std::mem::size_of::<glm::Mat4>();
+    profiler::record_upload(mat4_size);
     unsafe {
         gl::BindBuffer(gl::ARRAY_BUFFER, instance_id);
         gl::BufferSubData(
             gl::ARRAY_BUFFER,
             (mat4_size * index) as GLintptr,
             mat4_size as isize,
-            new_transform.as_ptr() as *const f32 as *const core::ffi::c_void
+            new_transform.as_ptr() as *const core::ffi::c_void
         );
         gl::BindBuffer(gl::ARRAY_BUFFER, 0);
     }
 }
 
-fn draw_all<T: Bindable>(target: &T, program_id: GLuint, indices_count: GLsizei, instance_count: GLsizei) {
+/// Draws every visible run of instances in the range with one glDrawElementsInstancedBaseInstance each
+fn draw_range<T: Bindable>(target: &T, program_id: GLuint, indices_count: GLsizei, instances: &InstanceState, first: usize, count: usize) {
+    let runs = instances.visible_runs(first, count);
+    if runs.is_empty() {
+        return;
+    }
+
     target.bind();
 
     unsafe {
         gl::UseProgram(program_id);
-        gl::DrawElementsInstanced(
-            gl::TRIANGLES,
-            indices_count,
-            gl::UNSIGNED_INT,
-            std::ptr::null(),
-            instance_count
-        ); 
+        for (run_first, run_count) in runs {
+            gl::DrawElementsInstancedBaseInstance(
+                gl::TRIANGLES,
+                indices_count,
+                gl::UNSIGNED_INT,
+                std::ptr::null(),
+                run_count as GLsizei,
+                run_first as GLuint
+            );
+        }
         gl::UseProgram(0);
     }
 
     target.unbind();
-}
\ No newline at end of file
+}
+
+/// Queues one copy of the template item for every visible run of instances in the range.
+/// Transparent instances are queued one by one so that the queue can sort them by depth
+fn queue_range(queue: &mut RenderQueue, template: DrawItem, instances: &InstanceState, first: usize, count: usize) {
+    for (run_first, run_count) in instances.visible_runs(first, count) {
+        if template.material.blend_mode.is_transparent() {
+            for i in run_first..(run_first + run_count) {
+                let t = &instances.transforms[i];
+                let position = glm::vec3(t[12], t[13], t[14]);
+                queue.push(DrawItem {
+                    first_instance: i as GLuint,
+                    instance_count: 1,
+                    depth: glm::distance(&position, &queue.view_position),
+                    ..template
+                });
+            }
+        } else {
+            queue.push(DrawItem {
+                first_instance: run_first as GLuint,
+                instance_count: run_count as GLsizei,
+                ..template
+            });
+        }
+    }
+}
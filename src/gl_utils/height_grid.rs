@@ -0,0 +1,266 @@
+extern crate nalgebra_glm as glm;
+
+use std::collections::HashSet;
+
+use super::mesh::Mesh;
+
+#[derive(Debug, Clone, Copy)]
+pub struct RayHit {
+    pub distance: f32,
+    pub point: glm::Vec3,
+    /// Faces the ray origin
+    pub normal: glm::Vec3,
+}
+
+/// Height and ray queries against terrain, answered by the grid of a terrain mesh or by the height function of
+/// streamed terrain, which has no edges
+pub trait Ground {
+    /// Height of the highest surface at (x, z), None outside the terrain
+    fn height_at(&self, x: f32, z: f32) -> Option<f32>;
+
+    /// Closest hit along a ray within max_distance, direction does not have to be normalized
+    fn raycast(&self, origin: &glm::Vec3, direction: &glm::Vec3, max_distance: f32) -> Option<RayHit>;
+
+    /// First hit on the segment from start to end
+    fn intersect_segment(&self, start: &glm::Vec3, end: &glm::Vec3) -> Option<RayHit> {
+        let length = glm::distance(start, end);
+        if length <= 0.0 {
+            return None;
+        }
+        self.raycast(start, &(end - start), length)
+    }
+}
+
+/// Triangles of a mesh bucketed into a uniform grid over the xz plane, for height and ray queries against terrain.
+/// Works for any mesh, but is only fast when the mesh is spread out horizontally like a heightmap
+pub struct HeightGrid {
+    triangles: Vec<[glm::Vec3; 3]>,
+    /// Indices into triangles for every cell, row major with x along the row
+    cells: Vec<Vec<u32>>,
+    min: glm::Vec2,
+    cell_size: f32,
+    columns: usize,
+    rows: usize,
+}
+
+impl HeightGrid {
+    pub fn from_mesh(mesh: &Mesh, cell_size: f32) -> Self {
+        let triangles: Vec<[glm::Vec3; 3]> = mesh.triangles().collect();
+        let (min, max) = mesh.bounds();
+        let (min, max) = (min.xz(), max.xz());
+
+        let columns = (((max.x - min.x) / cell_size).ceil() as usize).max(1);
+        let rows = (((max.y - min.y) / cell_size).ceil() as usize).max(1);
+
+        let mut grid = Self {
+            triangles,
+            cells: vec![Vec::new(); columns * rows],
+            min,
+            cell_size,
+            columns,
+            rows,
+        };
+
+        // A triangle goes in every cell its xz bounding box touches
+        for (index, triangle) in grid.triangles.iter().enumerate() {
+            let lower = glm::min2(&glm::min2(&triangle[0].xz(), &triangle[1].xz()), &triangle[2].xz());
+            let upper = glm::max2(&glm::max2(&triangle[0].xz(), &triangle[1].xz()), &triangle[2].xz());
+            let (c0, r0) = grid.cell_coordinates(&lower);
+            let (c1, r1) = grid.cell_coordinates(&upper);
+            for r in r0..=r1 {
+                for c in c0..=c1 {
+                    grid.cells[r * grid.columns + c].push(index as u32);
+                }
+            }
+        }
+
+        grid
+    }
+
+    /// Cell containing a point, points outside the grid are clamped to the closest edge cell
+    fn cell_coordinates(&self, p: &glm::Vec2) -> (usize, usize) {
+        let local = (p - self.min) / self.cell_size;
+        (
+            (local.x.max(0.0) as usize).min(self.columns - 1),
+            (local.y.max(0.0) as usize).min(self.rows - 1),
+        )
+    }
+
+    fn contains(&self, x: f32, z: f32) -> bool {
+        x >= self.min.x && z >= self.min.y
+            && x <= self.min.x + self.columns as f32 * self.cell_size
+            && z <= self.min.y + self.rows as f32 * self.cell_size
+    }
+
+    /// Highest triangle straight above or below (x, z), with its height
+    fn top_triangle(&self, x: f32, z: f32) -> Option<(f32, &[glm::Vec3; 3])> {
+        if !self.contains(x, z) {
+            return None;
+        }
+
+        let (c, r) = self.cell_coordinates(&glm::vec2(x, z));
+        let p = glm::vec2(x, z);
+        let mut top: Option<(f32, &[glm::Vec3; 3])> = None;
+        for &index in &self.cells[r * self.columns + c] {
+            let triangle = &self.triangles[index as usize];
+            let (a, b, c) = (triangle[0].xz(), triangle[1].xz(), triangle[2].xz());
+
+            // Barycentric coordinates in the xz plane, vertical triangles have no area and can't be stood on
+            let area = (b - a).perp(&(c - a));
+            if area.abs() < 1e-8 {
+                continue;
+            }
+            let u = (c - b).perp(&(p - b)) / area;
+            let v = (a - c).perp(&(p - c)) / area;
+            let w = 1.0 - u - v;
+            if u < 0.0 || v < 0.0 || w < 0.0 {
+                continue;
+            }
+
+            let height = u * triangle[0].y + v * triangle[1].y + w * triangle[2].y;
+            if top.map_or(true, |(top_height, _)| height > top_height) {
+                top = Some((height, triangle));
+            }
+        }
+
+        top
+    }
+
+    /// Upwards facing normal of the highest surface at (x, z), None outside the terrain
+    #[allow(dead_code)]
+    pub fn normal_at(&self, x: f32, z: f32) -> Option<glm::Vec3> {
+        self.top_triangle(x, z).map(|(_, t)| {
+            let normal = glm::normalize(&glm::cross(&(t[1] - t[0]), &(t[2] - t[0])));
+            if normal.y < 0.0 { -normal } else { normal }
+        })
+    }
+
+    /// Cells under a ray in the order it passes them, with the distance along the ray where each is entered. 2D DDA over xz
+    fn cells_along(&self, origin: &glm::Vec3, direction: &glm::Vec3, max_distance: f32) -> Vec<(usize, usize, f32)> {
+        let mut cells = Vec::new();
+
+        // Clip the ray to the grid bounds first
+        let grid_max = self.min + glm::vec2(self.columns as f32, self.rows as f32) * self.cell_size;
+        let (mut t_enter, mut t_exit) = (0.0f32, max_distance);
+        for axis in 0..2 {
+            let (o, d) = (origin.xz()[axis], direction.xz()[axis]);
+            if d.abs() < 1e-8 {
+                if o < self.min[axis] || o > grid_max[axis] {
+                    return cells;
+                }
+                continue;
+            }
+            let t0 = (self.min[axis] - o) / d;
+            let t1 = (grid_max[axis] - o) / d;
+            t_enter = t_enter.max(t0.min(t1));
+            t_exit = t_exit.min(t0.max(t1));
+        }
+        if t_enter > t_exit {
+            return cells;
+        }
+
+        let start = origin.xz() + direction.xz() * t_enter;
+        let (mut c, mut r) = self.cell_coordinates(&start);
+        let step = |d: f32| if d > 0.0 { 1isize } else { -1isize };
+        let (step_c, step_r) = (step(direction.x), step(direction.z));
+
+        // Distance along the ray to the next cell border on each axis, and between borders
+        let next_border = |cell: usize, axis: usize, d: f32| {
+            if d.abs() < 1e-8 {
+                return f32::MAX;
+            }
+            let border = self.min[axis] + (cell as f32 + if d > 0.0 { 1.0 } else { 0.0 }) * self.cell_size;
+            (border - origin.xz()[axis]) / d
+        };
+        let mut t_c = next_border(c, 0, direction.x);
+        let mut t_r = next_border(r, 1, direction.z);
+        let delta_c = if direction.x.abs() < 1e-8 { f32::MAX } else { self.cell_size / direction.x.abs() };
+        let delta_r = if direction.z.abs() < 1e-8 { f32::MAX } else { self.cell_size / direction.z.abs() };
+
+        let mut t = t_enter;
+        loop {
+            cells.push((c, r, t));
+            if t_c < t_r {
+                t = t_c;
+                t_c += delta_c;
+                c = match c.checked_add_signed(step_c) { Some(c) if c < self.columns => c, _ => break };
+            } else {
+                t = t_r;
+                t_r += delta_r;
+                r = match r.checked_add_signed(step_r) { Some(r) if r < self.rows => r, _ => break };
+            }
+            if t > t_exit {
+                break;
+            }
+        }
+
+        cells
+    }
+}
+
+impl Ground for HeightGrid {
+    fn height_at(&self, x: f32, z: f32) -> Option<f32> {
+        self.top_triangle(x, z).map(|(height, _)| height)
+    }
+
+    fn raycast(&self, origin: &glm::Vec3, direction: &glm::Vec3, max_distance: f32) -> Option<RayHit> {
+        let direction = glm::normalize(direction);
+        let mut closest: Option<RayHit> = None;
+        let mut tested = HashSet::<u32>::new();
+
+        // Walk the cells the ray passes over, a triangle can span several cells so each is only tested once
+        for (c, r, cell_enter) in self.cells_along(origin, &direction, max_distance) {
+            // Nothing in later cells can be closer than a hit we already have
+            if closest.is_some_and(|hit| hit.distance < cell_enter) {
+                break;
+            }
+
+            for &index in &self.cells[r * self.columns + c] {
+                if !tested.insert(index) {
+                    continue;
+                }
+
+                if let Some(distance) = ray_triangle(origin, &direction, &self.triangles[index as usize]) {
+                    if distance <= max_distance && closest.map_or(true, |hit| distance < hit.distance) {
+                        let t = &self.triangles[index as usize];
+                        let normal = glm::normalize(&glm::cross(&(t[1] - t[0]), &(t[2] - t[0])));
+                        closest = Some(RayHit {
+                            distance,
+                            point: origin + direction * distance,
+                            normal: if glm::dot(&normal, &direction) > 0.0 { -normal } else { normal },
+                        });
+                    }
+                }
+            }
+        }
+
+        closest
+    }
+}
+
+/// Möller-Trumbore, distance along a normalized ray to the triangle, both sides count
+pub fn ray_triangle(origin: &glm::Vec3, direction: &glm::Vec3, triangle: &[glm::Vec3; 3]) -> Option<f32> {
+    let edge1 = triangle[1] - triangle[0];
+    let edge2 = triangle[2] - triangle[0];
+    let p = glm::cross(direction, &edge2);
+    let determinant = glm::dot(&edge1, &p);
+    if determinant.abs() < 1e-8 {
+        return None;
+    }
+
+    let inverse = 1.0 / determinant;
+    let s = origin - triangle[0];
+    let u = glm::dot(&s, &p) * inverse;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+
+    let q = glm::cross(&s, &edge1);
+    let v = glm::dot(direction, &q) * inverse;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+
+    let distance = glm::dot(&edge2, &q) * inverse;
+    if distance >= 0.0 { Some(distance) } else { None }
+}
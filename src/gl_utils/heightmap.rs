@@ -0,0 +1,283 @@
+extern crate nalgebra_glm as glm;
+
+use image::DynamicImage;
+
+use std::fmt;
+
+use super::mesh::Mesh;
+
+#[derive(Debug)]
+pub enum HeightmapError {
+    Image(image::ImageError),
+    /// At least two samples are needed in each direction to make a surface
+    TooSmall(u32, u32),
+}
+
+impl fmt::Display for HeightmapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HeightmapError::Image(e) => e.fmt(f),
+            HeightmapError::TooSmall(w, h) => write!(f, "heightmap of {}x{} is too small, it needs at least 2x2 samples", w, h),
+        }
+    }
+}
+
+/// Settings for fractal noise, each octave adds detail at a higher frequency and lower amplitude
+#[derive(Debug, Clone, Copy)]
+pub struct NoiseSettings {
+    pub octaves: u32,
+    /// Features across the whole map in the first octave
+    pub frequency: f32,
+    /// Amplitude multiplier between octaves
+    pub persistence: f32,
+    /// Frequency multiplier between octaves
+    pub lacunarity: f32,
+}
+
+impl Default for NoiseSettings {
+    fn default() -> Self {
+        Self {
+            octaves: 6,
+            frequency: 4.0,
+            persistence: 0.5,
+            lacunarity: 2.0,
+        }
+    }
+}
+
+/// Grid of heights between 0 and 1, row major with x along the row
+pub struct Heightmap {
+    pub columns: usize,
+    pub rows: usize,
+    heights: Vec<f32>,
+}
+
+impl Heightmap {
+    /// Brightness is height, 16 bit grayscale images keep their precision
+    pub fn load(path: &str) -> Result<Self, HeightmapError> {
+        let (columns, rows, heights) = match image::open(path).map_err(HeightmapError::Image)? {
+            DynamicImage::ImageLuma16(image) => (image.width(), image.height(), image.pixels().map(|p| p[0] as f32 / 65535.0).collect()),
+            image => {
+                let image = image.to_luma();
+                (image.width(), image.height(), image.pixels().map(|p| p[0] as f32 / 255.0).collect())
+            },
+        };
+        if columns < 2 || rows < 2 {
+            return Err(HeightmapError::TooSmall(columns, rows));
+        }
+
+        Ok(Self {
+            columns: columns as usize,
+            rows: rows as usize,
+            heights,
+        })
+    }
+
+    /// Gradient noise summed over octaves and scaled to fill 0 to 1. The same seed always gives the same map
+    pub fn fractal(columns: usize, rows: usize, seed: u64, settings: &NoiseSettings) -> Self {
+        let columns = columns.max(2);
+        let rows = rows.max(2);
+        let mut heights = Vec::with_capacity(columns * rows);
+        for row in 0..rows {
+            for column in 0..columns {
+                let (u, v) = (column as f32 / (columns - 1) as f32, row as f32 / (rows - 1) as f32);
+                heights.push(fractal_noise(u, v, seed, settings));
+            }
+        }
+
+        let min = heights.iter().cloned().fold(f32::MAX, f32::min);
+        let max = heights.iter().cloned().fold(f32::MIN, f32::max);
+        let range = (max - min).max(1e-6);
+        heights.iter_mut().for_each(|h| *h = (*h - min) / range);
+
+        Self {
+            columns,
+            rows,
+            heights,
+        }
+    }
+
+    pub fn get(&self, column: usize, row: usize) -> f32 {
+        self.heights[row.min(self.rows - 1) * self.columns + column.min(self.columns - 1)]
+    }
+
+    /// Bilinear lookup, u and v go from 0 to 1 across the map and are clamped
+    pub fn sample(&self, u: f32, v: f32) -> f32 {
+        let x = u.clamp(0.0, 1.0) * (self.columns - 1) as f32;
+        let y = v.clamp(0.0, 1.0) * (self.rows - 1) as f32;
+        let (column, row) = (x.floor() as usize, y.floor() as usize);
+        let (fx, fy) = (x.fract(), y.fract());
+
+        let top = self.get(column, row) * (1.0 - fx) + self.get(column + 1, row) * fx;
+        let bottom = self.get(column, row + 1) * (1.0 - fx) + self.get(column + 1, row + 1) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+}
+
+/// Turns a heightmap into a grid mesh in the xz plane
+pub struct TerrainBuilder {
+    size: glm::Vec2,
+    /// Center in the xz plane
+    origin: glm::Vec2,
+    height: f32,
+    resolution: (usize, usize),
+    color: [f32; 4],
+}
+
+impl Default for TerrainBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TerrainBuilder {
+    pub fn new() -> Self {
+        Self {
+            size: glm::vec2(512.0, 512.0),
+            origin: glm::vec2(0.0, 0.0),
+            height: 30.0,
+            resolution: (257, 257),
+            color: [1.0, 1.0, 1.0, 1.0],
+        }
+    }
+
+    /// Width along x and depth along z
+    #[must_use]
+    pub fn size(mut self, width: f32, depth: f32) -> Self {
+        self.size = glm::vec2(width, depth);
+        self
+    }
+
+    /// Center of the terrain in the xz plane
+    #[must_use]
+    pub fn origin(mut self, x: f32, z: f32) -> Self {
+        self.origin = glm::vec2(x, z);
+        self
+    }
+
+    /// Height of the highest point of a heightmap, the lowest is at 0
+    #[must_use]
+    pub fn height(mut self, height: f32) -> Self {
+        self.height = height;
+        self
+    }
+
+    /// Vertices along x and z, independent of the heightmap resolution
+    #[must_use]
+    pub fn resolution(mut self, columns: usize, rows: usize) -> Self {
+        self.resolution = (columns.max(2), rows.max(2));
+        self
+    }
+
+    #[must_use]
+    pub fn color(mut self, color: [f32; 4]) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn build(&self, heightmap: &Heightmap) -> Mesh {
+        self.build_from_fn(|x, z| {
+            let u = (x - self.origin.x) / self.size.x + 0.5;
+            let v = (z - self.origin.y) / self.size.y + 0.5;
+            heightmap.sample(u, v) * self.height
+        })
+    }
+
+    /// Like build, but heights come from a function of world x and z instead of a heightmap
+    pub fn build_from_fn(&self, height: impl Fn(f32, f32) -> f32) -> Mesh {
+        let (columns, rows) = self.resolution;
+        let step = glm::vec2(self.size.x / (columns - 1) as f32, self.size.y / (rows - 1) as f32);
+        let corner = self.origin - self.size * 0.5;
+        // Columns and rows outside the grid are fine, the normals along the edges look at their neighbours
+        let position = |column: i64, row: i64| {
+            let x = corner.x + column as f32 * step.x;
+            let z = corner.y + row as f32 * step.y;
+            glm::vec3(x, height(x, z), z)
+        };
+
+        let mut vertices = Vec::with_capacity(columns * rows * 3);
+        let mut normals = Vec::with_capacity(columns * rows * 3);
+        let mut uvs = Vec::with_capacity(columns * rows * 2);
+        for row in 0..rows as i64 {
+            for column in 0..columns as i64 {
+                let p = position(column, row);
+                vertices.extend_from_slice(&[p.x, p.y, p.z]);
+
+                // Smooth normals from central differences
+                let dx = position(column + 1, row) - position(column - 1, row);
+                let dz = position(column, row + 1) - position(column, row - 1);
+                let n = glm::normalize(&glm::cross(&dz, &dx));
+                normals.extend_from_slice(&[n.x, n.y, n.z]);
+
+                uvs.push(column as f32 / (columns - 1) as f32);
+                uvs.push(row as f32 / (rows - 1) as f32);
+            }
+        }
+
+        // Two counter clockwise triangles per quad when seen from above
+        let mut indices = Vec::with_capacity((columns - 1) * (rows - 1) * 6);
+        for row in 0..rows - 1 {
+            for column in 0..columns - 1 {
+                let i = (row * columns + column) as u32;
+                let below = i + columns as u32;
+                indices.extend_from_slice(&[i, below, i + 1, i + 1, below, below + 1]);
+            }
+        }
+
+        let vertex_count = columns * rows;
+        let index_count = indices.len() as i32;
+        Mesh {
+            vertices,
+            normals,
+            uvs,
+            tangents: Vec::new(),
+            colors: self.color.iter().cloned().cycle().take(vertex_count * 4).collect(),
+            indices,
+            index_count,
+        }
+    }
+}
+
+/// Fractal noise at a point, where 1 unit is the whole map for settings.frequency. Not normalized,
+/// it roughly stays between -1 and 1. Can be sampled anywhere, which is what unbounded terrain needs
+pub fn fractal_noise(x: f32, y: f32, seed: u64, settings: &NoiseSettings) -> f32 {
+    let (mut x, mut y) = (x * settings.frequency, y * settings.frequency);
+    let mut amplitude = 1.0;
+    let mut height = 0.0;
+    for octave in 0..settings.octaves {
+        height += gradient_noise(x, y, seed.wrapping_add(octave as u64)) * amplitude;
+        amplitude *= settings.persistence;
+        x *= settings.lacunarity;
+        y *= settings.lacunarity;
+    }
+
+    height
+}
+
+/// Hash of a lattice point, the same inputs always give the same output
+fn hash(x: i32, y: i32, seed: u64) -> u64 {
+    // splitmix64 finalizer over the combined coordinates
+    let mut h = seed ^ ((x as u32 as u64) << 32 | y as u32 as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    h = (h ^ (h >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    h = (h ^ (h >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    h ^ (h >> 31)
+}
+
+/// Perlin style noise with a random unit gradient at every lattice point, roughly between -0.7 and 0.7
+fn gradient_noise(x: f32, y: f32, seed: u64) -> f32 {
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let (ix, iy) = (x0 as i32, y0 as i32);
+
+    let corner = |cx: i32, cy: i32| {
+        let angle = (hash(ix + cx, iy + cy, seed) >> 40) as f32 / (1u64 << 24) as f32 * 2.0 * std::f32::consts::PI;
+        angle.cos() * (fx - cx as f32) + angle.sin() * (fy - cy as f32)
+    };
+    // Quintic fade, so the slope is continuous across cells
+    let fade = |t: f32| t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+    let (sx, sy) = (fade(fx), fade(fy));
+
+    let top = corner(0, 0) + (corner(1, 0) - corner(0, 0)) * sx;
+    let bottom = corner(0, 1) + (corner(1, 1) - corner(0, 1)) * sx;
+    top + (bottom - top) * sy
+}
@@ -0,0 +1,92 @@
+use gl::types::{GLchar, GLint, GLuint};
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlendMode {
+    Opaque,
+    /// Classic alpha blending, src * a + dst * (1 - a)
+    Alpha,
+    /// src * a + dst, useful for glow and particles
+    Additive,
+    /// Color is already multiplied with alpha, src + dst * (1 - a)
+    Premultiplied,
+}
+
+impl BlendMode {
+    pub fn is_transparent(&self) -> bool {
+        *self != BlendMode::Opaque
+    }
+
+    /// Set the gl blend state for this mode
+    pub fn apply(&self) {
+        unsafe {
+            match self {
+                BlendMode::Opaque => {
+                    gl::Disable(gl::BLEND);
+                    return;
+                },
+                BlendMode::Alpha => gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA),
+                BlendMode::Additive => gl::BlendFunc(gl::SRC_ALPHA, gl::ONE),
+                BlendMode::Premultiplied => gl::BlendFunc(gl::ONE, gl::ONE_MINUS_SRC_ALPHA),
+            }
+            gl::Enable(gl::BLEND);
+        }
+    }
+}
+
+/// Render state shared by all instances of a GeometricObject
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Material {
+    /// Used to group draws with the same material, should be unique per material
+    pub id: u32,
+    pub blend_mode: BlendMode,
+    /// How much of the environment cubemap is mirrored, 0 is none and 1 is a perfect mirror
+    pub reflectivity: f32,
+}
+
+impl Material {
+    pub fn new(id: u32, blend_mode: BlendMode) -> Self {
+        Self {
+            id,
+            blend_mode,
+            reflectivity: 0.0,
+        }
+    }
+
+    #[must_use]
+    pub fn with_reflectivity(mut self, reflectivity: f32) -> Self {
+        self.reflectivity = reflectivity;
+        self
+    }
+
+    /// Set the uniforms for this material on the bound program they were located in, missing uniforms are left alone
+    pub fn apply_uniforms(&self, uniforms: &MaterialUniforms) {
+        unsafe {
+            if uniforms.reflectivity >= 0 {
+                gl::Uniform1f(uniforms.reflectivity, self.reflectivity);
+            }
+        }
+    }
+}
+
+/// Locations of the material uniforms in a program, -1 for uniforms the program does not have
+#[derive(Debug, Clone, Copy)]
+pub struct MaterialUniforms {
+    reflectivity: GLint,
+}
+
+impl MaterialUniforms {
+    pub fn locate(program_id: GLuint) -> Self {
+        unsafe {
+            Self {
+                reflectivity: gl::GetUniformLocation(program_id, "reflectivity\0".as_ptr() as *const GLchar),
+            }
+        }
+    }
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self::new(0, BlendMode::Opaque)
+    }
+}
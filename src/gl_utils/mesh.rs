@@ -9,6 +9,10 @@ fn generate_color_vec(color: [f32; 4], num: usize) -> Vec<f32> {
 pub struct Mesh {
     pub vertices: Vec<f32>,
     pub normals: Vec<f32>,
+    /// Two per vertex, empty when the source has none
+    pub uvs: Vec<f32>,
+    /// Four per vertex, the direction of increasing u and the handedness of the bitangent in w. Empty when not generated
+    pub tangents: Vec<f32>,
     pub colors: Vec<f32>,
     pub indices: Vec<u32>,
     pub index_count: i32,
@@ -18,21 +22,43 @@ impl Mesh {
     pub fn from(mesh: tobj::Mesh, color: [f32; 4]) -> Self {
         let num_verts = mesh.positions.len() / 3;
         let index_count = mesh.indices.len() as i32;
-        Mesh {
+        let missing_normals = mesh.normals.is_empty();
+        let mut result = Mesh {
             vertices: mesh.positions,
             normals: mesh.normals,
+            uvs: mesh.texcoords,
+            tangents: Vec::new(),
             indices: mesh.indices,
             colors: generate_color_vec(color, num_verts),
             index_count,
+        };
+        // Not every OBJ has normals, edges sharper than 60 degrees stay hard
+        if missing_normals {
+            result.compute_smooth_normals(60f32.to_radians());
         }
+        result
     }
 
-    pub fn into_geomtric_object(self, program_id: u32, instance_transfoms: &Vec<glm::Mat4>) -> GeometricObject {
-        let buffer_attrib_pairs = vec![
-            VerticesAttributesPair::init(self.vertices, gl::FLOAT).add_attribute(0, 0, 3, 0),
-            VerticesAttributesPair::init(self.normals, gl::FLOAT).add_attribute(1, 1, 3, 0),
-            VerticesAttributesPair::init(self.colors, gl::FLOAT).add_attribute(2, 2, 4, 0),
-        ];
+    /// Replaces the color of every vertex
+    #[must_use]
+    pub fn with_color(mut self, color: [f32; 4]) -> Self {
+        self.colors = generate_color_vec(color, self.vertices.len() / 3);
+        self
+    }
+
+    /// One interleaved buffer of 24 bytes a vertex, instead of three float buffers with 40
+    fn vertex_layout() -> VertexLayout {
+        VertexLayout::new()
+            .float(0, 3)
+            .normalized(1, 3, ComponentFormat::I16)
+            .padding(2)
+            .normalized(2, 4, ComponentFormat::U8)
+    }
+
+    pub fn into_geomtric_object(self, program_id: u32, instance_transfoms: &[glm::Mat4]) -> GeometricObject {
+        let layout = Mesh::vertex_layout();
+        let data = layout.pack(self.vertices.len() / 3, &[&self.vertices, &self.normals, &self.colors]);
+        let buffer_attrib_pairs = vec![VerticesAttributesPair::with_layout(data, layout)];
 
         GeometricObject::init(program_id, &buffer_attrib_pairs, &self.indices, instance_transfoms)
     }
@@ -43,21 +69,21 @@ impl Terrain {
     pub fn load(path: &str) -> Mesh {
         println!("Loading terrain model...");
         let before = std::time::Instant::now();
-        let (models, _materials) = tobj::load_obj(path, true).expect("Failed to load terrain model");
+        let mut models = mesh_cache::load_obj(path).expect("Failed to load terrain model");
         let after = std::time::Instant::now();
         println!("Done in {:.3}ms.", after.duration_since(before).as_micros() as f32 / 1e3);
-        if models.len() > 1 || models.len() == 0 { panic!("Please use a model with a single mesh") }
+        if models.len() != 1 { panic!("Please use a model with a single mesh") }
 
-        let terrain = models[0].to_owned();
-        println!("Loaded {} with {} points and {} triangles.", terrain.name, terrain.mesh.positions.len() /3, terrain.mesh.indices.len() / 3);
+        let terrain = models.remove(0);
+        println!("Loaded {} with {} points and {} triangles.", terrain.name, terrain.mesh.vertices.len() /3, terrain.mesh.indices.len() / 3);
 
-        Mesh::from(terrain.mesh, [1.0, 1.0, 1.0, 1.0])
+        terrain.mesh
     }
 }
 
 use std::ops::Index;
 
-use super::{geometric_object::GeometricObject, vertex_attributes::VerticesAttributesPair};
+use super::{geometric_object::GeometricObject, mesh_cache, vertex_attributes::{ComponentFormat, VertexLayout, VerticesAttributesPair}};
 pub struct Helicopter {
     pub body: Mesh,
     pub main_rotor: Mesh,
@@ -68,7 +94,7 @@ pub struct Helicopter {
 // You can use square brackets to access the components of the helicopter, if you want to use loops!
 impl Index<usize> for Helicopter {
     type Output = Mesh;
-    fn index<'a>(&'a self, i: usize) -> &'a Mesh {
+    fn index(&self, i: usize) -> &Mesh {
         match i {
             0 => &self.body,
             1 => &self.main_rotor,
@@ -83,24 +109,42 @@ impl Helicopter {
     pub fn load(path: &str) -> Self {
         println!("Loading helicopter model...");
         let before = std::time::Instant::now();
-        let (models, _materials) = tobj::load_obj(path, true).expect("Failed to load helicopter model");
+        let mut models = mesh_cache::load_obj(path).expect("Failed to load helicopter model");
         let after = std::time::Instant::now();
         println!("Done in {:.3}ms.", after.duration_since(before).as_micros() as f32 / 1e3);
 
         for model in &models {
-            println!("Loaded {} with {} points and {} triangles.", model.name, model.mesh.positions.len() / 3, model.mesh.indices.len() / 3);
+            println!("Loaded {} with {} points and {} triangles.", model.name, model.mesh.vertices.len() / 3, model.mesh.indices.len() / 3);
         }
 
-        let body_model = models.iter().find(|m| m.name == "Body_body").expect("Incorrect model file!").to_owned();
-        let main_rotor_model = models.iter().find(|m| m.name == "Main_Rotor_main_rotor").expect("Incorrect model file!").to_owned();
-        let tail_rotor_model = models.iter().find(|m| m.name == "Tail_Rotor_tail_rotor").expect("Incorrect model file!").to_owned();
-        let door_model = models.iter().find(|m| m.name == "Door_door").expect("Incorrect model file!").to_owned();
+        let mut take = |name: &str| {
+            let i = models.iter().position(|m| m.name == name).expect("Incorrect model file!");
+            models.swap_remove(i).mesh
+        };
 
         Helicopter {
-            body:       Mesh::from(body_model.mesh,         [0.3, 0.3, 0.3, 1.0]),
-            main_rotor: Mesh::from(main_rotor_model.mesh,   [0.3, 0.1, 0.1, 1.0]),
-            tail_rotor: Mesh::from(tail_rotor_model.mesh,   [0.1, 0.3, 0.1, 1.0]),
-            door:       Mesh::from(door_model.mesh,         [0.1, 0.1, 0.3, 1.0]),
+            body:       take("Body_body").with_color([0.3, 0.3, 0.3, 1.0]),
+            main_rotor: take("Main_Rotor_main_rotor").with_color([0.3, 0.1, 0.1, 1.0]),
+            tail_rotor: take("Tail_Rotor_tail_rotor").with_color([0.1, 0.3, 0.1, 1.0]),
+            door:       take("Door_door").with_color([0.1, 0.1, 0.3, 0.5]),
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vertex_layout_packs_a_vertex_into_24_bytes() {
+        let layout = Mesh::vertex_layout();
+        let offsets: Vec<usize> = layout.attributes().iter().map(|a| a.offset).collect();
+        assert_eq!(offsets, [0, 12, 20]);
+        assert_eq!(layout.stride(), 24);
+
+        let data = layout.pack(1, &[&[1.0, 2.0, 3.0], &[0.0, -1.0, 0.0], &[1.0, 0.0, 0.0, 1.0]]);
+        assert_eq!(data.len(), 24);
+        assert_eq!(data[4..8], 2.0f32.to_le_bytes());
+        assert_eq!(data[14..16], (-i16::MAX).to_le_bytes());
+        assert_eq!(data[20..24], [255, 0, 0, 255]);
+    }
+}
@@ -0,0 +1,386 @@
+extern crate nalgebra_glm as glm;
+
+use memmap2::Mmap;
+
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use super::mesh::Mesh;
+
+// Binary copy of the models in an OBJ file, so big assets load without parsing text. Little endian, laid out as
+//   header:  magic "GMSH", version u32, model count u32
+//   model:   name, material (strings are a u32 length and utf8 bytes, padded to 4 bytes, u32::MAX length for none),
+//            flags u32, vertex count u32, index count u32, bounds min and max as 6 f32,
+//            interleaved vertices of f32 (position, then normal, uv and tangent when flagged), indices as u32
+
+const MAGIC: &[u8; 4] = b"GMSH";
+/// Bumped whenever the layout changes, older caches are then rebuilt from the OBJ
+const VERSION: u32 = 1;
+
+const HAS_NORMALS: u32 = 1;
+const HAS_UVS: u32 = 1 << 1;
+const HAS_TANGENTS: u32 = 1 << 2;
+
+#[derive(Debug)]
+pub enum MeshCacheError {
+    Io(io::Error),
+    Obj(tobj::LoadError),
+    NotAMeshFile,
+    UnsupportedVersion(u32),
+    /// The file ended in the middle of a model
+    Truncated,
+    /// An index past the end of its model's vertices
+    InvalidIndex(u32),
+}
+
+impl fmt::Display for MeshCacheError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MeshCacheError::Io(e) => e.fmt(f),
+            MeshCacheError::Obj(e) => e.fmt(f),
+            MeshCacheError::NotAMeshFile => write!(f, "not a mesh cache file"),
+            MeshCacheError::UnsupportedVersion(v) => write!(f, "mesh cache version {} is not supported, expected {}", v, VERSION),
+            MeshCacheError::Truncated => write!(f, "mesh cache file is truncated"),
+            MeshCacheError::InvalidIndex(i) => write!(f, "mesh cache index {} is past the end of the vertices", i),
+        }
+    }
+}
+
+/// A named mesh from a model file. The mesh is white, recolor it with Mesh::with_color
+pub struct CachedModel {
+    pub name: String,
+    /// Name of the material in the OBJ's material library
+    pub material: Option<String>,
+    pub mesh: Mesh,
+    pub bounds_min: glm::Vec3,
+    pub bounds_max: glm::Vec3,
+}
+
+impl CachedModel {
+    pub fn new(name: String, material: Option<String>, mesh: Mesh) -> Self {
+        let (bounds_min, bounds_max) = mesh.bounds();
+        Self {
+            name,
+            material,
+            mesh,
+            bounds_min,
+            bounds_max,
+        }
+    }
+}
+
+/// Where the cache of an OBJ file goes, right next to it
+pub fn cache_path(obj_path: &Path) -> PathBuf {
+    obj_path.with_extension("mesh")
+}
+
+/// Models from an OBJ file, read from its cache when that is newer than the OBJ. Otherwise the OBJ is parsed
+/// and the cache written for next time
+pub fn load_obj(path: &str) -> Result<Vec<CachedModel>, MeshCacheError> {
+    let cache = cache_path(Path::new(path));
+    if is_newer(&cache, Path::new(path)) {
+        match read(&cache) {
+            Ok(models) => return Ok(models),
+            Err(e) => eprintln!("Ignoring mesh cache {}, e: {}", cache.display(), e),
+        }
+    }
+
+    let models = parse_obj(path)?;
+    if let Err(e) = write(&cache, &models) {
+        eprintln!("Failed to write mesh cache {}, e: {}", cache.display(), e);
+    }
+    Ok(models)
+}
+
+/// Parses an OBJ and writes its cache, whether or not the cache is up to date
+pub fn convert(path: &str) -> Result<PathBuf, MeshCacheError> {
+    let cache = cache_path(Path::new(path));
+    write(&cache, &parse_obj(path)?)?;
+    Ok(cache)
+}
+
+pub fn parse_obj(path: &str) -> Result<Vec<CachedModel>, MeshCacheError> {
+    let file = File::open(path).map_err(MeshCacheError::Io)?;
+    let directory = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+    let (models, materials) = tobj::load_obj_buf(&mut BufReader::new(file), true, |library| {
+        // A missing material library only loses the material names
+        Ok(tobj::load_mtl(directory.join(library)).unwrap_or_default())
+    }).map_err(MeshCacheError::Obj)?;
+
+    Ok(models.into_iter().map(|model| {
+        let material = model.mesh.material_id.and_then(|id| materials.get(id)).map(|m| m.name.clone());
+        CachedModel::new(model.name, material, Mesh::from(model.mesh, [1.0, 1.0, 1.0, 1.0]))
+    }).collect())
+}
+
+fn is_newer(path: &Path, than: &Path) -> bool {
+    let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+    match (modified(path), modified(than)) {
+        (Some(path), Some(than)) => path >= than,
+        _ => false,
+    }
+}
+
+/// Writes a temporary file next to path and renames it over path, so a cache that is mapped by read is replaced
+/// instead of truncated and nobody sees a half written cache
+pub fn write(path: &Path, models: &[CachedModel]) -> Result<(), MeshCacheError> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    let temporary = PathBuf::from(temporary);
+
+    let result = File::create(&temporary)
+        .and_then(|file| {
+            let mut out = BufWriter::new(file);
+            write_models(&mut out, models)?;
+            out.flush()
+        })
+        .and_then(|_| fs::rename(&temporary, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temporary);
+    }
+    result.map_err(MeshCacheError::Io)
+}
+
+fn write_models(out: &mut impl Write, models: &[CachedModel]) -> io::Result<()> {
+    let write_string = |out: &mut dyn Write, string: Option<&str>| -> io::Result<()> {
+        match string {
+            Some(string) => {
+                out.write_all(&(string.len() as u32).to_le_bytes())?;
+                out.write_all(string.as_bytes())?;
+                out.write_all(&[0; 3][..padding(string.len())])
+            },
+            None => out.write_all(&u32::MAX.to_le_bytes()),
+        }
+    };
+
+    out.write_all(MAGIC)?;
+    out.write_all(&VERSION.to_le_bytes())?;
+    out.write_all(&(models.len() as u32).to_le_bytes())?;
+    for model in models {
+        let mesh = &model.mesh;
+        let count = mesh.vertices.len() / 3;
+        // Streams without an entry for every vertex are left out
+        let streams: Vec<(&[f32], usize, u32)> = vec![(&mesh.normals[..], 3, HAS_NORMALS), (&mesh.uvs[..], 2, HAS_UVS), (&mesh.tangents[..], 4, HAS_TANGENTS)]
+            .into_iter()
+            .filter(|(stream, size, _)| count > 0 && stream.len() == count * size)
+            .collect();
+        let flags = streams.iter().fold(0, |flags, (_, _, flag)| flags | flag);
+
+        write_string(out, Some(&model.name))?;
+        write_string(out, model.material.as_deref())?;
+        for value in &[flags, count as u32, mesh.indices.len() as u32] {
+            out.write_all(&value.to_le_bytes())?;
+        }
+        for value in model.bounds_min.iter().chain(model.bounds_max.iter()) {
+            out.write_all(&value.to_le_bytes())?;
+        }
+
+        for vertex in 0..count {
+            let attributes = std::iter::once((&mesh.vertices[..], 3)).chain(streams.iter().map(|&(stream, size, _)| (stream, size)));
+            for (stream, size) in attributes {
+                for value in &stream[vertex * size..(vertex + 1) * size] {
+                    out.write_all(&value.to_le_bytes())?;
+                }
+            }
+        }
+        for index in &mesh.indices {
+            out.write_all(&index.to_le_bytes())?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads a cache file through a memory map, the models are parsed straight from the mapped file without copying it
+/// into a buffer first
+pub fn read(path: &Path) -> Result<Vec<CachedModel>, MeshCacheError> {
+    let file = File::open(path).map_err(MeshCacheError::Io)?;
+    // Safety: the file must not be truncated while it is mapped, reading past its new end raises SIGBUS. Caches are
+    // only replaced by renaming a new file over them, which leaves this mapping on the old file
+    let map = unsafe { Mmap::map(&file) }.map_err(MeshCacheError::Io)?;
+    let mut reader = Reader { bytes: &map[..] };
+
+    if reader.take(4)? != MAGIC {
+        return Err(MeshCacheError::NotAMeshFile);
+    }
+    let version = reader.u32()?;
+    if version != VERSION {
+        return Err(MeshCacheError::UnsupportedVersion(version));
+    }
+
+    let model_count = reader.u32()?;
+    let mut models = Vec::new();
+    for _ in 0..model_count {
+        let name = reader.string()?.unwrap_or_default();
+        let material = reader.string()?;
+        let (flags, count, index_count) = (reader.u32()?, reader.u32()? as usize, reader.u32()? as usize);
+        let bounds_min = glm::vec3(reader.f32()?, reader.f32()?, reader.f32()?);
+        let bounds_max = glm::vec3(reader.f32()?, reader.f32()?, reader.f32()?);
+
+        let streams: Vec<usize> = [(HAS_NORMALS, 3), (HAS_UVS, 2), (HAS_TANGENTS, 4)].iter()
+            .map(|&(flag, size)| if flags & flag != 0 { size } else { 0 })
+            .collect();
+        let stride = 3 + streams.iter().sum::<usize>();
+
+        // Both sizes come from the file, so they are checked against what is left before anything is allocated
+        let vertex_bytes = count.checked_mul(stride * 4).ok_or(MeshCacheError::Truncated)?;
+        let index_bytes = index_count.checked_mul(4).ok_or(MeshCacheError::Truncated)?;
+        let vertex_data = reader.take(vertex_bytes)?;
+        let index_data = reader.take(index_bytes)?;
+
+        let mut mesh = Mesh {
+            vertices: Vec::with_capacity(count * 3),
+            normals: Vec::with_capacity(count * streams[0]),
+            uvs: Vec::with_capacity(count * streams[1]),
+            tangents: Vec::with_capacity(count * streams[2]),
+            colors: vec![1.0; count * 4],
+            indices: Vec::with_capacity(index_count),
+            index_count: index_count as i32,
+        };
+        for vertex in vertex_data.chunks_exact(stride * 4) {
+            let mut floats = vertex.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+            mesh.vertices.extend(floats.by_ref().take(3));
+            mesh.normals.extend(floats.by_ref().take(streams[0]));
+            mesh.uvs.extend(floats.by_ref().take(streams[1]));
+            mesh.tangents.extend(floats.take(streams[2]));
+        }
+        for bytes in index_data.chunks_exact(4) {
+            let index = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+            if index as usize >= count {
+                return Err(MeshCacheError::InvalidIndex(index));
+            }
+            mesh.indices.push(index);
+        }
+
+        models.push(CachedModel {
+            name,
+            material,
+            mesh,
+            bounds_min,
+            bounds_max,
+        });
+    }
+
+    Ok(models)
+}
+
+/// Bytes after a string of length to reach a multiple of 4
+fn padding(length: usize) -> usize {
+    (4 - length % 4) % 4
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], MeshCacheError> {
+        if self.bytes.len() < count {
+            return Err(MeshCacheError::Truncated);
+        }
+        let (taken, rest) = self.bytes.split_at(count);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u32(&mut self) -> Result<u32, MeshCacheError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn f32(&mut self) -> Result<f32, MeshCacheError> {
+        self.u32().map(f32::from_bits)
+    }
+
+    fn string(&mut self) -> Result<Option<String>, MeshCacheError> {
+        let length = self.u32()?;
+        if length == u32::MAX {
+            return Ok(None);
+        }
+        let bytes = self.take(length as usize)?;
+        self.take(padding(length as usize))?;
+        Ok(Some(String::from_utf8_lossy(bytes).into_owned()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gl_utils::primitives;
+
+    #[test]
+    fn written_models_read_back_the_same() {
+        let models = vec![
+            CachedModel::new("sphere".to_string(), Some("metal".to_string()), primitives::uv_sphere(2.0, 8, 4)),
+            CachedModel::new("bare".to_string(), None, Mesh {
+                vertices: vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0],
+                normals: Vec::new(),
+                uvs: Vec::new(),
+                tangents: Vec::new(),
+                colors: vec![1.0; 12],
+                indices: vec![0, 1, 2],
+                index_count: 3,
+            }),
+        ];
+        let path = std::env::temp_dir().join(format!("gloom-mesh-cache-{}.mesh", std::process::id()));
+        write(&path, &models).unwrap();
+        assert!(!path.with_extension("mesh.tmp").exists());
+        let read_back = read(&path);
+        fs::remove_file(&path).unwrap();
+        let read_back = read_back.unwrap();
+
+        assert_eq!(read_back.len(), models.len());
+        for (read, written) in read_back.iter().zip(&models) {
+            assert_eq!(read.name, written.name);
+            assert_eq!(read.material, written.material);
+            assert_eq!(read.bounds_min, written.bounds_min);
+            assert_eq!(read.bounds_max, written.bounds_max);
+            assert_eq!(read.mesh.vertices, written.mesh.vertices);
+            assert_eq!(read.mesh.normals, written.mesh.normals);
+            assert_eq!(read.mesh.uvs, written.mesh.uvs);
+            assert_eq!(read.mesh.tangents, written.mesh.tangents);
+            assert_eq!(read.mesh.indices, written.mesh.indices);
+            assert_eq!(read.mesh.index_count, written.mesh.index_count);
+        }
+    }
+
+    /// Writes bytes to a temporary file and reads it as a cache
+    fn read_bytes(name: &str, bytes: &[u8]) -> Result<Vec<CachedModel>, MeshCacheError> {
+        let path = std::env::temp_dir().join(format!("gloom-mesh-cache-{}-{}.mesh", name, std::process::id()));
+        fs::write(&path, bytes).unwrap();
+        let result = read(&path);
+        fs::remove_file(&path).unwrap();
+        result
+    }
+
+    #[test]
+    fn vertex_count_past_the_end_of_the_file_is_truncated() {
+        let mut bytes = Vec::new();
+        write_models(&mut bytes, &[CachedModel::new("plane".to_string(), None, primitives::plane(1.0, 1.0, 1, 1))]).unwrap();
+        bytes.truncate(bytes.len() - 4);
+        assert!(matches!(read_bytes("truncated", &bytes), Err(MeshCacheError::Truncated)));
+    }
+
+    #[test]
+    fn huge_vertex_count_is_truncated_instead_of_allocated() {
+        let mut bytes = Vec::new();
+        write_models(&mut bytes, &[CachedModel::new("plane".to_string(), None, primitives::plane(1.0, 1.0, 1, 1))]).unwrap();
+        // After the header, the padded name "plane" and the missing material come the flags and the vertex count
+        bytes[32..36].copy_from_slice(&0x4000_0000u32.to_le_bytes());
+        assert!(matches!(read_bytes("huge", &bytes), Err(MeshCacheError::Truncated)));
+    }
+
+    #[test]
+    fn out_of_range_index_is_an_error() {
+        let mut mesh = primitives::plane(1.0, 1.0, 1, 1);
+        mesh.indices[0] = 4;
+        let mut bytes = Vec::new();
+        write_models(&mut bytes, &[CachedModel::new("plane".to_string(), None, mesh)]).unwrap();
+
+        assert!(matches!(read_bytes("index", &bytes), Err(MeshCacheError::InvalidIndex(4))));
+    }
+}
@@ -0,0 +1,617 @@
+extern crate nalgebra_glm as glm;
+
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap},
+    fmt,
+};
+
+use super::mesh::Mesh;
+
+#[derive(Debug)]
+pub enum MeshProcessingError {
+    /// Tangents follow the direction of the uvs
+    MissingUvs,
+    MissingNormals,
+}
+
+impl fmt::Display for MeshProcessingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MeshProcessingError::MissingUvs => write!(f, "mesh has no uvs"),
+            MeshProcessingError::MissingNormals => write!(f, "mesh has no normals"),
+        }
+    }
+}
+
+/// Vertices in the simulated post transform cache, typical for desktop GPUs
+const CACHE_SIZE: usize = 32;
+
+// Processing that rebuilds the vertex streams. Streams that are empty, or don't have an entry for every vertex, are left alone
+impl Mesh {
+    pub fn vertex_count(&self) -> usize {
+        self.vertices.len() / 3
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    /// Corner positions of every triangle, for building CPU side query structures
+    pub fn triangles(&self) -> impl Iterator<Item = [glm::Vec3; 3]> + '_ {
+        (0..self.triangle_count()).map(move |t| self.triangle(t).map(|i| self.position(i)))
+    }
+
+    /// Smallest and largest corner of the box around the vertices, both zero for an empty mesh
+    pub fn bounds(&self) -> (glm::Vec3, glm::Vec3) {
+        let mut positions = (0..self.vertex_count() as u32).map(|i| self.position(i));
+        let first = match positions.next() {
+            Some(first) => first,
+            None => return (glm::zero(), glm::zero()),
+        };
+        positions.fold((first, first), |(min, max), p| (glm::min2(&min, &p), glm::max2(&max, &p)))
+    }
+
+    fn position(&self, i: u32) -> glm::Vec3 {
+        let i = i as usize * 3;
+        glm::vec3(self.vertices[i], self.vertices[i + 1], self.vertices[i + 2])
+    }
+
+    fn triangle(&self, t: usize) -> [u32; 3] {
+        [self.indices[t * 3], self.indices[t * 3 + 1], self.indices[t * 3 + 2]]
+    }
+
+    /// Rebuilds every stream so new vertex i is a copy of old vertex order[i]. The indices are left as they are
+    fn reorder_vertices(&mut self, order: &[u32]) {
+        let count = self.vertex_count();
+        let streams = [(&mut self.normals, 3), (&mut self.uvs, 2), (&mut self.tangents, 4), (&mut self.colors, 4), (&mut self.vertices, 3)];
+        for (stream, size) in IntoIterator::into_iter(streams) {
+            if stream.len() == count * size {
+                *stream = order.iter().flat_map(|&i| stream[i as usize * size..(i as usize + 1) * size].iter().cloned()).collect();
+            }
+        }
+    }
+
+    /// Gives every triangle corner its own vertex
+    pub fn unweld(&mut self) {
+        let order = self.indices.clone();
+        self.reorder_vertices(&order);
+        self.indices = (0..order.len() as u32).collect();
+        self.index_count = self.indices.len() as i32;
+    }
+
+    /// Drops vertices no triangle uses and orders the rest by first use, which helps the pre transform cache
+    pub fn remove_unused_vertices(&mut self) {
+        let mut remap = vec![u32::MAX; self.vertex_count()];
+        let mut order = Vec::new();
+        for index in self.indices.iter_mut() {
+            if remap[*index as usize] == u32::MAX {
+                remap[*index as usize] = order.len() as u32;
+                order.push(*index);
+            }
+            *index = remap[*index as usize];
+        }
+        self.reorder_vertices(&order);
+    }
+
+    /// Merges vertices that are identical in every stream
+    pub fn deduplicate(&mut self) {
+        let count = self.vertex_count();
+        let streams = [(&self.vertices, 3), (&self.normals, 3), (&self.uvs, 2), (&self.tangents, 4), (&self.colors, 4)];
+        let streams: Vec<_> = IntoIterator::into_iter(streams).filter(|(stream, size)| stream.len() == count * size).collect();
+
+        let mut unique = HashMap::new();
+        let mut remap = Vec::with_capacity(count);
+        for vertex in 0..count {
+            // -0.0 and 0.0 are the same vertex
+            let key: Vec<u32> = streams.iter()
+                .flat_map(|(stream, size)| stream[vertex * size..(vertex + 1) * size].iter().map(|v| (v + 0.0).to_bits()))
+                .collect();
+            remap.push(*unique.entry(key).or_insert(vertex as u32));
+        }
+
+        self.indices.iter_mut().for_each(|i| *i = remap[*i as usize]);
+        self.remove_unused_vertices();
+    }
+
+    /// Merges vertices closer than epsilon, keeping the attributes of the first one. Triangles that collapse are removed.
+    /// Unlike deduplicate this also closes uv and normal seams
+    #[allow(dead_code)]
+    pub fn weld(&mut self, epsilon: f32) {
+        let epsilon = epsilon.max(f32::MIN_POSITIVE);
+        let cell = |p: &glm::Vec3| ((p.x / epsilon).floor() as i64, (p.y / epsilon).floor() as i64, (p.z / epsilon).floor() as i64);
+
+        let mut grid: HashMap<(i64, i64, i64), Vec<u32>> = HashMap::new();
+        let mut remap = Vec::with_capacity(self.vertex_count());
+        for vertex in 0..self.vertex_count() as u32 {
+            let p = self.position(vertex);
+            let (x, y, z) = cell(&p);
+            // Close vertices can be in a neighbouring cell
+            let existing = (-1..=1).flat_map(|dx| (-1..=1).flat_map(move |dy| (-1..=1).map(move |dz| (x + dx, y + dy, z + dz))))
+                .filter_map(|key| grid.get(&key))
+                .flatten()
+                .find(|&&other| glm::distance(&self.position(other), &p) <= epsilon)
+                .cloned();
+            match existing {
+                Some(other) => remap.push(other),
+                None => {
+                    grid.entry((x, y, z)).or_default().push(vertex);
+                    remap.push(vertex);
+                },
+            }
+        }
+
+        let indices = self.indices.chunks(3)
+            .map(|t| [remap[t[0] as usize], remap[t[1] as usize], remap[t[2] as usize]])
+            .filter(|t| t[0] != t[1] && t[1] != t[2] && t[2] != t[0])
+            .flat_map(IntoIterator::into_iter)
+            .collect();
+        self.indices = indices;
+        self.index_count = self.indices.len() as i32;
+        self.remove_unused_vertices();
+    }
+
+    /// One normal per triangle, every corner gets its own vertex
+    #[allow(dead_code)]
+    pub fn compute_flat_normals(&mut self) {
+        self.unweld();
+        self.normals = (0..self.triangle_count()).flat_map(|t| {
+            let n = face_normal(&self.triangle(t).map(|i| self.position(i)));
+            IntoIterator::into_iter([n; 3]).flat_map(|n| IntoIterator::into_iter([n.x, n.y, n.z]))
+        }).collect();
+    }
+
+    /// Averages the normals of triangles meeting at a position, weighted by their angle at it. Triangles facing more than
+    /// crease_angle (in radians) apart don't blend, so vertices are split along hard edges and joined everywhere else
+    pub fn compute_smooth_normals(&mut self, crease_angle: f32) {
+        let min_cos = crease_angle.cos();
+        let triangles: Vec<[glm::Vec3; 3]> = (0..self.triangle_count()).map(|t| self.triangle(t).map(|i| self.position(i))).collect();
+        let face_normals: Vec<glm::Vec3> = triangles.iter().map(face_normal).collect();
+
+        // Corners sharing a position, whichever vertex they use
+        let mut corners_at: HashMap<[u32; 3], Vec<(usize, f32)>> = HashMap::new();
+        for (t, corners) in triangles.iter().enumerate() {
+            for c in 0..3 {
+                let angle = corner_angle(&corners[c], &corners[(c + 1) % 3], &corners[(c + 2) % 3]);
+                corners_at.entry(position_key(&corners[c])).or_default().push((t, angle));
+            }
+        }
+
+        let mut normals = Vec::with_capacity(triangles.len() * 9);
+        for (t, corners) in triangles.iter().enumerate() {
+            for corner in corners {
+                let sum = corners_at[&position_key(corner)].iter()
+                    .filter(|(other, _)| glm::dot(&face_normals[t], &face_normals[*other]) >= min_cos)
+                    .fold(glm::Vec3::zeros(), |sum, (other, angle)| sum + face_normals[*other] * *angle);
+                let n = if glm::length(&sum) > 1e-12 { glm::normalize(&sum) } else { face_normals[t] };
+                normals.extend_from_slice(&[n.x, n.y, n.z]);
+            }
+        }
+
+        self.unweld();
+        self.normals = normals;
+        self.deduplicate();
+    }
+
+    /// Per vertex tangents in the MikkTSpace convention: xyz points along increasing u and w is the handedness, so the
+    /// bitangent is cross(normal, tangent.xyz) * tangent.w. Triangles are weighted by their corner angle. Vertices are
+    /// shared as given, where MikkTSpace would split one shared by triangles with mirrored uvs
+    #[allow(dead_code)]
+    pub fn compute_tangents(&mut self) -> Result<(), MeshProcessingError> {
+        let count = self.vertex_count();
+        if self.uvs.len() != count * 2 {
+            return Err(MeshProcessingError::MissingUvs);
+        }
+        if self.normals.len() != count * 3 {
+            return Err(MeshProcessingError::MissingNormals);
+        }
+
+        let uv = |i: u32| glm::vec2(self.uvs[i as usize * 2], self.uvs[i as usize * 2 + 1]);
+        let mut tangents = vec![glm::Vec3::zeros(); count];
+        let mut bitangents = vec![glm::Vec3::zeros(); count];
+        for t in 0..self.triangle_count() {
+            let triangle = self.triangle(t);
+            let p = triangle.map(|i| self.position(i));
+            let (e1, e2) = (p[1] - p[0], p[2] - p[0]);
+            let (d1, d2) = (uv(triangle[1]) - uv(triangle[0]), uv(triangle[2]) - uv(triangle[0]));
+            let det = d1.x * d2.y - d2.x * d1.y;
+            if det.abs() < 1e-12 {
+                continue;
+            }
+            let tangent = (e1 * d2.y - e2 * d1.y) / det;
+            let bitangent = (e2 * d1.x - e1 * d2.x) / det;
+
+            for c in 0..3 {
+                let angle = corner_angle(&p[c], &p[(c + 1) % 3], &p[(c + 2) % 3]);
+                tangents[triangle[c] as usize] += tangent * angle;
+                bitangents[triangle[c] as usize] += bitangent * angle;
+            }
+        }
+
+        self.tangents = Vec::with_capacity(count * 4);
+        for vertex in 0..count {
+            let n = glm::vec3(self.normals[vertex * 3], self.normals[vertex * 3 + 1], self.normals[vertex * 3 + 2]);
+            // Gram-Schmidt, with any direction along the surface where the uvs give none
+            let mut t = tangents[vertex] - n * glm::dot(&n, &tangents[vertex]);
+            if glm::length(&t) < 1e-12 {
+                let axis = if n.x.abs() < 0.9 { glm::vec3(1.0, 0.0, 0.0) } else { glm::vec3(0.0, 1.0, 0.0) };
+                t = axis - n * glm::dot(&n, &axis);
+            }
+            let t = glm::normalize(&t);
+            let w = if glm::dot(&glm::cross(&n, &t), &bitangents[vertex]) < 0.0 { -1.0 } else { 1.0 };
+            self.tangents.extend_from_slice(&[t.x, t.y, t.z, w]);
+        }
+
+        Ok(())
+    }
+
+    /// Reorders triangles so vertices are reused while still in the post transform cache (Forsyth's algorithm),
+    /// then orders the vertices by first use
+    #[allow(dead_code)]
+    pub fn optimize_vertex_cache(&mut self) {
+        let triangle_count = self.triangle_count();
+        let mut vertex_triangles = vec![Vec::new(); self.vertex_count()];
+        for t in 0..triangle_count {
+            for &i in &self.triangle(t) {
+                vertex_triangles[i as usize].push(t);
+            }
+        }
+
+        // Vertices used by a few remaining triangles score higher, so no lonely triangles are left behind
+        let vertex_score = |cache_position: Option<usize>, remaining: usize| {
+            if remaining == 0 {
+                return -1.0;
+            }
+            let cache = match cache_position {
+                // The last triangle's vertices are scored lower, favouring strips that turn
+                Some(position) if position < 3 => 0.75,
+                Some(position) => (1.0 - (position - 3) as f32 / (CACHE_SIZE - 3) as f32).powf(1.5),
+                None => 0.0,
+            };
+            cache + 2.0 / (remaining as f32).sqrt()
+        };
+
+        let mut cache_position: Vec<Option<usize>> = vec![None; self.vertex_count()];
+        let mut scores: Vec<f32> = vertex_triangles.iter().map(|triangles| vertex_score(None, triangles.len())).collect();
+        let triangle_score = |t: usize, scores: &[f32], indices: &[u32]| indices[t * 3..t * 3 + 3].iter().map(|&i| scores[i as usize]).sum::<f32>();
+        let mut emitted = vec![false; triangle_count];
+        let mut cache: Vec<u32> = Vec::new();
+        let mut indices = Vec::with_capacity(self.indices.len());
+        let mut next_unemitted = 0;
+        let mut best = (0..triangle_count).max_by(|&a, &b| {
+            triangle_score(a, &scores, &self.indices).total_cmp(&triangle_score(b, &scores, &self.indices))
+        });
+
+        while indices.len() < self.indices.len() {
+            // Nothing in the cache has triangles left, start over somewhere else
+            let t = match best {
+                Some(t) => t,
+                None => {
+                    while emitted[next_unemitted] {
+                        next_unemitted += 1;
+                    }
+                    next_unemitted
+                },
+            };
+            let triangle = self.triangle(t);
+            emitted[t] = true;
+            indices.extend_from_slice(&triangle);
+            for &i in &triangle {
+                vertex_triangles[i as usize].retain(|&other| other != t);
+            }
+
+            let mut new_cache: Vec<u32> = triangle.to_vec();
+            new_cache.extend(cache.iter().filter(|i| !triangle.contains(i)));
+            for &i in new_cache.iter().skip(CACHE_SIZE) {
+                cache_position[i as usize] = None;
+                scores[i as usize] = vertex_score(None, vertex_triangles[i as usize].len());
+            }
+            new_cache.truncate(CACHE_SIZE);
+            for (position, &i) in new_cache.iter().enumerate() {
+                cache_position[i as usize] = Some(position);
+                scores[i as usize] = vertex_score(Some(position), vertex_triangles[i as usize].len());
+            }
+            cache = new_cache;
+
+            best = cache.iter()
+                .flat_map(|&i| vertex_triangles[i as usize].iter().cloned())
+                .max_by(|&a, &b| triangle_score(a, &scores, &self.indices).total_cmp(&triangle_score(b, &scores, &self.indices)));
+        }
+
+        self.indices = indices;
+        self.remove_unused_vertices();
+    }
+
+    /// A copy with about target_triangles triangles for a lower level of detail, made by collapsing the edges that move
+    /// the surface the least (quadric error metrics). Vertices on uv or normal seams and on open borders stay put or
+    /// only slide along them. Weld or deduplicate first, an unwelded mesh has nothing to collapse
+    #[allow(dead_code)]
+    pub fn simplified(&self, target_triangles: usize) -> Mesh {
+        let count = self.vertex_count();
+
+        // Vertices at the same position form a group, only vertices alone in their group can move
+        let mut group_ids = HashMap::new();
+        let group: Vec<usize> = (0..count as u32).map(|i| {
+            let next = group_ids.len();
+            *group_ids.entry(position_key(&self.position(i))).or_insert(next)
+        }).collect();
+        let mut group_sizes = vec![0; group_ids.len()];
+        group.iter().for_each(|&g| group_sizes[g] += 1);
+        let movable: Vec<bool> = group.iter().map(|&g| group_sizes[g] == 1).collect();
+
+        let mut triangles: Vec<[u32; 3]> = (0..self.triangle_count()).map(|t| self.triangle(t)).collect();
+        let mut quadrics = vec![Quadric::default(); group_ids.len()];
+        let mut edge_uses: HashMap<(usize, usize), (usize, u32, u32)> = HashMap::new();
+        for (t, triangle) in triangles.iter().enumerate() {
+            let p = triangle.map(|i| self.position(i));
+            let cross = glm::cross(&(p[1] - p[0]), &(p[2] - p[0]));
+            let area = glm::length(&cross) * 0.5;
+            if area > 0.0 {
+                let plane = Quadric::plane(&(cross / (area * 2.0)), &p[0], area);
+                triangle.iter().for_each(|&i| quadrics[group[i as usize]].add(&plane));
+            }
+            for c in 0..3 {
+                let (a, b) = (triangle[c], triangle[(c + 1) % 3]);
+                let key = (group[a as usize].min(group[b as usize]), group[a as usize].max(group[b as usize]));
+                // Edges used more than once are marked with usize::MAX
+                match edge_uses.get_mut(&key) {
+                    Some(uses) => uses.0 = usize::MAX,
+                    None => {
+                        edge_uses.insert(key, (t, a, b));
+                    },
+                }
+            }
+        }
+        // A steep plane through every open edge keeps borders from shrinking
+        for &(t, a, b) in edge_uses.values().filter(|(t, _, _)| *t != usize::MAX) {
+            let p = triangles[t].map(|i| self.position(i));
+            let (pa, pb) = (self.position(a), self.position(b));
+            let edge = pb - pa;
+            let side = glm::cross(&edge, &face_normal(&p));
+            if glm::length(&side) > 0.0 {
+                let plane = Quadric::plane(&glm::normalize(&side), &pa, glm::length2(&edge) * 10.0);
+                quadrics[group[a as usize]].add(&plane);
+                quadrics[group[b as usize]].add(&plane);
+            }
+        }
+
+        let mut vertex_triangles = vec![Vec::new(); count];
+        for (t, triangle) in triangles.iter().enumerate() {
+            triangle.iter().for_each(|&i| vertex_triangles[i as usize].push(t));
+        }
+        let original_normals: Vec<glm::Vec3> = triangles.iter().map(|triangle| face_normal(&triangle.map(|i| self.position(i)))).collect();
+        let mut alive = vec![true; triangles.len()];
+        let mut alive_count = triangles.len();
+        let mut versions = vec![0u32; count];
+
+        let mut heap = BinaryHeap::new();
+        let push = |heap: &mut BinaryHeap<Collapse>, quadrics: &[Quadric], versions: &[u32], from: u32, to: u32| {
+            if movable[from as usize] {
+                let quadric = quadrics[group[from as usize]].sum(&quadrics[group[to as usize]]);
+                heap.push(Collapse {
+                    cost: quadric.error(&self.position(to)),
+                    from,
+                    to,
+                    versions: (versions[from as usize], versions[to as usize]),
+                });
+            }
+        };
+        for triangle in &triangles {
+            for c in 0..3 {
+                let (a, b) = (triangle[c], triangle[(c + 1) % 3]);
+                push(&mut heap, &quadrics, &versions, a, b);
+                push(&mut heap, &quadrics, &versions, b, a);
+            }
+        }
+
+        while alive_count > target_triangles {
+            let collapse = match heap.pop() {
+                Some(collapse) => collapse,
+                None => break,
+            };
+            let (from, to) = (collapse.from as usize, collapse.to as usize);
+            if collapse.versions != (versions[from], versions[to]) {
+                continue;
+            }
+            let around: Vec<usize> = vertex_triangles[from].iter().cloned().filter(|&t| alive[t]).collect();
+            // Triangles along the collapsed edge disappear
+            let removed: Vec<bool> = around.iter().map(|&t| triangles[t].iter().any(|&i| group[i as usize] == group[to])).collect();
+            if !removed.contains(&true) {
+                continue;
+            }
+            // Moving the vertex must not turn any remaining triangle over. Comparing with the original normal stops
+            // triangles from turning a bit with every collapse until they stand on edge
+            let target = self.position(to as u32);
+            let flips = around.iter().zip(&removed).filter(|(_, &removed)| !removed).any(|(&t, _)| {
+                let after = triangles[t].map(|i| if i as usize == from { target } else { self.position(i) });
+                glm::dot(&original_normals[t], &face_normal(&after)) < 0.5
+            });
+            if flips {
+                continue;
+            }
+
+            for (&t, removed) in around.iter().zip(removed) {
+                if removed {
+                    alive[t] = false;
+                    alive_count -= 1;
+                } else {
+                    triangles[t].iter_mut().filter(|i| **i as usize == from).for_each(|i| *i = to as u32);
+                    vertex_triangles[to].push(t);
+                }
+            }
+            let merged = quadrics[group[from]].sum(&quadrics[group[to]]);
+            quadrics[group[to]] = merged;
+            versions[from] += 1;
+            versions[to] += 1;
+
+            let neighbours: Vec<u32> = vertex_triangles[to].iter().filter(|&&t| alive[t]).flat_map(|&t| IntoIterator::into_iter(triangles[t])).filter(|&i| i as usize != to).collect();
+            for neighbour in neighbours {
+                push(&mut heap, &quadrics, &versions, to as u32, neighbour);
+                push(&mut heap, &quadrics, &versions, neighbour, to as u32);
+            }
+        }
+
+        let mut mesh = Mesh {
+            vertices: self.vertices.clone(),
+            normals: self.normals.clone(),
+            uvs: self.uvs.clone(),
+            tangents: self.tangents.clone(),
+            colors: self.colors.clone(),
+            indices: triangles.iter().zip(&alive).filter(|(_, &alive)| alive).flat_map(|(t, _)| t.iter().cloned()).collect(),
+            index_count: 0,
+        };
+        mesh.index_count = mesh.indices.len() as i32;
+        mesh.remove_unused_vertices();
+        mesh
+    }
+}
+
+fn face_normal(p: &[glm::Vec3; 3]) -> glm::Vec3 {
+    let n = glm::cross(&(p[1] - p[0]), &(p[2] - p[0]));
+    if glm::length(&n) > 0.0 { glm::normalize(&n) } else { glm::vec3(0.0, 1.0, 0.0) }
+}
+
+/// Angle at corner between the edges to a and b
+fn corner_angle(corner: &glm::Vec3, a: &glm::Vec3, b: &glm::Vec3) -> f32 {
+    let (ea, eb) = (a - corner, b - corner);
+    if glm::length(&ea) == 0.0 || glm::length(&eb) == 0.0 {
+        return 0.0;
+    }
+    glm::angle(&ea, &eb)
+}
+
+fn position_key(p: &glm::Vec3) -> [u32; 3] {
+    [(p.x + 0.0).to_bits(), (p.y + 0.0).to_bits(), (p.z + 0.0).to_bits()]
+}
+
+/// Sum of squared distances to a set of planes, the upper half of a symmetric 4x4 matrix
+#[derive(Clone, Copy, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    fn plane(normal: &glm::Vec3, point: &glm::Vec3, weight: f32) -> Self {
+        let (a, b, c) = (normal.x as f64, normal.y as f64, normal.z as f64);
+        let d = -glm::dot(normal, point) as f64;
+        let w = weight as f64;
+        Self([a * a * w, a * b * w, a * c * w, a * d * w, b * b * w, b * c * w, b * d * w, c * c * w, c * d * w, d * d * w])
+    }
+
+    fn add(&mut self, other: &Quadric) {
+        self.0.iter_mut().zip(&other.0).for_each(|(a, b)| *a += b);
+    }
+
+    fn sum(&self, other: &Quadric) -> Quadric {
+        let mut sum = *self;
+        sum.add(other);
+        sum
+    }
+
+    fn error(&self, p: &glm::Vec3) -> f64 {
+        let (x, y, z) = (p.x as f64, p.y as f64, p.z as f64);
+        let q = &self.0;
+        q[0] * x * x + 2.0 * q[1] * x * y + 2.0 * q[2] * x * z + 2.0 * q[3] * x
+            + q[4] * y * y + 2.0 * q[5] * y * z + 2.0 * q[6] * y
+            + q[7] * z * z + 2.0 * q[8] * z
+            + q[9]
+    }
+}
+
+/// Moving from onto to, versions tell whether either vertex changed since the cost was computed
+struct Collapse {
+    cost: f64,
+    from: u32,
+    to: u32,
+    versions: (u32, u32),
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Collapse {}
+
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Collapse {
+    // Reversed, so the heap pops the cheapest collapse first
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.cost.total_cmp(&self.cost)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gl_utils::primitives;
+
+    /// Position and uv bits of every corner, with each triangle rotated to start at its smallest corner so the
+    /// winding is kept. Sorted, so two meshes with the same triangles in any order compare equal
+    fn triangle_set(mesh: &Mesh) -> Vec<[[u32; 5]; 3]> {
+        let corner = |i: u32| {
+            let (p, uv) = (mesh.position(i), (mesh.uvs[i as usize * 2], mesh.uvs[i as usize * 2 + 1]));
+            [p.x.to_bits(), p.y.to_bits(), p.z.to_bits(), uv.0.to_bits(), uv.1.to_bits()]
+        };
+        let mut triangles: Vec<_> = (0..mesh.triangle_count()).map(|t| {
+            let c = mesh.triangle(t).map(corner);
+            let first = (0..3).min_by_key(|&i| c[i]).unwrap();
+            [c[first], c[(first + 1) % 3], c[(first + 2) % 3]]
+        }).collect();
+        triangles.sort();
+        triangles
+    }
+
+    #[test]
+    fn weld_merges_unwelded_cube_into_eight_corners() {
+        let mut cube = primitives::cube(1.0, 1);
+        cube.unweld();
+        assert_eq!(cube.vertex_count(), 36);
+
+        cube.weld(1e-4);
+        assert_eq!(cube.vertex_count(), 8);
+        assert_eq!(cube.triangle_count(), 12);
+    }
+
+    #[test]
+    fn optimize_vertex_cache_keeps_the_triangles() {
+        let mut sphere = primitives::uv_sphere(1.0, 24, 12);
+        let before = triangle_set(&sphere);
+
+        sphere.optimize_vertex_cache();
+        assert_eq!(triangle_set(&sphere), before);
+    }
+
+    #[test]
+    fn simplified_meets_target_without_flipping_faces() {
+        let mut sphere = primitives::uv_sphere(1.0, 32, 16);
+        sphere.weld(1e-5);
+
+        let target = sphere.triangle_count() / 4;
+        let simple = sphere.simplified(target);
+        assert!(simple.triangle_count() > 0);
+        assert!(simple.triangle_count() <= target);
+
+        // Every face of a sphere looks away from its centre
+        for t in 0..simple.triangle_count() {
+            let p = simple.triangle(t).map(|i| simple.position(i));
+            let centroid = (p[0] + p[1] + p[2]) / 3.0;
+            assert!(glm::dot(&face_normal(&p), &centroid) > 0.0, "triangle {} is flipped", t);
+        }
+    }
+
+    #[test]
+    fn plane_tangents_follow_x() {
+        let mut plane = primitives::plane(2.0, 2.0, 2, 2);
+        plane.compute_tangents().unwrap();
+
+        for tangent in plane.tangents.chunks(4) {
+            assert!((tangent[0] - 1.0).abs() < 1e-5 && tangent[1].abs() < 1e-5 && tangent[2].abs() < 1e-5);
+            assert_eq!(tangent[3], 1.0);
+        }
+    }
+}
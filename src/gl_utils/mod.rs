@@ -5,5 +5,24 @@ pub mod helpers;
 pub mod shaders;
 pub mod camera;
 pub mod mesh;
+pub mod mesh_cache;
+pub mod mesh_processing;
+pub mod primitives;
+pub mod height_grid;
+pub mod heightmap;
+pub mod terrain_chunks;
+pub mod picking;
+pub mod collision;
 pub mod scene_graph;
+pub mod animation;
+pub mod path;
+pub mod parsing;
+pub mod render_queue;
+pub mod material;
+pub mod outline;
+pub mod profiler;
+pub mod debug_draw;
+pub mod stream_buffer;
+pub mod skybox;
+pub mod text;
 pub mod toolbox;
\ No newline at end of file
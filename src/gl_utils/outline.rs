@@ -0,0 +1,44 @@
+use gl;
+use gl::types::GLuint;
+
+use super::geometric_object::GeometricInstance;
+
+/// Draws an outline around a group of instances with the stencil buffer. Needs a context with a stencil buffer
+/// and a program that pushes vertices out along their normals, like assets/shaders/outline.vert
+pub fn draw_outlined(instances: &[&GeometricInstance], outline_program_id: GLuint) {
+    if instances.is_empty() {
+        return;
+    }
+
+    unsafe {
+        gl::Enable(gl::STENCIL_TEST);
+        gl::StencilMask(0xFF);
+
+        // Mark the whole silhouette, including parts hidden behind other geometry. Only the stencil is written
+        gl::StencilFunc(gl::ALWAYS, 1, 0xFF);
+        gl::StencilOp(gl::KEEP, gl::KEEP, gl::REPLACE);
+        gl::ColorMask(gl::FALSE, gl::FALSE, gl::FALSE, gl::FALSE);
+        gl::DepthMask(gl::FALSE);
+        gl::Disable(gl::DEPTH_TEST);
+    }
+    for instance in instances {
+        instance.draw();
+    }
+
+    // The grown copy only shows outside the marked pixels, and through anything in front of it
+    unsafe {
+        gl::ColorMask(gl::TRUE, gl::TRUE, gl::TRUE, gl::TRUE);
+        gl::StencilFunc(gl::NOTEQUAL, 1, 0xFF);
+        gl::StencilMask(0x00);
+    }
+    for instance in instances {
+        instance.draw_with_program(outline_program_id);
+    }
+
+    unsafe {
+        gl::Enable(gl::DEPTH_TEST);
+        gl::DepthMask(gl::TRUE);
+        gl::StencilMask(0xFF);
+        gl::Disable(gl::STENCIL_TEST);
+    }
+}
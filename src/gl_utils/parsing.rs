@@ -0,0 +1,30 @@
+use std::fmt;
+
+// Helpers shared by the line based text formats: animations, paths, bindings, gamepad scripts and input recordings
+
+/// A line of a text format with content on it
+pub struct Line<'a> {
+    /// Counted from 1, for error messages
+    pub number: usize,
+    /// Trimmed
+    pub text: &'a str,
+    /// Split on whitespace
+    pub words: Vec<&'a str>,
+}
+
+/// The lines of src that have content, blank lines and lines starting with # are skipped
+pub fn lines(src: &str) -> impl Iterator<Item = Line<'_>> {
+    src.lines().enumerate()
+        .map(|(i, text)| (i, text.trim()))
+        .filter(|(_, text)| !text.is_empty() && !text.starts_with('#'))
+        .map(|(i, text)| Line {
+            number: i + 1,
+            text,
+            words: text.split_whitespace().collect(),
+        })
+}
+
+/// The value in values whose Debug name is name, for parsing enums that list their variants in an ALL constant
+pub fn from_debug_name<T: fmt::Debug + Copy>(values: &[T], name: &str) -> Option<T> {
+    values.iter().find(|v| format!("{:?}", v) == name).cloned()
+}
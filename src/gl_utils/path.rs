@@ -0,0 +1,322 @@
+extern crate nalgebra_glm as glm;
+
+use std::{
+    fmt,
+    fs,
+    io,
+    rc::Rc,
+    str::FromStr
+};
+
+use super::{parsing, toolbox::Heading};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SplineKind {
+    /// Passes through every point
+    CatmullRom,
+    /// Cubic segments, every third point is on the curve and the two between are control points
+    Bezier,
+}
+
+impl SplineKind {
+    const ALL: [SplineKind; 2] = [SplineKind::CatmullRom, SplineKind::Bezier];
+}
+
+impl FromStr for SplineKind {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parsing::from_debug_name(&SplineKind::ALL, s).ok_or(())
+    }
+}
+
+pub enum PathError {
+    Io(io::Error),
+    Parse(usize, String),
+    /// Catmull-Rom needs 2 points, or 3 when closed. Bezier needs 3n + 1 points, or 3n when closed
+    PointCount(SplineKind, usize),
+    /// Every point is in the same place, distances along the path would divide by 0
+    ZeroLength,
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PathError::Io(e) => e.fmt(f),
+            PathError::Parse(line, content) => write!(f, "line {}: failed to parse '{}'", line, content),
+            PathError::PointCount(kind, count) => write!(f, "{} points can not form a {:?} spline", count, kind),
+            PathError::ZeroLength => write!(f, "path has zero length"),
+        }
+    }
+}
+
+pub struct Spline {
+    pub kind: SplineKind,
+    pub closed: bool,
+    points: Vec<glm::Vec3>,
+}
+
+impl Spline {
+    pub fn new(kind: SplineKind, closed: bool, points: Vec<glm::Vec3>) -> Result<Self, PathError> {
+        let count = points.len();
+        let valid = match (kind, closed) {
+            (SplineKind::CatmullRom, false) => count >= 2,
+            (SplineKind::CatmullRom, true) => count >= 3,
+            (SplineKind::Bezier, false) => count >= 4 && count % 3 == 1,
+            (SplineKind::Bezier, true) => count >= 3 && count % 3 == 0,
+        };
+        if !valid {
+            return Err(PathError::PointCount(kind, count));
+        }
+
+        Ok(Self {
+            kind,
+            closed,
+            points,
+        })
+    }
+
+    pub fn segment_count(&self) -> usize {
+        let n = self.points.len();
+        match (self.kind, self.closed) {
+            (SplineKind::CatmullRom, false) => n - 1,
+            (SplineKind::CatmullRom, true) => n,
+            (SplineKind::Bezier, false) => (n - 1) / 3,
+            (SplineKind::Bezier, true) => n / 3,
+        }
+    }
+
+    fn point(&self, i: isize) -> glm::Vec3 {
+        let n = self.points.len() as isize;
+        let i = if self.closed { i.rem_euclid(n) } else { i.clamp(0, n - 1) };
+        self.points[i as usize]
+    }
+
+    /// Position, first and second derivative at parameter u in [0, segment_count]
+    fn evaluate(&self, u: f32) -> (glm::Vec3, glm::Vec3, glm::Vec3) {
+        let segment = (u.max(0.0) as usize).min(self.segment_count() - 1);
+        let t = u - segment as f32;
+        let i = segment as isize;
+
+        match self.kind {
+            SplineKind::CatmullRom => {
+                let (p0, p1, p2, p3) = (self.point(i - 1), self.point(i), self.point(i + 1), self.point(i + 2));
+                let a = p1 * 2.0;
+                let b = p2 - p0;
+                let c = p0 * 2.0 - p1 * 5.0 + p2 * 4.0 - p3;
+                let d = -p0 + p1 * 3.0 - p2 * 3.0 + p3;
+                (
+                    (a + b * t + c * t * t + d * t * t * t) * 0.5,
+                    (b + c * 2.0 * t + d * 3.0 * t * t) * 0.5,
+                    (c * 2.0 + d * 6.0 * t) * 0.5,
+                )
+            },
+            SplineKind::Bezier => {
+                let (b0, b1, b2, b3) = (self.point(3 * i), self.point(3 * i + 1), self.point(3 * i + 2), self.point(3 * i + 3));
+                let s = 1.0 - t;
+                (
+                    b0 * (s * s * s) + b1 * (3.0 * s * s * t) + b2 * (3.0 * s * t * t) + b3 * (t * t * t),
+                    (b1 - b0) * (3.0 * s * s) + (b2 - b1) * (6.0 * s * t) + (b3 - b2) * (3.0 * t * t),
+                    (b2 - b1 * 2.0 + b0) * (6.0 * s) + (b3 - b2 * 2.0 + b1) * (6.0 * t),
+                )
+            },
+        }
+    }
+}
+
+/// Spline with an arc length table, so it can be traversed by distance instead of by spline parameter
+pub struct Path {
+    spline: Spline,
+    /// Distance along the path at every sample, samples are evenly spaced in the spline parameter
+    distances: Vec<f32>,
+}
+
+impl Path {
+    const SAMPLES_PER_SEGMENT: usize = 32;
+
+    pub fn new(spline: Spline) -> Result<Self, PathError> {
+        let samples = spline.segment_count() * Path::SAMPLES_PER_SEGMENT;
+        let mut distances = Vec::with_capacity(samples + 1);
+        distances.push(0.0);
+
+        let mut previous = spline.evaluate(0.0).0;
+        for k in 1..=samples {
+            let point = spline.evaluate(k as f32 / Path::SAMPLES_PER_SEGMENT as f32).0;
+            distances.push(distances[k - 1] + glm::distance(&previous, &point));
+            previous = point;
+        }
+
+        // Also catches NaN and infinite points, which parse as floats too
+        if !distances[samples].is_normal() {
+            return Err(PathError::ZeroLength);
+        }
+
+        Ok(Self {
+            spline,
+            distances,
+        })
+    }
+
+    /// Paths are text files, each line is one of
+    ///   kind <CatmullRom|Bezier>
+    ///   closed <true|false>
+    ///   <x> <y> <z>
+    /// Lines starting with # are ignored
+    pub fn load(path: &str) -> Result<Self, PathError> {
+        let src = fs::read_to_string(path).map_err(PathError::Io)?;
+        Self::parse(&src)
+    }
+
+    pub fn parse(src: &str) -> Result<Self, PathError> {
+        let mut kind = SplineKind::CatmullRom;
+        let mut closed = false;
+        let mut points = Vec::new();
+
+        for line in parsing::lines(src) {
+            let error = || PathError::Parse(line.number, line.text.to_string());
+            match line.words.as_slice() {
+                ["kind", k] => kind = k.parse().map_err(|_| error())?,
+                ["closed", c] => closed = c.parse().map_err(|_| error())?,
+                [x, y, z] => {
+                    let parse = |s: &str| s.parse::<f32>().map_err(|_| error());
+                    points.push(glm::vec3(parse(x)?, parse(y)?, parse(z)?));
+                },
+                _ => return Err(error()),
+            }
+        }
+
+        Path::new(Spline::new(kind, closed, points)?)
+    }
+
+    /// The lissajous figure eight the helicopters originally flew, as a closed Catmull-Rom spline. Panics when size is 0
+    pub fn figure_eight(size: f32) -> Self {
+        let count = 24;
+        let points = (0..count).map(|i| {
+            let angle = i as f32 / count as f32 * 2.0 * std::f32::consts::PI;
+            glm::vec3(size * (2.0 * angle).sin(), 0.0, 3.0 * size * angle.cos())
+        }).collect();
+
+        Path::new(Spline {
+            kind: SplineKind::CatmullRom,
+            closed: true,
+            points,
+        }).unwrap_or_else(|e| panic!("figure eight of size {}: {}", size, e))
+    }
+
+    pub fn length(&self) -> f32 {
+        *self.distances.last().unwrap_or(&0.0)
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.spline.closed
+    }
+
+    /// Spline parameter at a distance along the path, distances outside the path wrap when closed and clamp otherwise
+    fn parameter_at(&self, distance: f32) -> f32 {
+        let length = self.length();
+        let distance = if self.spline.closed { distance.rem_euclid(length) } else { distance.clamp(0.0, length) };
+
+        // First sample at or past distance, then interpolate linearly within the sample interval
+        let k = self.distances.partition_point(|&d| d < distance).clamp(1, self.distances.len() - 1);
+        let (d0, d1) = (self.distances[k - 1], self.distances[k]);
+        let fraction = if d1 > d0 { (distance - d0) / (d1 - d0) } else { 0.0 };
+
+        (k - 1) as f32 / Path::SAMPLES_PER_SEGMENT as f32 + fraction / Path::SAMPLES_PER_SEGMENT as f32
+    }
+
+    pub fn point_at(&self, distance: f32) -> glm::Vec3 {
+        self.spline.evaluate(self.parameter_at(distance)).0
+    }
+
+    /// Unit direction of travel at a distance along the path
+    pub fn tangent_at(&self, distance: f32) -> glm::Vec3 {
+        glm::normalize(&self.spline.evaluate(self.parameter_at(distance)).1)
+    }
+
+    /// Curvature of the path seen from above, positive when turning left (counter clockwise around +y)
+    pub fn horizontal_curvature_at(&self, distance: f32) -> f32 {
+        let (_, d1, d2) = self.spline.evaluate(self.parameter_at(distance));
+        let speed = glm::length(&glm::vec2(d1.x, d1.z));
+        if speed < 1e-5 {
+            return 0.0;
+        }
+
+        (d1.z * d2.x - d1.x * d2.z) / (speed * speed * speed)
+    }
+}
+
+/// Moves along a path at constant speed, closed paths are looped and open paths are flown back and forth
+pub struct PathFollower {
+    path: Rc<Path>,
+    pub distance: f32,
+    /// Units per second, negative flies the path backwards
+    pub speed: f32,
+    /// Added to every point of the path, lets several followers share one path
+    pub offset: glm::Vec3,
+    /// Largest bank angle in radians
+    pub max_bank: f32,
+}
+
+impl PathFollower {
+    const GRAVITY: f32 = 9.81;
+    // Nose down tilt per unit of speed, helicopters lean into the direction they fly
+    const FORWARD_TILT: f32 = 0.00875;
+
+    pub fn new(path: Rc<Path>, speed: f32) -> Self {
+        Self {
+            path,
+            distance: 0.0,
+            speed,
+            offset: glm::zero(),
+            max_bank: 0.6,
+        }
+    }
+
+    #[must_use]
+    pub fn start_at(mut self, distance: f32) -> Self {
+        self.distance = distance;
+        self
+    }
+
+    #[must_use]
+    pub fn offset(mut self, offset: glm::Vec3) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    pub fn update(&mut self, delta_time: f32) {
+        self.distance += self.speed * delta_time;
+
+        if self.path.is_closed() {
+            self.distance = self.distance.rem_euclid(self.path.length());
+        } else if self.distance < 0.0 || self.distance > self.path.length() {
+            self.distance = self.distance.clamp(0.0, self.path.length());
+            self.speed = -self.speed;
+        }
+    }
+
+    pub fn velocity(&self) -> glm::Vec3 {
+        self.path.tangent_at(self.distance) * self.speed
+    }
+
+    pub fn heading(&self) -> Heading {
+        let position = self.path.point_at(self.distance) + self.offset;
+        let direction = self.path.tangent_at(self.distance) * self.speed.signum();
+
+        // Bank like a coordinated turn, the lift has to supply the centripetal force v^2 * curvature
+        let curvature = self.path.horizontal_curvature_at(self.distance) * self.speed.signum();
+        let bank = (self.speed * self.speed * curvature / PathFollower::GRAVITY).atan();
+
+        let horizontal = glm::length(&glm::vec2(direction.x, direction.z));
+        let climb = direction.y.atan2(horizontal);
+
+        Heading {
+            x: position.x,
+            y: position.y,
+            z: position.z,
+            yaw: std::f32::consts::PI + direction.x.atan2(direction.z),
+            pitch: climb - PathFollower::FORWARD_TILT * self.speed.abs(),
+            roll: bank.clamp(-self.max_bank, self.max_bank),
+        }
+    }
+}
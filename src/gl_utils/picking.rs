@@ -0,0 +1,60 @@
+extern crate nalgebra_glm as glm;
+
+use super::{height_grid::ray_triangle, mesh::Mesh, scene_graph::SceneNode};
+
+/// CPU side copy of a mesh's triangles for ray casting, in the mesh's local space
+#[derive(Debug)]
+pub struct PickMesh {
+    triangles: Vec<[glm::Vec3; 3]>,
+    min: glm::Vec3,
+    max: glm::Vec3,
+}
+
+impl PickMesh {
+    pub fn from_mesh(mesh: &Mesh) -> Self {
+        let (min, max) = mesh.bounds();
+        Self {
+            triangles: mesh.triangles().collect(),
+            min,
+            max,
+        }
+    }
+
+    /// Distance along the ray to the bounding box, None if it misses. Slab test
+    fn intersect_bounds(&self, origin: &glm::Vec3, direction: &glm::Vec3) -> Option<f32> {
+        let (mut t_enter, mut t_exit) = (0.0f32, f32::MAX);
+        for axis in 0..3 {
+            if direction[axis].abs() < 1e-8 {
+                if origin[axis] < self.min[axis] || origin[axis] > self.max[axis] {
+                    return None;
+                }
+                continue;
+            }
+            let t0 = (self.min[axis] - origin[axis]) / direction[axis];
+            let t1 = (self.max[axis] - origin[axis]) / direction[axis];
+            t_enter = t_enter.max(t0.min(t1));
+            t_exit = t_exit.min(t0.max(t1));
+        }
+
+        if t_enter <= t_exit { Some(t_enter) } else { None }
+    }
+
+    /// Closest hit in local space, the ray is tested against the bounds before any triangles
+    pub fn intersect(&self, origin: &glm::Vec3, direction: &glm::Vec3) -> Option<f32> {
+        let direction = glm::normalize(direction);
+        self.intersect_bounds(origin, &direction)?;
+
+        self.triangles.iter()
+            .filter_map(|t| ray_triangle(origin, &direction, t))
+            .min_by(|a, b| a.total_cmp(b))
+    }
+}
+
+pub struct PickHit<'a> {
+    pub node: &'a SceneNode,
+    /// World space distance from the ray origin
+    pub distance: f32,
+    /// Slot of the node's GeometricInstance in its instance buffer
+    #[allow(dead_code)]
+    pub instance_index: usize,
+}
@@ -0,0 +1,399 @@
+extern crate nalgebra_glm as glm;
+
+use std::{collections::HashMap, f32::consts::PI};
+
+use super::mesh::Mesh;
+
+// Generated meshes for tests, debug visualisation and placeholders. All of them are white, centered on the origin,
+// wound counter clockwise seen from outside and have normals, uvs and tangents
+
+/// Collects vertices and indices, the tangents' w is always 1 since every shape keeps uvs right handed
+struct Builder {
+    vertices: Vec<f32>,
+    normals: Vec<f32>,
+    uvs: Vec<f32>,
+    tangents: Vec<f32>,
+    indices: Vec<u32>,
+}
+
+impl Builder {
+    fn new() -> Self {
+        Self {
+            vertices: Vec::new(),
+            normals: Vec::new(),
+            uvs: Vec::new(),
+            tangents: Vec::new(),
+            indices: Vec::new(),
+        }
+    }
+
+    fn vertex(&mut self, position: &glm::Vec3, normal: &glm::Vec3, uv: (f32, f32), tangent: &glm::Vec3) -> u32 {
+        let index = (self.vertices.len() / 3) as u32;
+        self.vertices.extend_from_slice(&[position.x, position.y, position.z]);
+        self.normals.extend_from_slice(&[normal.x, normal.y, normal.z]);
+        self.uvs.extend_from_slice(&[uv.0, uv.1]);
+        self.tangents.extend_from_slice(&[tangent.x, tangent.y, tangent.z, 1.0]);
+        index
+    }
+
+    /// Copy of the vertex at index, for seams where it needs different uvs
+    fn duplicate(&mut self, index: u32) -> u32 {
+        let i = index as usize;
+        let copy = (self.vertices.len() / 3) as u32;
+        self.vertices.extend_from_within(i * 3..i * 3 + 3);
+        self.normals.extend_from_within(i * 3..i * 3 + 3);
+        self.uvs.extend_from_within(i * 2..i * 2 + 2);
+        self.tangents.extend_from_within(i * 4..i * 4 + 4);
+        copy
+    }
+
+    /// Two triangles for a quad given counter clockwise
+    fn quad(&mut self, a: u32, b: u32, c: u32, d: u32) {
+        self.indices.extend_from_slice(&[a, b, c, a, c, d]);
+    }
+
+    /// Triangles between a grid of vertices starting at first, columns + 1 wide and row major.
+    /// Expects u to run along the rows and v along the columns, with u cross v pointing out
+    fn grid_indices(&mut self, first: u32, columns: u32, rows: u32) {
+        for row in 0..rows {
+            for column in 0..columns {
+                let i = first + row * (columns + 1) + column;
+                let above = i + columns + 1;
+                self.quad(i, i + 1, above + 1, above);
+            }
+        }
+    }
+
+    /// A flat rectangle from corner along u_axis and v_axis, u_axis cross v_axis is the facing direction
+    fn face(&mut self, corner: &glm::Vec3, u_axis: &glm::Vec3, v_axis: &glm::Vec3, columns: u32, rows: u32) {
+        let normal = glm::normalize(&glm::cross(u_axis, v_axis));
+        let tangent = glm::normalize(u_axis);
+        let first = (self.vertices.len() / 3) as u32;
+        for row in 0..=rows {
+            for column in 0..=columns {
+                let (u, v) = (column as f32 / columns as f32, row as f32 / rows as f32);
+                self.vertex(&(corner + u_axis * u + v_axis * v), &normal, (u, v), &tangent);
+            }
+        }
+        self.grid_indices(first, columns, rows);
+    }
+
+    /// Sweeps a profile around the y axis. Each profile point is a radius, a height, the normal in the
+    /// radius and height plane and its v coordinate, ordered from the bottom up
+    fn revolve(&mut self, profile: &[(f32, f32, glm::Vec2, f32)], segments: u32) {
+        let first = (self.vertices.len() / 3) as u32;
+        for &(radius, y, normal, v) in profile {
+            // The seam is doubled so the uvs can wrap from 1 back to 0
+            for segment in 0..=segments {
+                let u = segment as f32 / segments as f32;
+                let (sin, cos) = (u * 2.0 * PI).sin_cos();
+                let position = glm::vec3(radius * cos, y, -radius * sin);
+                let n = glm::normalize(&glm::vec3(normal.x * cos, normal.y, -normal.x * sin));
+                self.vertex(&position, &n, (u, v), &glm::vec3(-sin, 0.0, -cos));
+            }
+        }
+        self.grid_indices(first, segments, profile.len() as u32 - 1);
+    }
+
+    /// Flat disk facing up or down at height y
+    fn cap(&mut self, radius: f32, y: f32, segments: u32, up: bool) {
+        let normal = glm::vec3(0.0, if up { 1.0 } else { -1.0 }, 0.0);
+        let tangent = glm::vec3(1.0, 0.0, 0.0);
+        let direction = if up { 1.0 } else { -1.0 };
+        let center = self.vertex(&glm::vec3(0.0, y, 0.0), &normal, (0.5, 0.5), &tangent);
+        for segment in 0..=segments {
+            let (sin, cos) = (segment as f32 / segments as f32 * 2.0 * PI).sin_cos();
+            self.vertex(&glm::vec3(radius * cos, y, -radius * sin), &normal, (0.5 + 0.5 * cos, 0.5 + 0.5 * sin * direction), &tangent);
+        }
+        for segment in 0..segments {
+            let (a, b) = (center + 1 + segment, center + 2 + segment);
+            if up {
+                self.indices.extend_from_slice(&[center, a, b]);
+            } else {
+                self.indices.extend_from_slice(&[center, b, a]);
+            }
+        }
+    }
+
+    fn finish(self) -> Mesh {
+        let vertex_count = self.vertices.len() / 3;
+        let index_count = self.indices.len() as i32;
+        Mesh {
+            vertices: self.vertices,
+            normals: self.normals,
+            uvs: self.uvs,
+            tangents: self.tangents,
+            colors: vec![1.0; vertex_count * 4],
+            indices: self.indices,
+            index_count,
+        }
+    }
+}
+
+/// Cube with sides of size, each face split into subdivisions by subdivisions quads
+#[allow(dead_code)]
+pub fn cube(size: f32, subdivisions: u32) -> Mesh {
+    let s = subdivisions.max(1);
+    let h = size * 0.5;
+    // Normal, u axis and v axis of every face, u cross v is the normal
+    let faces = [
+        (glm::vec3(1.0, 0.0, 0.0), glm::vec3(0.0, 0.0, -1.0), glm::vec3(0.0, 1.0, 0.0)),
+        (glm::vec3(-1.0, 0.0, 0.0), glm::vec3(0.0, 0.0, 1.0), glm::vec3(0.0, 1.0, 0.0)),
+        (glm::vec3(0.0, 1.0, 0.0), glm::vec3(1.0, 0.0, 0.0), glm::vec3(0.0, 0.0, -1.0)),
+        (glm::vec3(0.0, -1.0, 0.0), glm::vec3(1.0, 0.0, 0.0), glm::vec3(0.0, 0.0, 1.0)),
+        (glm::vec3(0.0, 0.0, 1.0), glm::vec3(1.0, 0.0, 0.0), glm::vec3(0.0, 1.0, 0.0)),
+        (glm::vec3(0.0, 0.0, -1.0), glm::vec3(-1.0, 0.0, 0.0), glm::vec3(0.0, 1.0, 0.0)),
+    ];
+
+    let mut builder = Builder::new();
+    for (normal, u, v) in &faces {
+        let corner = (normal - u - v) * h;
+        builder.face(&corner, &(u * size), &(v * size), s, s);
+    }
+    builder.finish()
+}
+
+/// Sphere of rings stacked from pole to pole, each with segments quads
+#[allow(dead_code)]
+pub fn uv_sphere(radius: f32, segments: u32, rings: u32) -> Mesh {
+    let rings = rings.max(2);
+    let profile: Vec<_> = (0..=rings).map(|ring| {
+        let v = ring as f32 / rings as f32;
+        let (sin, cos) = ((v - 0.5) * PI).sin_cos();
+        (radius * cos, radius * sin, glm::vec2(cos, sin), v)
+    }).collect();
+
+    let mut builder = Builder::new();
+    builder.revolve(&profile, segments.max(3));
+    builder.finish()
+}
+
+/// Icosahedron with every triangle split in four subdivisions times, evenly spread vertices without poles.
+/// The uvs are spherical, vertices on triangles crossing the seam along +x are duplicated with u past 1 and every
+/// triangle touching a pole gets its own pole vertex
+#[allow(dead_code)]
+pub fn icosphere(radius: f32, subdivisions: u32) -> Mesh {
+    let t = (1.0 + 5.0f32.sqrt()) / 2.0;
+    let mut points: Vec<glm::Vec3> = [
+        (-1.0, t, 0.0), (1.0, t, 0.0), (-1.0, -t, 0.0), (1.0, -t, 0.0),
+        (0.0, -1.0, t), (0.0, 1.0, t), (0.0, -1.0, -t), (0.0, 1.0, -t),
+        (t, 0.0, -1.0), (t, 0.0, 1.0), (-t, 0.0, -1.0), (-t, 0.0, 1.0),
+    ].iter().map(|&(x, y, z)| glm::normalize(&glm::vec3(x, y, z))).collect();
+    let mut triangles: Vec<[u32; 3]> = vec![
+        [0, 11, 5], [0, 5, 1], [0, 1, 7], [0, 7, 10], [0, 10, 11],
+        [1, 5, 9], [5, 11, 4], [11, 10, 2], [10, 7, 6], [7, 1, 8],
+        [3, 9, 4], [3, 4, 2], [3, 2, 6], [3, 6, 8], [3, 8, 9],
+        [4, 9, 5], [2, 4, 11], [6, 2, 10], [8, 6, 7], [9, 8, 1],
+    ];
+
+    for _ in 0..subdivisions {
+        // Neighbouring triangles share the midpoint of their shared edge
+        let mut midpoints = HashMap::<(u32, u32), u32>::new();
+        let mut midpoint = |a: u32, b: u32, points: &mut Vec<glm::Vec3>| {
+            *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                points.push(glm::normalize(&(points[a as usize] + points[b as usize])));
+                points.len() as u32 - 1
+            })
+        };
+        triangles = triangles.iter().flat_map(|&[a, b, c]| {
+            let (ab, bc, ca) = (midpoint(a, b, &mut points), midpoint(b, c, &mut points), midpoint(c, a, &mut points));
+            [[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+        }).collect();
+    }
+
+    let mut builder = Builder::new();
+    for p in &points {
+        let longitude = (-p.z).atan2(p.x);
+        let u = longitude / (2.0 * PI) + if longitude < 0.0 { 1.0 } else { 0.0 };
+        let v = p.y.clamp(-1.0, 1.0).asin() / PI + 0.5;
+        // Along increasing longitude, any direction works at the poles
+        let tangent = glm::vec3(p.z, 0.0, -p.x);
+        let tangent = if glm::length(&tangent) > 1e-6 { glm::normalize(&tangent) } else { glm::vec3(1.0, 0.0, 0.0) };
+        builder.vertex(&(p * radius), p, (u, v), &tangent);
+    }
+    // Triangles wrapping around the seam use copies of their vertices near u = 0, moved to u + 1
+    let mut seam_copies = HashMap::<u32, u32>::new();
+    for &[a, b, c] in &triangles {
+        // The table above is not consistently wound, so turn every triangle to face out
+        let (pa, pb, pc) = (points[a as usize], points[b as usize], points[c as usize]);
+        let mut corners = if glm::dot(&glm::cross(&(pb - pa), &(pc - pa)), &(pa + pb + pc)) >= 0.0 { [a, b, c] } else { [a, c, b] };
+
+        // u is undefined at the poles, so they are left out of the seam check and get their u after it
+        let is_pole = |i: u32| points[i as usize].y.abs() > 1.0 - 1e-6;
+        let pole = corners.iter().position(|&i| is_pole(i));
+        let us: Vec<f32> = corners.iter().filter(|&&i| !is_pole(i)).map(|&i| builder.uvs[i as usize * 2]).collect();
+        if us.iter().cloned().fold(f32::MIN, f32::max) - us.iter().cloned().fold(f32::MAX, f32::min) > 0.5 {
+            for corner in corners.iter_mut().filter(|i| !is_pole(**i)) {
+                if builder.uvs[*corner as usize * 2] < 0.5 {
+                    *corner = *seam_copies.entry(*corner).or_insert_with(|| {
+                        let copy = builder.duplicate(*corner);
+                        builder.uvs[copy as usize * 2] += 1.0;
+                        copy
+                    });
+                }
+            }
+        }
+
+        // Each triangle touching a pole gets its own pole vertex under the middle of its other corners
+        if let Some(pole) = pole {
+            let u = (0..3).filter(|&j| j != pole).map(|j| builder.uvs[corners[j] as usize * 2]).sum::<f32>() * 0.5;
+            let copy = builder.duplicate(corners[pole]);
+            builder.uvs[copy as usize * 2] = u;
+            corners[pole] = copy;
+        }
+        builder.indices.extend_from_slice(&corners);
+    }
+    builder.finish()
+}
+
+/// Upright cylinder with flat caps
+#[allow(dead_code)]
+pub fn cylinder(radius: f32, height: f32, segments: u32) -> Mesh {
+    let h = height * 0.5;
+    let segments = segments.max(3);
+    let side = glm::vec2(1.0, 0.0);
+
+    let mut builder = Builder::new();
+    builder.revolve(&[(radius, -h, side, 0.0), (radius, h, side, 1.0)], segments);
+    builder.cap(radius, h, segments, true);
+    builder.cap(radius, -h, segments, false);
+    builder.finish()
+}
+
+/// Cone standing on its base, with the tip at height / 2
+#[allow(dead_code)]
+pub fn cone(radius: f32, height: f32, segments: u32) -> Mesh {
+    let h = height * 0.5;
+    let segments = segments.max(3);
+    // The tip gets the slope normal as well, so the side shades smoothly all the way up
+    let slope = glm::normalize(&glm::vec2(height, radius));
+
+    let mut builder = Builder::new();
+    builder.revolve(&[(radius, -h, slope, 0.0), (0.0, h, slope, 1.0)], segments);
+    builder.cap(radius, -h, segments, false);
+    builder.finish()
+}
+
+/// Ring around the y axis, major_radius to the middle of the tube and minor_radius across it
+#[allow(dead_code)]
+pub fn torus(major_radius: f32, minor_radius: f32, major_segments: u32, minor_segments: u32) -> Mesh {
+    let minor_segments = minor_segments.max(3);
+    let profile: Vec<_> = (0..=minor_segments).map(|segment| {
+        let v = segment as f32 / minor_segments as f32;
+        let (sin, cos) = (v * 2.0 * PI).sin_cos();
+        (major_radius + minor_radius * cos, minor_radius * sin, glm::vec2(cos, sin), v)
+    }).collect();
+
+    let mut builder = Builder::new();
+    builder.revolve(&profile, major_segments.max(3));
+    builder.finish()
+}
+
+/// Flat grid in the xz plane facing up
+#[allow(dead_code)]
+pub fn plane(width: f32, depth: f32, columns: u32, rows: u32) -> Mesh {
+    let mut builder = Builder::new();
+    builder.face(&glm::vec3(-width * 0.5, 0.0, depth * 0.5), &glm::vec3(width, 0.0, 0.0), &glm::vec3(0.0, 0.0, -depth), columns.max(1), rows.max(1));
+    builder.finish()
+}
+
+/// Upright cylinder of length with a half sphere on each end, rings is per half sphere
+#[allow(dead_code)]
+pub fn capsule(radius: f32, length: f32, segments: u32, rings: u32) -> Mesh {
+    let rings = rings.max(1);
+    let h = length * 0.5;
+    // v follows the distance along the outline, so the texture does not stretch on the cylinder
+    let total = PI * radius + length;
+
+    let mut profile = Vec::new();
+    for ring in 0..=rings {
+        let angle = -PI / 2.0 + ring as f32 / rings as f32 * PI / 2.0;
+        let (sin, cos) = angle.sin_cos();
+        profile.push((radius * cos, -h + radius * sin, glm::vec2(cos, sin), (angle + PI / 2.0) * radius / total));
+    }
+    for ring in 0..=rings {
+        let angle = ring as f32 / rings as f32 * PI / 2.0;
+        let (sin, cos) = angle.sin_cos();
+        profile.push((radius * cos, h + radius * sin, glm::vec2(cos, sin), (PI / 2.0 * radius + length + angle * radius) / total));
+    }
+
+    let mut builder = Builder::new();
+    builder.revolve(&profile, segments.max(3));
+    builder.finish()
+}
+
+/// Two triangles covering clip space from -1 to 1 at z 0, facing +z
+#[allow(dead_code)]
+pub fn fullscreen_quad() -> Mesh {
+    let mut builder = Builder::new();
+    builder.face(&glm::vec3(-1.0, -1.0, 0.0), &glm::vec3(2.0, 0.0, 0.0), &glm::vec3(0.0, 2.0, 0.0), 1, 1);
+    builder.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every primitive with whether it is convex, convex shapes centered on the origin face away from it everywhere
+    fn all_primitives() -> Vec<(&'static str, Mesh, bool)> {
+        vec![
+            ("cube", cube(2.0, 2), true),
+            ("uv_sphere", uv_sphere(1.0, 12, 6), true),
+            ("icosphere", icosphere(1.0, 2), true),
+            ("cylinder", cylinder(1.0, 2.0, 12), true),
+            ("cone", cone(1.0, 2.0, 12), true),
+            ("torus", torus(2.0, 0.5, 12, 8), false),
+            ("plane", plane(2.0, 3.0, 2, 3), false),
+            ("capsule", capsule(0.5, 2.0, 12, 4), true),
+            ("fullscreen_quad", fullscreen_quad(), false),
+        ]
+    }
+
+    fn vec3_at(stream: &[f32], size: usize, index: usize) -> glm::Vec3 {
+        glm::vec3(stream[index * size], stream[index * size + 1], stream[index * size + 2])
+    }
+
+    #[test]
+    fn normals_and_tangents_are_unit_length_and_perpendicular() {
+        for (name, mesh, _) in all_primitives() {
+            for i in 0..mesh.vertices.len() / 3 {
+                let normal = vec3_at(&mesh.normals, 3, i);
+                let tangent = vec3_at(&mesh.tangents, 4, i);
+                assert!((glm::length(&normal) - 1.0).abs() < 1e-4, "{} normal {} is {:?}", name, i, normal);
+                assert!((glm::length(&tangent) - 1.0).abs() < 1e-4, "{} tangent {} is {:?}", name, i, tangent);
+                assert!(glm::dot(&normal, &tangent).abs() < 1e-4, "{} vertex {} has normal {:?} and tangent {:?}", name, i, normal, tangent);
+                assert_eq!(mesh.tangents[i * 4 + 3].abs(), 1.0, "{} tangent {} handedness", name, i);
+            }
+        }
+    }
+
+    #[test]
+    fn triangles_face_the_same_way_as_their_normals() {
+        for (name, mesh, convex) in all_primitives() {
+            for triangle in mesh.indices.chunks_exact(3) {
+                let [a, b, c] = [0, 1, 2].map(|j| vec3_at(&mesh.vertices, 3, triangle[j] as usize));
+                let face = glm::cross(&(b - a), &(c - a));
+                // Rings collapsing into a pole or a tip leave triangles without area
+                if glm::length(&face) < 1e-6 {
+                    continue;
+                }
+
+                let normals = triangle.iter().fold(glm::Vec3::zeros(), |sum, &i| sum + vec3_at(&mesh.normals, 3, i as usize));
+                assert!(glm::dot(&face, &normals) > 0.0, "{} triangle {:?} is wound against its normals", name, triangle);
+                if convex {
+                    assert!(glm::dot(&face, &(a + b + c)) > 0.0, "{} triangle {:?} faces inwards", name, triangle);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn icosphere_triangles_do_not_stretch_across_the_seam() {
+        let mesh = icosphere(1.0, 2);
+        for triangle in mesh.indices.chunks_exact(3) {
+            let us: Vec<f32> = triangle.iter().map(|&i| mesh.uvs[i as usize * 2]).collect();
+            let spread = us.iter().cloned().fold(f32::MIN, f32::max) - us.iter().cloned().fold(f32::MAX, f32::min);
+            assert!(spread < 0.5, "triangle {:?} spans u {:?}", triangle, us);
+        }
+    }
+}
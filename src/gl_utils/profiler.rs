@@ -0,0 +1,240 @@
+use gl::types::{GLint, GLuint, GLuint64};
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant}
+};
+
+use super::render_queue::SubmitStats;
+
+// Buffer uploads happen deep inside gl_utils, so they are counted globally instead of threading the profiler everywhere
+static UPLOADED_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+/// Count bytes uploaded to the GPU for the current frame
+pub fn record_upload(bytes: usize) {
+    UPLOADED_BYTES.fetch_add(bytes, Ordering::Relaxed);
+}
+
+/// GL_TIME_ELAPSED query pair for a named pass. Frames alternate between the two queries, so results are read
+/// two frames after they were issued. Results that still aren't available are dropped instead of waited for
+struct PassTimer {
+    name: &'static str,
+    queries: [GLuint; 2],
+    issued: [bool; 2],
+    gpu_ms_sum: f64,
+    samples: usize,
+}
+
+/// Draw calls and triangles only count the render queue, the skybox, outline, debug, text and gui passes draw directly
+#[derive(Default)]
+struct FrameStats {
+    cpu_ms: f64,
+    draw_calls: usize,
+    triangles: usize,
+    uploaded_bytes: usize,
+}
+
+pub struct Profiler {
+    passes: Vec<PassTimer>,
+    active_pass: Option<usize>,
+    frame: usize,
+    frame_start: Instant,
+    current: FrameStats,
+    /// Sum of all frames since last summary
+    summary: FrameStats,
+    summary_frames: usize,
+    summary_interval: Duration,
+    last_summary: Instant,
+    trace: Option<BufWriter<File>>,
+}
+
+impl Drop for Profiler {
+    fn drop(&mut self) {
+        for pass in &self.passes {
+            unsafe {
+                gl::DeleteQueries(2, pass.queries.as_ptr());
+            }
+        }
+
+        if let Some(trace) = &mut self.trace {
+            if let Err(e) = trace.flush() {
+                eprintln!("Failed to flush profiling trace, e: {}", e);
+            }
+        }
+    }
+}
+
+impl Profiler {
+    /// A summary is printed to stdout every summary_interval
+    pub fn new(summary_interval: Duration) -> Self {
+        let now = Instant::now();
+        Self {
+            passes: Vec::new(),
+            active_pass: None,
+            frame: 0,
+            frame_start: now,
+            current: FrameStats::default(),
+            summary: FrameStats::default(),
+            summary_frames: 0,
+            summary_interval,
+            last_summary: now,
+            trace: None,
+        }
+    }
+
+    /// Write every measurement as a 'frame,metric,value' row to a csv file
+    pub fn trace_to_csv(&mut self, path: &str) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "frame,metric,value")?;
+        self.trace = Some(writer);
+
+        Ok(())
+    }
+
+    pub fn begin_frame(&mut self) {
+        self.frame_start = Instant::now();
+        self.current = FrameStats::default();
+        UPLOADED_BYTES.store(0, Ordering::Relaxed);
+
+        // Collect the results of the queries we are about to reuse, they were issued two frames ago
+        let parity = self.frame % 2;
+        let measured_frame = self.frame.wrapping_sub(2);
+        for pass in &mut self.passes {
+            if !pass.issued[parity] {
+                continue;
+            }
+
+            pass.issued[parity] = false;
+
+            // Waiting for the result would stall, skip the sample and reuse the query
+            let mut available: GLint = 0;
+            let mut elapsed_ns: GLuint64 = 0;
+            unsafe {
+                gl::GetQueryObjectiv(pass.queries[parity], gl::QUERY_RESULT_AVAILABLE, &mut available);
+                if available == 0 {
+                    continue;
+                }
+                gl::GetQueryObjectui64v(pass.queries[parity], gl::QUERY_RESULT, &mut elapsed_ns);
+            }
+
+            let gpu_ms = elapsed_ns as f64 / 1e6;
+            pass.gpu_ms_sum += gpu_ms;
+            pass.samples += 1;
+
+            if let Some(trace) = &mut self.trace {
+                if let Err(e) = writeln!(trace, "{},gpu_{},{:.4}", measured_frame, pass.name, gpu_ms) {
+                    eprintln!("Failed to write profiling trace, e: {}", e);
+                }
+            }
+        }
+    }
+
+    /// Time the gpu work issued until end_pass. Passes can not be nested
+    pub fn begin_pass(&mut self, name: &'static str) {
+        if self.active_pass.is_some() {
+            eprintln!("Profiler pass {} started while another pass is active", name);
+            return;
+        }
+
+        let index = match self.passes.iter().position(|p| p.name == name) {
+            Some(i) => i,
+            None => {
+                let mut queries: [GLuint; 2] = [0; 2];
+                unsafe {
+                    gl::GenQueries(2, queries.as_mut_ptr());
+                }
+                self.passes.push(PassTimer {
+                    name,
+                    queries,
+                    issued: [false; 2],
+                    gpu_ms_sum: 0.0,
+                    samples: 0,
+                });
+                self.passes.len() - 1
+            }
+        };
+
+        let parity = self.frame % 2;
+        let pass = &mut self.passes[index];
+        unsafe {
+            gl::BeginQuery(gl::TIME_ELAPSED, pass.queries[parity]);
+        }
+        pass.issued[parity] = true;
+        self.active_pass = Some(index);
+    }
+
+    pub fn end_pass(&mut self) {
+        if self.active_pass.take().is_some() {
+            unsafe {
+                gl::EndQuery(gl::TIME_ELAPSED);
+            }
+        }
+    }
+
+    /// Count the draw calls and triangles of a render queue submit
+    pub fn record_submit(&mut self, stats: &SubmitStats) {
+        self.current.draw_calls += stats.draw_calls;
+        self.current.triangles += stats.triangles;
+    }
+
+    /// Call before swapping buffers, the cpu time is measured from begin_frame until here
+    pub fn end_frame(&mut self) {
+        self.end_pass();
+
+        self.current.cpu_ms = self.frame_start.elapsed().as_secs_f64() * 1e3;
+        self.current.uploaded_bytes = UPLOADED_BYTES.load(Ordering::Relaxed);
+
+        if let Some(trace) = &mut self.trace {
+            let frame = self.frame;
+            let current = &self.current;
+            let result = writeln!(trace, "{},cpu_ms,{:.4}", frame, current.cpu_ms)
+                .and_then(|_| writeln!(trace, "{},draw_calls,{}", frame, current.draw_calls))
+                .and_then(|_| writeln!(trace, "{},triangles,{}", frame, current.triangles))
+                .and_then(|_| writeln!(trace, "{},uploaded_bytes,{}", frame, current.uploaded_bytes));
+            if let Err(e) = result {
+                eprintln!("Failed to write profiling trace, e: {}", e);
+            }
+        }
+
+        self.summary.cpu_ms += self.current.cpu_ms;
+        self.summary.draw_calls += self.current.draw_calls;
+        self.summary.triangles += self.current.triangles;
+        self.summary.uploaded_bytes += self.current.uploaded_bytes;
+        self.summary_frames += 1;
+        self.frame += 1;
+
+        if self.last_summary.elapsed() >= self.summary_interval {
+            self.print_summary();
+        }
+    }
+
+    fn print_summary(&mut self) {
+        let frames = self.summary_frames.max(1) as f64;
+        let seconds = self.last_summary.elapsed().as_secs_f64();
+
+        let mut summary = format!(
+            "Frame {} | fps: {:.1} | cpu: {:.2}ms | draw calls: {:.0} | triangles: {:.0} | uploaded: {:.1}KB",
+            self.frame,
+            self.summary_frames as f64 / seconds,
+            self.summary.cpu_ms / frames,
+            self.summary.draw_calls as f64 / frames,
+            self.summary.triangles as f64 / frames,
+            self.summary.uploaded_bytes as f64 / frames / 1024.0,
+        );
+
+        for pass in &mut self.passes {
+            if pass.samples > 0 {
+                summary.push_str(&format!(" | {}: {:.3}ms", pass.name, pass.gpu_ms_sum / pass.samples as f64));
+            }
+            pass.gpu_ms_sum = 0.0;
+            pass.samples = 0;
+        }
+        println!("{}", summary);
+
+        self.summary = FrameStats::default();
+        self.summary_frames = 0;
+        self.last_summary = Instant::now();
+    }
+}
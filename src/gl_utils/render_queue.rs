@@ -0,0 +1,265 @@
+use gl::types::{GLint, GLsizei, GLuint};
+
+use std::{cmp::Ordering, collections::HashMap};
+
+use super::{helpers, material::{BlendMode, Material, MaterialUniforms}, profiler};
+
+/// A single instanced draw of a range of instances in a GeometricObject
+#[derive(Debug, Clone, Copy)]
+pub struct DrawItem {
+    pub program_id: GLuint,
+    pub material: Material,
+    pub vao_id: GLuint,
+    pub elem_id: GLuint,
+    pub indices_count: GLsizei,
+    pub first_instance: GLuint,
+    pub instance_count: GLsizei,
+    /// Distance to the viewer, only used to sort transparent items
+    pub depth: f32,
+}
+
+impl DrawItem {
+    fn is_transparent(&self) -> bool {
+        self.material.blend_mode.is_transparent()
+    }
+
+    /// Opaque items are sorted so that the most expensive state changes happen the least,
+    /// and are all drawn before the transparent items which are sorted back to front
+    fn draw_order(&self, other: &DrawItem) -> Ordering {
+        match (self.is_transparent(), other.is_transparent()) {
+            (false, true) => Ordering::Less,
+            (true, false) => Ordering::Greater,
+            (false, false) => (self.program_id, self.material.id, self.vao_id, self.first_instance)
+                .cmp(&(other.program_id, other.material.id, other.vao_id, other.first_instance)),
+            (true, true) => other.depth.partial_cmp(&self.depth).unwrap_or(Ordering::Equal),
+        }
+    }
+
+    fn same_batch(&self, other: &DrawItem) -> bool {
+        self.vao_id == other.vao_id && self.program_id == other.program_id && self.material == other.material
+    }
+}
+
+// Layout defined by the OpenGL spec for glMultiDrawElementsIndirect
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct DrawElementsIndirectCommand {
+    count: GLuint,
+    instance_count: GLuint,
+    first_index: GLuint,
+    base_vertex: GLint,
+    base_instance: GLuint,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SubmitStats {
+    pub draw_calls: usize,
+    pub program_binds: usize,
+    pub vao_binds: usize,
+    pub triangles: usize,
+}
+
+/// Collects draw items for a frame and submits them with as few state changes as possible
+pub struct RenderQueue {
+    /// World position of the viewer, used to sort transparent items
+    pub view_position: glm::Vec3,
+    items: Vec<DrawItem>,
+    commands: Vec<DrawElementsIndirectCommand>,
+    indirect_buffer: GLuint,
+    indirect_capacity: usize,
+    multi_draw_indirect: bool,
+    /// Looked up once per program instead of every time a material is applied
+    material_uniforms: HashMap<GLuint, MaterialUniforms>,
+}
+
+impl Drop for RenderQueue {
+    fn drop(&mut self) {
+        if self.indirect_buffer != 0 {
+            unsafe {
+                gl::DeleteBuffers(1, &self.indirect_buffer);
+            }
+        }
+    }
+}
+
+impl Default for RenderQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RenderQueue {
+    pub fn new() -> Self {
+        let (mut major, mut minor): (GLint, GLint) = (0, 0);
+        unsafe {
+            gl::GetIntegerv(gl::MAJOR_VERSION, &mut major);
+            gl::GetIntegerv(gl::MINOR_VERSION, &mut minor);
+        }
+
+        let multi_draw_indirect = (major, minor) >= (4, 3);
+        let mut indirect_buffer = 0;
+        if multi_draw_indirect {
+            unsafe {
+                gl::GenBuffers(1, &mut indirect_buffer);
+            }
+        }
+
+        Self {
+            view_position: glm::zero(),
+            items: Vec::new(),
+            commands: Vec::new(),
+            indirect_buffer,
+            indirect_capacity: 0,
+            multi_draw_indirect,
+            material_uniforms: HashMap::new(),
+        }
+    }
+
+    /// Fallback to one glDrawElementsInstancedBaseInstance per item, even if GL 4.3 is available
+    pub fn set_multi_draw_indirect(&mut self, enabled: bool) {
+        self.multi_draw_indirect = enabled && self.indirect_buffer != 0;
+    }
+
+    pub fn push(&mut self, item: DrawItem) {
+        if item.instance_count > 0 {
+            self.items.push(item);
+        }
+    }
+
+    /// Draws all queued items and empties the queue. Opaque items are drawn first with as few state changes as possible,
+    /// then transparent items back to front with depth writes disabled
+    pub fn submit(&mut self) -> SubmitStats {
+        let mut stats = SubmitStats::default();
+        if self.items.is_empty() {
+            return stats;
+        }
+
+        self.items.sort_by(|a, b| a.draw_order(b));
+
+        let mut bound_program: Option<GLuint> = None;
+        let mut bound_blend: Option<BlendMode> = None;
+        let mut bound_material: Option<(GLuint, Material)> = None;
+        let mut depth_write = true;
+        let mut batch_start = 0;
+        while batch_start < self.items.len() {
+            let first = self.items[batch_start];
+            let batch_end = self.items[batch_start..].iter()
+                .position(|item| !item.same_batch(&first))
+                .map_or(self.items.len(), |i| batch_start + i);
+
+            if depth_write && first.is_transparent() {
+                unsafe {
+                    gl::DepthMask(gl::FALSE);
+                }
+                depth_write = false;
+            }
+
+            if bound_blend != Some(first.material.blend_mode) {
+                first.material.blend_mode.apply();
+                bound_blend = Some(first.material.blend_mode);
+            }
+
+            if bound_program != Some(first.program_id) {
+                unsafe {
+                    gl::UseProgram(first.program_id);
+                }
+                bound_program = Some(first.program_id);
+                stats.program_binds += 1;
+            }
+
+            // Uniforms belong to the program, so they are set again when either changes
+            if bound_material != Some((first.program_id, first.material)) {
+                let uniforms = self.material_uniforms.entry(first.program_id)
+                    .or_insert_with(|| MaterialUniforms::locate(first.program_id));
+                first.material.apply_uniforms(uniforms);
+                bound_material = Some((first.program_id, first.material));
+            }
+
+            unsafe {
+                gl::BindVertexArray(first.vao_id);
+                gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, first.elem_id);
+            }
+            stats.vao_binds += 1;
+
+            let batch = batch_start..batch_end;
+            for item in &self.items[batch.clone()] {
+                stats.triangles += (item.indices_count as usize / 3) * item.instance_count as usize;
+            }
+
+            if self.multi_draw_indirect && batch.len() > 1 {
+                self.draw_indirect(batch);
+                stats.draw_calls += 1;
+            } else {
+                for item in &self.items[batch.clone()] {
+                    unsafe {
+                        gl::DrawElementsInstancedBaseInstance(
+                            gl::TRIANGLES,
+                            item.indices_count,
+                            gl::UNSIGNED_INT,
+                            std::ptr::null(),
+                            item.instance_count,
+                            item.first_instance
+                        );
+                    }
+                }
+                stats.draw_calls += batch.len();
+            }
+
+            batch_start = batch_end;
+        }
+
+        unsafe {
+            gl::DepthMask(gl::TRUE);
+            BlendMode::Opaque.apply();
+            gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, 0);
+            gl::BindVertexArray(0);
+            gl::UseProgram(0);
+        }
+
+        self.items.clear();
+
+        stats
+    }
+
+    /// Draws a batch of items sharing the same vao with a single glMultiDrawElementsIndirect
+    fn draw_indirect(&mut self, batch: std::ops::Range<usize>) {
+        self.commands.clear();
+        self.commands.extend(self.items[batch].iter().map(|item| DrawElementsIndirectCommand {
+            count: item.indices_count as GLuint,
+            instance_count: item.instance_count as GLuint,
+            first_index: 0,
+            base_vertex: 0,
+            base_instance: item.first_instance,
+        }));
+
+        unsafe {
+            gl::BindBuffer(gl::DRAW_INDIRECT_BUFFER, self.indirect_buffer);
+            if self.commands.len() > self.indirect_capacity {
+                self.indirect_capacity = self.commands.len().next_power_of_two();
+                gl::BufferData(
+                    gl::DRAW_INDIRECT_BUFFER,
+                    (self.indirect_capacity * std::mem::size_of::<DrawElementsIndirectCommand>()) as isize,
+                    std::ptr::null(),
+                    gl::STREAM_DRAW
+                );
+            }
+
+            profiler::record_upload(std::mem::size_of_val(&self.commands[..]));
+            gl::BufferSubData(
+                gl::DRAW_INDIRECT_BUFFER,
+                0,
+                helpers::byte_size_of_array(&self.commands),
+                helpers::array_to_c_void(&self.commands)
+            );
+
+            gl::MultiDrawElementsIndirect(
+                gl::TRIANGLES,
+                gl::UNSIGNED_INT,
+                std::ptr::null(),
+                self.commands.len() as GLsizei,
+                0
+            );
+            gl::BindBuffer(gl::DRAW_INDIRECT_BUFFER, 0);
+        }
+    }
+}
@@ -7,7 +7,7 @@ extern crate nalgebra_glm as glm;
 use std::mem::ManuallyDrop;
 use std::pin::Pin;
 
-use super::{camera::Camera, geometric_object::GeometricInstance, geometric_object::GeometricObject};
+use super::{collision::Collider, geometric_object::GeometricInstance, picking::PickHit, render_queue::RenderQueue};
 
 // Used to crete an unholy abomination upon which you should not cast your gaze.
 // This ended up being a necessity due to wanting to keep the code written by students as "straight forward" as possible
@@ -19,6 +19,8 @@ use super::{camera::Camera, geometric_object::GeometricInstance, geometric_objec
 pub type Node = ManuallyDrop<Pin<Box<SceneNode>>>;
 
 pub struct SceneNode {
+    /// Only used to tell nodes apart when inspecting the graph
+    pub name: String,
     pub position: glm::Vec3,
     /// In radians
     pub rotation: glm::Vec3,
@@ -26,6 +28,8 @@ pub struct SceneNode {
     pub reference_point: glm::Vec3,
 
     pub current_transformation_matrix: glm::Mat4,
+    /// Transformation before the last step, None until the node has been stepped once
+    pub previous_transformation_matrix: Option<glm::Mat4>,
 
     pub geometric_instance: Option<GeometricInstance>,
 
@@ -35,11 +39,13 @@ pub struct SceneNode {
 impl SceneNode {
     pub fn new() -> Node {
         ManuallyDrop::new(Pin::new(Box::new(SceneNode {
+            name: String::new(),
             position: glm::zero(),
             rotation: glm::zero(),
             scale: glm::vec3(1.0, 1.0, 1.0),
             reference_point: glm::zero(),
             current_transformation_matrix: glm::identity(),
+            previous_transformation_matrix: None,
             geometric_instance: None,
             children: vec![],
         })))
@@ -47,11 +53,13 @@ impl SceneNode {
 
     pub fn from_vao(geometric_instance: GeometricInstance) -> Node {
         ManuallyDrop::new(Pin::new(Box::new(SceneNode {
+            name: String::new(),
             position: glm::zero(),
             rotation: glm::zero(),
             scale: glm::vec3(1.0, 1.0, 1.0),
             reference_point: glm::zero(),
             current_transformation_matrix: glm::identity(),
+            previous_transformation_matrix: None,
             geometric_instance: Some(geometric_instance),
             children: vec![],
         })))
@@ -61,7 +69,13 @@ impl SceneNode {
         self.children.push(child as *const SceneNode as *mut SceneNode)
     }
 
+    pub fn remove_child(&mut self, child: &SceneNode) {
+        let child = child as *const SceneNode as *mut SceneNode;
+        self.children.retain(|&c| c != child);
+    }
+
     // TODO: impl Display instead
+    #[allow(dead_code)]
     pub fn print(&self) {
         let m = self.current_transformation_matrix;
         let matrix_string = format!(
@@ -116,23 +130,23 @@ impl SceneNode {
 
     // Again, no consideration of speed, we construct a lot of discarded vectors in this function.
     // Which is even worse in a recursive context! Also using euler rotation here might bite me later ...
+    fn local_transformation(&self) -> glm::Mat4 {
+        let mut self_mat = glm::scale(&glm::Mat4::identity(), &self.scale);
+
+        self_mat = glm::translate(&self_mat, &self.reference_point);
+        self_mat = glm::rotate(&self_mat, self.rotation.x, &glm::vec3(1.0, 0.0, 0.0));
+        self_mat = glm::rotate(&self_mat, self.rotation.y, &glm::vec3(0.0, 1.0, 0.0));
+        self_mat = glm::rotate(&self_mat, self.rotation.z, &glm::vec3(0.0, 0.0, 1.0));
+        self_mat = glm::translate(&self_mat, &glm::vec3(-self.reference_point.x, -self.reference_point.y, -self.reference_point.z));
+
+        glm::translate(&self_mat, &self.position)
+    }
+
+    #[allow(dead_code)]
     pub fn update_node_transformations(&mut self, transformation_so_far: &glm::Mat4) {
         unsafe {
-            // Construct the correct transformation matrix
-            let transformation = {
-                let mut self_mat = glm::scale(&glm::Mat4::identity(), &self.scale);
-
-                self_mat = glm::translate(&self_mat, &self.reference_point);
-                self_mat = glm::rotate(&self_mat, self.rotation.x, &glm::vec3(1.0, 0.0, 0.0));
-                self_mat = glm::rotate(&self_mat, self.rotation.y, &glm::vec3(0.0, 1.0, 0.0));
-                self_mat = glm::rotate(&self_mat, self.rotation.z, &glm::vec3(0.0, 0.0, 1.0));
-                self_mat = glm::translate(&self_mat, &glm::vec3(-self.reference_point.x, -self.reference_point.y, -self.reference_point.z));
-                
-                glm::translate(&self_mat, &self.position)
-            };
-
             // Update the node's transformation matrix
-            self.current_transformation_matrix = transformation_so_far * transformation;
+            self.current_transformation_matrix = transformation_so_far * self.local_transformation();
 
             // Again, we don't care about perfomance and always update transform
             if let Some(g) = &self.geometric_instance {
@@ -145,18 +159,184 @@ impl SceneNode {
         }
     }
 
-    pub fn draw(&self, camera: &Camera, drawn_vaos: &mut Vec<u32>) {
+    /// Like update_node_transformations, but keeps the previous transformation and does not upload anything.
+    /// Used once per simulation tick, interpolate_node_transformations then uploads what is rendered
+    pub fn step_node_transformations(&mut self, transformation_so_far: &glm::Mat4) {
+        unsafe {
+            let transformation = transformation_so_far * self.local_transformation();
+
+            // New nodes have no history, so they start out standing still
+            self.previous_transformation_matrix = Some(match self.previous_transformation_matrix {
+                Some(_) => self.current_transformation_matrix,
+                None => transformation,
+            });
+            self.current_transformation_matrix = transformation;
+
+            for &child in &self.children {
+                (*child).step_node_transformations(&self.current_transformation_matrix);
+            }
+        }
+    }
+
+    /// Like step_node_transformations, but forgets the previous transformation so a change shows right away instead of being blended in
+    pub fn snap_node_transformations(&mut self, transformation_so_far: &glm::Mat4) {
+        let transformation = transformation_so_far * self.local_transformation();
+        self.previous_transformation_matrix = Some(transformation);
+        self.current_transformation_matrix = transformation;
+
+        unsafe {
+            for &child in &self.children {
+                (*child).snap_node_transformations(&self.current_transformation_matrix);
+            }
+        }
+    }
+
+    /// Uploads transformations blended between the last two steps, alpha 0 is the previous step and 1 the latest
+    pub fn interpolate_node_transformations(&self, alpha: f32) {
         if let Some(g) = &self.geometric_instance {
-            if !drawn_vaos.contains(&g.vao_id) {
-                g.draw_all();
-                drawn_vaos.push(g.vao_id);
+            let transformation = match &self.previous_transformation_matrix {
+                Some(previous) => interpolate_transformation(previous, &self.current_transformation_matrix, alpha),
+                None => self.current_transformation_matrix,
+            };
+            g.update_transform(&transformation);
+        }
+
+        unsafe {
+            for &child in &self.children {
+                (*child).interpolate_node_transformations(alpha);
+            }
+        }
+    }
+
+    /// Queues the instances of every node in the graph, batched per geometry. Detached nodes are not queued
+    pub fn queue_draw_items(&self, queue: &mut RenderQueue) {
+        for (g, indices) in self.instance_groups() {
+            g.queue_instances(queue, &indices);
+        }
+    }
+
+    /// The instances in the graph grouped by geometry, with the sorted instance indices of each group
+    fn instance_groups(&self) -> Vec<(&GeometricInstance, Vec<usize>)> {
+        let mut instances = Vec::<&GeometricInstance>::new();
+        self.collect_instances(&mut instances);
+        instances.sort_by_key(|g| (g.vao_id, g.instance_index));
+
+        let mut groups: Vec<(&GeometricInstance, Vec<usize>)> = Vec::new();
+        for g in instances {
+            match groups.last_mut() {
+                Some((first, indices)) if first.vao_id == g.vao_id => indices.push(g.instance_index),
+                _ => groups.push((g, vec![g.instance_index])),
+            }
+        }
+
+        groups
+    }
+
+    /// Closest node with pickable geometry hit by a world space ray, uses the transformations from the last update
+    pub fn pick(&self, origin: &glm::Vec3, direction: &glm::Vec3) -> Option<PickHit<'_>> {
+        let direction = glm::normalize(direction);
+        let mut closest: Option<PickHit> = None;
+        self.pick_recursive(origin, &direction, &mut closest);
+
+        closest
+    }
+
+    fn pick_recursive<'a>(&'a self, origin: &glm::Vec3, direction: &glm::Vec3, closest: &mut Option<PickHit<'a>>) {
+        let pickable = self.geometric_instance.as_ref()
+            .and_then(|g| g.pick_mesh.as_ref().map(|mesh| (g.instance_index, mesh)));
+
+        if let Some((instance_index, mesh)) = pickable {
+            // Intersect in local space so the triangles don't have to be transformed
+            let to_local = glm::inverse(&self.current_transformation_matrix);
+            let local_origin = glm::vec4_to_vec3(&(to_local * glm::vec4(origin.x, origin.y, origin.z, 1.0)));
+            let local_direction = glm::vec4_to_vec3(&(to_local * glm::vec4(direction.x, direction.y, direction.z, 0.0)));
+
+            if let Some(local_distance) = mesh.intersect(&local_origin, &local_direction) {
+                // Scaling changes distances, so measure the hit again in world space
+                let local_hit = local_origin + glm::normalize(&local_direction) * local_distance;
+                let hit = glm::vec4_to_vec3(&(self.current_transformation_matrix * glm::vec4(local_hit.x, local_hit.y, local_hit.z, 1.0)));
+                let distance = glm::distance(origin, &hit);
+
+                if closest.as_ref().map_or(true, |c| distance < c.distance) {
+                    *closest = Some(PickHit {
+                        node: self,
+                        distance,
+                        instance_index,
+                    });
+                }
+            }
+        }
+
+        unsafe {
+            for &child in &self.children {
+                (*child).pick_recursive(origin, direction, closest);
+            }
+        }
+    }
+
+    /// World space bounds of every visible node with bounds, from the transformations of the last step
+    pub fn colliders(&self) -> Vec<Collider<'_>> {
+        let mut colliders = Vec::new();
+        self.collect_colliders(&mut colliders);
+
+        colliders
+    }
+
+    fn collect_colliders<'a>(&'a self, colliders: &mut Vec<Collider<'a>>) {
+        if let Some(g) = &self.geometric_instance {
+            if let Some(bounds) = &g.bounds {
+                if g.is_visible() {
+                    colliders.push(Collider::new(self, bounds));
+                }
             }
         }
 
         unsafe {
             for &child in &self.children {
-                (*child).draw(&camera, drawn_vaos);
+                (*child).collect_colliders(colliders);
             }
         }
     }
+
+    fn collect_instances<'a>(&'a self, instances: &mut Vec<&'a GeometricInstance>) {
+        if let Some(g) = &self.geometric_instance {
+            instances.push(g);
+        }
+
+        unsafe {
+            for &child in &self.children {
+                (*child).collect_instances(instances);
+            }
+        }
+    }
+}
+
+// Blends translation, rotation and scale separately, blending the matrices directly would shrink rotating objects.
+// Assumes the transformations have no shear, which holds as long as non uniform scales are not rotated
+fn interpolate_transformation(from: &glm::Mat4, to: &glm::Mat4, alpha: f32) -> glm::Mat4 {
+    let decompose = |m: &glm::Mat4| {
+        let translation = glm::vec3(m[12], m[13], m[14]);
+        let scale = glm::vec3(
+            glm::length(&glm::vec3(m[0], m[1], m[2])),
+            glm::length(&glm::vec3(m[4], m[5], m[6])),
+            glm::length(&glm::vec3(m[8], m[9], m[10])),
+        );
+        let rotation = glm::to_quat(&glm::scale(m, &glm::vec3(1.0 / scale.x, 1.0 / scale.y, 1.0 / scale.z)));
+        (translation, rotation, scale)
+    };
+
+    let (from_translation, from_rotation, from_scale) = decompose(from);
+    let (to_translation, mut to_rotation, to_scale) = decompose(to);
+
+    // Normalized lerp, the rotation between two steps is small so it is close enough to slerp.
+    // Flip to the same hemisphere to take the short way around
+    if glm::quat_dot(&from_rotation, &to_rotation) < 0.0 {
+        to_rotation = -to_rotation;
+    }
+    let rotation = glm::quat_normalize(&glm::quat_lerp(&from_rotation, &to_rotation, alpha));
+
+    let translation = glm::lerp(&from_translation, &to_translation, alpha);
+    let scale = glm::lerp(&from_scale, &to_scale, alpha);
+
+    glm::translation(&translation) * glm::quat_to_mat4(&rotation) * glm::scaling(&scale)
 }
@@ -2,6 +2,7 @@
 use gl::types::GLenum;
 use std::{fmt, ffi};
 
+#[derive(Debug)]
 pub enum ShaderProgramError {
     GlUniform(GlUniformError),
     UniformNotFound,
@@ -20,6 +21,7 @@ impl fmt::Display for ShaderProgramError {
 
 // TODO: convert file to module
 // TODO: Split into 3 errors and have an upper error type see: https://doc.rust-lang.org/stable/rust-by-example/error/multiple_error_types/wrap_error.html 
+#[derive(Debug)]
 pub struct GlUniformError {
     error_code: GLenum,
 }
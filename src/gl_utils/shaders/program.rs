@@ -4,6 +4,7 @@ use super::{shader_type::ShaderType, errors::GlUniformError};
 
 use gl::types::{
     GLboolean, 
+    GLenum,
     GLsizei, 
     GLuint, 
     GLint
@@ -21,6 +22,44 @@ pub struct Program {
     uniforms: HashMap<String, GLint> 
 }
 
+/// A uniform the linked program actually uses, as reported by the driver
+pub struct ActiveUniform {
+    pub name: String,
+    pub location: GLint,
+    pub gl_type: GLenum,
+}
+
+impl ActiveUniform {
+    /// Number of floats in float, vector and matrix uniforms, None for other types
+    pub fn float_components(&self) -> Option<usize> {
+        match self.gl_type {
+            gl::FLOAT => Some(1),
+            gl::FLOAT_VEC2 => Some(2),
+            gl::FLOAT_VEC3 => Some(3),
+            gl::FLOAT_VEC4 => Some(4),
+            gl::FLOAT_MAT3 => Some(9),
+            gl::FLOAT_MAT4 => Some(16),
+            _ => None,
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self.gl_type {
+            gl::FLOAT => "float",
+            gl::FLOAT_VEC2 => "vec2",
+            gl::FLOAT_VEC3 => "vec3",
+            gl::FLOAT_VEC4 => "vec4",
+            gl::FLOAT_MAT3 => "mat3",
+            gl::FLOAT_MAT4 => "mat4",
+            gl::INT => "int",
+            gl::BOOL => "bool",
+            gl::SAMPLER_2D => "sampler2D",
+            gl::SAMPLER_CUBE => "samplerCube",
+            _ => "other",
+        }
+    }
+}
+
 impl Bindable for Program {
     fn bind(&self) {
         unsafe {
@@ -37,7 +76,7 @@ impl Bindable for Program {
 
 impl Program {
     pub fn locate_uniform(&mut self, name: &str) -> Result<(), ShaderProgramError> {
-        if let Some(_) = self.uniforms.get(name) {
+        if self.uniforms.contains_key(name) {
             return Ok(());
         }
 
@@ -62,7 +101,81 @@ impl Program {
 
         self.uniforms.insert(name.to_string(), target_location);
 
-        return Ok(());
+        Ok(())
+    }
+
+    /// Whether locate_uniform has found the uniform
+    pub fn has_uniform(&self, name: &str) -> bool {
+        self.uniforms.contains_key(name)
+    }
+
+    /// Every uniform used by the program, not only the located ones. Array uniforms are listed by their first element
+    pub fn active_uniforms(&self) -> Vec<ActiveUniform> {
+        let mut uniforms = Vec::new();
+        unsafe {
+            let mut count: GLint = 0;
+            gl::GetProgramiv(self.program_id, gl::ACTIVE_UNIFORMS, &mut count);
+
+            for index in 0..count as GLuint {
+                let mut name = [0u8; 256];
+                let (mut length, mut size, mut gl_type): (GLsizei, GLint, GLenum) = (0, 0, 0);
+                gl::GetActiveUniform(
+                    self.program_id,
+                    index,
+                    name.len() as GLsizei,
+                    &mut length,
+                    &mut size,
+                    &mut gl_type,
+                    name.as_mut_ptr() as *mut gl::types::GLchar
+                );
+                let name = String::from_utf8_lossy(&name[..length as usize]).into_owned();
+
+                // Uniforms in blocks have no location
+                let c_name = match CString::new(name.as_str()) {
+                    Ok(c_name) => c_name,
+                    Err(_) => continue,
+                };
+                let location = gl::GetUniformLocation(self.program_id, c_name.as_ptr());
+                if location >= 0 {
+                    uniforms.push(ActiveUniform {
+                        name,
+                        location,
+                        gl_type,
+                    });
+                }
+            }
+        }
+
+        uniforms
+    }
+
+    /// Current value of a float, vector or matrix uniform, matrices are column major
+    pub fn uniform_values(&self, uniform: &ActiveUniform) -> Option<Vec<f32>> {
+        let mut values = vec![0.0; uniform.float_components()?];
+        unsafe {
+            gl::GetUniformfv(self.program_id, uniform.location, values.as_mut_ptr());
+        }
+
+        Some(values)
+    }
+
+    /// Assigns a float, vector or matrix uniform, values must have as many components as the uniform
+    pub fn set_uniform_values(&self, uniform: &ActiveUniform, values: &[f32]) {
+        if uniform.float_components() != Some(values.len()) {
+            return;
+        }
+
+        unsafe {
+            match uniform.gl_type {
+                gl::FLOAT => gl::ProgramUniform1fv(self.program_id, uniform.location, 1, values.as_ptr()),
+                gl::FLOAT_VEC2 => gl::ProgramUniform2fv(self.program_id, uniform.location, 1, values.as_ptr()),
+                gl::FLOAT_VEC3 => gl::ProgramUniform3fv(self.program_id, uniform.location, 1, values.as_ptr()),
+                gl::FLOAT_VEC4 => gl::ProgramUniform4fv(self.program_id, uniform.location, 1, values.as_ptr()),
+                gl::FLOAT_MAT3 => gl::ProgramUniformMatrix3fv(self.program_id, uniform.location, 1, gl::FALSE, values.as_ptr()),
+                gl::FLOAT_MAT4 => gl::ProgramUniformMatrix4fv(self.program_id, uniform.location, 1, gl::FALSE, values.as_ptr()),
+                _ => { },
+            }
+        }
     }
 
     // TODO: solve duplicate code in set_uniform...
@@ -138,7 +251,7 @@ impl ProgramBuilder {
             let shader_type = ShaderType::from_ext(extension)
                 .expect("Failed to parse file extension.");
             let shader_src = std::fs::read_to_string(path)
-                .expect(&format!("Failed to read shader source. {}", shader_path));
+                .unwrap_or_else(|_| panic!("Failed to read shader source. {}", shader_path));
 
             self.compile_shader(&shader_src, shader_type)
         } else {
@@ -167,9 +280,8 @@ impl ProgramBuilder {
 
     unsafe fn check_shader_errors(&self, shader_id: u32) -> bool {
         let mut success = i32::from(gl::FALSE);
-        let mut info_log = Vec::with_capacity(512);
+        let mut info_log = vec![0u8; 512];
 
-        info_log.set_len(512 - 1);
         gl::GetShaderiv(shader_id, gl::COMPILE_STATUS, &mut success);
         if success != i32::from(gl::TRUE) {
             gl::GetShaderInfoLog(
@@ -187,9 +299,8 @@ impl ProgramBuilder {
 
     unsafe fn check_linker_errors(&self) -> bool {
         let mut success = i32::from(gl::FALSE);
-        let mut info_log = Vec::with_capacity(512);
+        let mut info_log = vec![0u8; 512];
 
-        info_log.set_len(512 - 1);
         gl::GetProgramiv(self.program_id, gl::LINK_STATUS, &mut success);
         if success != i32::from(gl::TRUE) {
             gl::GetProgramInfoLog(
@@ -0,0 +1,306 @@
+extern crate nalgebra_glm as glm;
+
+use gl;
+use gl::types::{GLint, GLsizei, GLuint};
+use image::hdr::HdrDecoder;
+
+use std::{f32::consts::PI, fmt, fs::{self, File}, io::BufReader, path::Path};
+
+use super::{camera::Camera, helpers, profiler, shaders::program::Program};
+
+#[derive(Debug)]
+pub enum CubemapError {
+    Io(std::io::Error),
+    Image(image::ImageError),
+    /// Faces have to be square and all the same size
+    FaceSize(String),
+    MissingFace(String),
+}
+
+impl fmt::Display for CubemapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CubemapError::Io(e) => e.fmt(f),
+            CubemapError::Image(e) => e.fmt(f),
+            CubemapError::FaceSize(path) => write!(f, "cubemap face {} is not square or differs in size from the first face", path),
+            CubemapError::MissingFace(path) => write!(f, "no image for cubemap face {}", path),
+        }
+    }
+}
+
+/// Floating point RGB pixels, row by row from the top left
+struct Image {
+    width: usize,
+    height: usize,
+    pixels: Vec<glm::Vec3>,
+}
+
+impl Image {
+    /// Radiance .hdr files keep their range, everything else is read as 8 bit and scaled to 0..1
+    fn load(path: &str) -> Result<Self, CubemapError> {
+        let is_hdr = Path::new(path).extension().is_some_and(|e| e.eq_ignore_ascii_case("hdr"));
+        if is_hdr {
+            let file = File::open(path).map_err(CubemapError::Io)?;
+            let decoder = HdrDecoder::new(BufReader::new(file)).map_err(CubemapError::Image)?;
+            let meta = decoder.metadata();
+            let pixels = decoder.read_image_hdr().map_err(CubemapError::Image)?;
+
+            Ok(Self {
+                width: meta.width as usize,
+                height: meta.height as usize,
+                pixels: pixels.iter().map(|p| glm::vec3(p[0], p[1], p[2])).collect(),
+            })
+        } else {
+            let image = image::open(path).map_err(CubemapError::Image)?.to_rgb();
+
+            Ok(Self {
+                width: image.width() as usize,
+                height: image.height() as usize,
+                pixels: image.pixels().map(|p| glm::vec3(p[0] as f32, p[1] as f32, p[2] as f32) / 255.0).collect(),
+            })
+        }
+    }
+
+    /// Bilinear lookup, u wraps around and v is clamped
+    fn sample(&self, u: f32, v: f32) -> glm::Vec3 {
+        let x = u * self.width as f32 - 0.5;
+        let y = (v * self.height as f32 - 0.5).clamp(0.0, (self.height - 1) as f32);
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+
+        let pixel = |x: f32, y: f32| {
+            let column = (x as i64).rem_euclid(self.width as i64) as usize;
+            let row = (y as usize).min(self.height - 1);
+            self.pixels[row * self.width + column]
+        };
+        let top = glm::lerp(&pixel(x0, y0), &pixel(x0 + 1.0, y0), fx);
+        let bottom = glm::lerp(&pixel(x0, y0 + 1.0), &pixel(x0 + 1.0, y0 + 1.0), fx);
+        glm::lerp(&top, &bottom, fy)
+    }
+}
+
+/// A cube texture, used both as the skybox and as the environment for reflections
+pub struct Cubemap {
+    pub texture_id: GLuint,
+}
+
+impl Cubemap {
+    /// A directory with right, left, top, bottom, front and back images in any format, or a single panorama image
+    pub fn load(path: &str, face_size: usize) -> Result<Self, CubemapError> {
+        if !Path::new(path).is_dir() {
+            return Cubemap::load_equirectangular(path, face_size);
+        }
+
+        let entries: Vec<_> = fs::read_dir(path).map_err(CubemapError::Io)?
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .collect();
+        let mut faces = Vec::with_capacity(6);
+        for name in &["right", "left", "top", "bottom", "front", "back"] {
+            let face = entries.iter().find(|p| p.file_stem().is_some_and(|s| s == *name))
+                .ok_or_else(|| CubemapError::MissingFace(format!("{}/{}", path, name)))?;
+            faces.push(face.to_string_lossy().into_owned());
+        }
+
+        Cubemap::load_faces(&[&faces[0], &faces[1], &faces[2], &faces[3], &faces[4], &faces[5]])
+    }
+
+    /// Faces in the order OpenGL numbers them, +x, -x, +y, -y, +z, -z. Also known as right, left, top, bottom, front and back
+    pub fn load_faces(paths: &[&str; 6]) -> Result<Self, CubemapError> {
+        let mut faces = Vec::with_capacity(6);
+        for path in paths {
+            let face = Image::load(path)?;
+            let size = faces.first().map_or(face.width, |first: &Image| first.width);
+            if face.width != face.height || face.width != size {
+                return Err(CubemapError::FaceSize(path.to_string()));
+            }
+            faces.push(face);
+        }
+
+        let size = faces[0].width;
+        Ok(Cubemap::from_faces(size, |face, _| faces[face].pixels.clone()))
+    }
+
+    /// Converts a panorama with longitude along x and latitude along y, like most .hdr sky images, into faces of face_size pixels
+    pub fn load_equirectangular(path: &str, face_size: usize) -> Result<Self, CubemapError> {
+        let image = Image::load(path)?;
+
+        Ok(Cubemap::from_faces(face_size, |_, directions| {
+            directions.iter().map(|d| {
+                let longitude = d.x.atan2(-d.z);
+                let latitude = d.y.clamp(-1.0, 1.0).asin();
+                image.sample(longitude / (2.0 * PI) + 0.5, 0.5 - latitude / PI)
+            }).collect()
+        }))
+    }
+
+    /// Sky fading from the horizon color to the zenith color above, and to the ground color below. Needs no files
+    pub fn gradient(size: usize, zenith: glm::Vec3, horizon: glm::Vec3, ground: glm::Vec3) -> Self {
+        Cubemap::from_faces(size, |_, directions| {
+            directions.iter().map(|d| {
+                // The square root keeps the horizon band narrow
+                if d.y >= 0.0 {
+                    glm::lerp(&horizon, &zenith, d.y.sqrt())
+                } else {
+                    glm::lerp(&horizon, &ground, (-d.y).sqrt())
+                }
+            }).collect()
+        })
+    }
+
+    /// World direction through the center of every pixel of a face, following the face orientations in the OpenGL spec
+    fn face_directions(face: usize, size: usize) -> Vec<glm::Vec3> {
+        let mut directions = Vec::with_capacity(size * size);
+        for row in 0..size {
+            for column in 0..size {
+                let u = 2.0 * (column as f32 + 0.5) / size as f32 - 1.0;
+                let v = 2.0 * (row as f32 + 0.5) / size as f32 - 1.0;
+                let direction = match face {
+                    0 => glm::vec3(1.0, -v, -u),
+                    1 => glm::vec3(-1.0, -v, u),
+                    2 => glm::vec3(u, 1.0, v),
+                    3 => glm::vec3(u, -1.0, -v),
+                    4 => glm::vec3(u, -v, 1.0),
+                    _ => glm::vec3(-u, -v, -1.0),
+                };
+                directions.push(glm::normalize(&direction));
+            }
+        }
+
+        directions
+    }
+
+    /// face_pixels gets the face index and the direction of each of its pixels, and returns their colors
+    fn from_faces(size: usize, mut face_pixels: impl FnMut(usize, &[glm::Vec3]) -> Vec<glm::Vec3>) -> Self {
+        let mut texture_id = 0;
+        unsafe {
+            gl::GenTextures(1, &mut texture_id);
+            gl::BindTexture(gl::TEXTURE_CUBE_MAP, texture_id);
+
+            for face in 0..6 {
+                let pixels = face_pixels(face, &Cubemap::face_directions(face, size));
+                let floats: Vec<f32> = pixels.iter().flat_map(|p| [p.x, p.y, p.z]).collect();
+                gl::TexImage2D(
+                    gl::TEXTURE_CUBE_MAP_POSITIVE_X + face as u32, 0, gl::RGB16F as GLint,
+                    size as GLsizei, size as GLsizei, 0,
+                    gl::RGB, gl::FLOAT, helpers::array_to_c_void(&floats)
+                );
+                profiler::record_upload(helpers::byte_size_of_array(&floats) as usize);
+            }
+
+            gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_MIN_FILTER, gl::LINEAR_MIPMAP_LINEAR as GLint);
+            gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
+            for wrap in &[gl::TEXTURE_WRAP_S, gl::TEXTURE_WRAP_T, gl::TEXTURE_WRAP_R] {
+                gl::TexParameteri(gl::TEXTURE_CUBE_MAP, *wrap, gl::CLAMP_TO_EDGE as GLint);
+            }
+            // Filter across face edges, otherwise the seams show in blurry mip levels
+            gl::Enable(gl::TEXTURE_CUBE_MAP_SEAMLESS);
+            gl::GenerateMipmap(gl::TEXTURE_CUBE_MAP);
+            gl::BindTexture(gl::TEXTURE_CUBE_MAP, 0);
+        }
+
+        Self {
+            texture_id,
+        }
+    }
+
+    /// Binds to a texture unit, leaves unit 0 active
+    pub fn bind(&self, unit: u32) {
+        unsafe {
+            gl::ActiveTexture(gl::TEXTURE0 + unit);
+            gl::BindTexture(gl::TEXTURE_CUBE_MAP, self.texture_id);
+            gl::ActiveTexture(gl::TEXTURE0);
+        }
+    }
+}
+
+impl Drop for Cubemap {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteTextures(1, &self.texture_id);
+        }
+    }
+}
+
+/// Draws a cubemap behind everything else. Expects a program like assets/shaders/skybox.vert with a sky uniform
+/// for the camera rotation and projection, and the cubemap on texture unit 0
+pub struct Skybox {
+    program: Program,
+    vao_id: GLuint,
+    vbo_id: GLuint,
+}
+
+impl Skybox {
+    pub fn new(mut program: Program) -> Self {
+        if let Err(e) = program.locate_uniform("sky") {
+            eprintln!("Failed to find sky uniform in skybox shader, e: {}", e);
+        }
+
+        // Unit cube, culling is off while it is drawn so the winding does not matter
+        let corner = |i: usize| [
+            if i & 1 == 0 { -1.0 } else { 1.0 },
+            if i & 2 == 0 { -1.0 } else { 1.0 },
+            if i & 4 == 0 { -1.0 } else { 1.0 },
+        ];
+        let quads: [[usize; 4]; 6] = [
+            [1, 3, 7, 5], [0, 4, 6, 2],
+            [2, 6, 7, 3], [0, 1, 5, 4],
+            [4, 5, 7, 6], [0, 2, 3, 1],
+        ];
+        let vertices: Vec<f32> = quads.iter()
+            .flat_map(|q| [q[0], q[2], q[1], q[0], q[3], q[2]])
+            .flat_map(corner)
+            .collect();
+
+        let (mut vao_id, mut vbo_id) = (0, 0);
+        unsafe {
+            gl::GenVertexArrays(1, &mut vao_id);
+            gl::BindVertexArray(vao_id);
+            gl::GenBuffers(1, &mut vbo_id);
+            gl::BindBuffer(gl::ARRAY_BUFFER, vbo_id);
+            gl::BufferData(gl::ARRAY_BUFFER, helpers::byte_size_of_array(&vertices), helpers::array_to_c_void(&vertices), gl::STATIC_DRAW);
+            gl::EnableVertexAttribArray(0);
+            gl::VertexAttribPointer(0, 3, gl::FLOAT, gl::FALSE, 3 * helpers::size_of::<f32>(), helpers::offset::<f32>(0));
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+            gl::BindVertexArray(0);
+        }
+
+        Self {
+            program,
+            vao_id,
+            vbo_id,
+        }
+    }
+
+    /// Draw first, the sky is placed at the far plane and writes no depth so the scene covers it
+    pub fn draw(&self, camera: &Camera, cubemap: &Cubemap) {
+        let sky = camera.sky_view_projection();
+        if let Err(e) = self.program.set_uniform_matrix("sky", sky.as_ptr(), gl::UniformMatrix4fv) {
+            eprintln!("Failed to set sky matrix, e: {}", e);
+        }
+
+        cubemap.bind(0);
+        unsafe {
+            gl::DepthFunc(gl::LEQUAL);
+            gl::DepthMask(gl::FALSE);
+            gl::Disable(gl::CULL_FACE);
+            gl::UseProgram(self.program.program_id);
+            gl::BindVertexArray(self.vao_id);
+            gl::DrawArrays(gl::TRIANGLES, 0, 36);
+            gl::BindVertexArray(0);
+            gl::UseProgram(0);
+            gl::Enable(gl::CULL_FACE);
+            gl::DepthMask(gl::TRUE);
+            gl::DepthFunc(gl::LESS);
+        }
+    }
+}
+
+impl Drop for Skybox {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteBuffers(1, &self.vbo_id);
+            gl::DeleteVertexArrays(1, &self.vao_id);
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use gl;
+use gl::types::GLuint;
+
+use super::{helpers, profiler};
+
+/// Array buffer that is filled again every frame, for vertices collected on the cpu like debug lines and text
+pub struct StreamBuffer {
+    id: GLuint,
+    /// Floats the buffer has room for
+    capacity: usize,
+}
+
+impl StreamBuffer {
+    pub fn new() -> Self {
+        let mut id = 0;
+        unsafe {
+            gl::GenBuffers(1, &mut id);
+        }
+
+        Self {
+            id,
+            capacity: 0,
+        }
+    }
+
+    pub fn id(&self) -> GLuint {
+        self.id
+    }
+
+    /// Replaces the contents of the buffer with data
+    pub fn upload(&mut self, data: &[f32]) {
+        if data.is_empty() {
+            return;
+        }
+
+        unsafe {
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.id);
+            // Grow by doubling, otherwise orphan the old storage so the driver does not wait for the last frame's draw
+            if data.len() > self.capacity {
+                self.capacity = data.len().next_power_of_two();
+            }
+            let capacity_bytes = (self.capacity * std::mem::size_of::<f32>()) as isize;
+            gl::BufferData(gl::ARRAY_BUFFER, capacity_bytes, std::ptr::null(), gl::STREAM_DRAW);
+            gl::BufferSubData(gl::ARRAY_BUFFER, 0, helpers::byte_size_of_array(data), helpers::array_to_c_void(data));
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        }
+        profiler::record_upload(helpers::byte_size_of_array(data) as usize);
+    }
+}
+
+impl Default for StreamBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for StreamBuffer {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteBuffers(1, &self.id);
+        }
+    }
+}
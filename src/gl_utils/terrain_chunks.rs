@@ -0,0 +1,293 @@
+extern crate nalgebra_glm as glm;
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{mpsc, Arc},
+    thread::{self, JoinHandle},
+};
+
+use super::{geometric_object::GeometricObject, height_grid::{Ground, RayHit}, heightmap::TerrainBuilder, mesh::Mesh, render_queue::RenderQueue};
+
+/// World height at an x and z, shared with the worker thread
+pub type HeightFn = Arc<dyn Fn(f32, f32) -> f32 + Send + Sync>;
+
+/// Chunk position in the grid, chunk (0, 0) starts at the origin and grows along +x and +z
+pub type ChunkCoord = (i32, i32);
+
+#[derive(Debug, Clone, Copy)]
+pub struct ChunkSettings {
+    /// Width and depth of a chunk
+    pub chunk_size: f32,
+    /// Quads along each side of a chunk at level 0, halved for every level after
+    pub resolution: usize,
+    pub lod_levels: u32,
+    /// Chunks closer than this use level 0, the distance doubles for every level after
+    pub lod_distance: f32,
+    /// Chunks are loaded within this distance of the camera
+    pub view_distance: f32,
+    /// How far the skirts hang below the chunk edges, they hide the cracks between levels
+    pub skirt_depth: f32,
+    /// Finished chunks uploaded per update, spreads the cost over frames
+    pub uploads_per_update: usize,
+    /// Chunks requested from the worker and not back yet, nearest first
+    pub max_pending: usize,
+    pub color: [f32; 4],
+}
+
+impl Default for ChunkSettings {
+    fn default() -> Self {
+        Self {
+            chunk_size: 64.0,
+            resolution: 32,
+            lod_levels: 4,
+            lod_distance: 96.0,
+            view_distance: 600.0,
+            skirt_depth: 4.0,
+            uploads_per_update: 4,
+            max_pending: 16,
+            color: [1.0, 1.0, 1.0, 1.0],
+        }
+    }
+}
+
+impl ChunkSettings {
+    /// Level of detail for a chunk at a distance from the camera, 0 is the finest
+    pub fn lod_for(&self, distance: f32) -> u32 {
+        let mut lod = 0;
+        let mut limit = self.lod_distance;
+        while distance >= limit && lod + 1 < self.lod_levels {
+            lod += 1;
+            limit *= 2.0;
+        }
+        lod
+    }
+
+    fn chunk_center(&self, coord: ChunkCoord) -> glm::Vec2 {
+        glm::vec2(coord.0 as f32 + 0.5, coord.1 as f32 + 0.5) * self.chunk_size
+    }
+}
+
+struct Chunk {
+    lod: u32,
+    geometry: GeometricObject,
+}
+
+/// Terrain split into square chunks that load around the camera and get coarser with distance.
+/// Meshes are built on a worker thread, update uploads them on the thread with the OpenGL context
+pub struct ChunkedTerrain {
+    settings: ChunkSettings,
+    program_id: u32,
+    height: HeightFn,
+    chunks: HashMap<ChunkCoord, Chunk>,
+    pending: HashSet<(ChunkCoord, u32)>,
+    requests: Option<mpsc::Sender<(ChunkCoord, u32)>>,
+    results: mpsc::Receiver<(ChunkCoord, u32, Mesh)>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl ChunkedTerrain {
+    pub fn new(program_id: u32, settings: ChunkSettings, height: HeightFn) -> Self {
+        let (requests, request_receiver) = mpsc::channel::<(ChunkCoord, u32)>();
+        let (result_sender, results) = mpsc::channel();
+
+        let worker_height = Arc::clone(&height);
+        let worker = thread::spawn(move || {
+            // Ends when the terrain is dropped and the request channel closes
+            for (coord, lod) in request_receiver {
+                let mesh = build_chunk(&settings, &*worker_height, coord, lod);
+                if result_sender.send((coord, lod, mesh)).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Self {
+            settings,
+            program_id,
+            height,
+            chunks: HashMap::new(),
+            pending: HashSet::new(),
+            requests: Some(requests),
+            results,
+            worker: Some(worker),
+        }
+    }
+
+    pub fn loaded_chunks(&self) -> usize {
+        self.chunks.len()
+    }
+
+    pub fn pending_chunks(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Uploads finished chunks, then requests and unloads chunks for the camera position. Call once per frame
+    pub fn update(&mut self, camera_position: &glm::Vec3) {
+        let camera = camera_position.xz();
+        let settings = self.settings;
+        let wanted_lod = |coord: ChunkCoord| {
+            let distance = glm::distance(&settings.chunk_center(coord), &camera);
+            if distance <= settings.view_distance { Some(settings.lod_for(distance)) } else { None }
+        };
+
+        for _ in 0..self.settings.uploads_per_update {
+            let (coord, lod, mesh) = match self.results.try_recv() {
+                Ok(result) => result,
+                Err(_) => break,
+            };
+            self.pending.remove(&(coord, lod));
+            // The camera may have moved on while the chunk was being built
+            if wanted_lod(coord) == Some(lod) {
+                let geometry = mesh.into_geomtric_object(self.program_id, &[glm::identity()]);
+                self.chunks.insert(coord, Chunk {
+                    lod,
+                    geometry,
+                });
+            }
+        }
+
+        // Old chunks stay until their replacement arrives, so nothing pops out while levels change
+        let reach = (self.settings.view_distance / self.settings.chunk_size).ceil() as i32 + 1;
+        let center = (
+            (camera.x / self.settings.chunk_size).floor() as i32,
+            (camera.y / self.settings.chunk_size).floor() as i32,
+        );
+        let mut missing = Vec::new();
+        for z in center.1 - reach..=center.1 + reach {
+            for x in center.0 - reach..=center.0 + reach {
+                let coord = (x, z);
+                let lod = match wanted_lod(coord) {
+                    Some(lod) => lod,
+                    None => continue,
+                };
+                let loaded = self.chunks.get(&coord).map(|c| c.lod);
+                if loaded != Some(lod) && !self.pending.contains(&(coord, lod)) {
+                    let distance = glm::distance2(&self.settings.chunk_center(coord), &camera);
+                    missing.push((distance, coord, lod));
+                }
+            }
+        }
+        missing.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let free = self.settings.max_pending.saturating_sub(self.pending.len());
+        if let Some(requests) = &self.requests {
+            for &(_, coord, lod) in missing.iter().take(free) {
+                if requests.send((coord, lod)).is_ok() {
+                    self.pending.insert((coord, lod));
+                }
+            }
+        }
+
+        // A chunk of margin, so chunks on the edge don't load and unload while the camera moves back and forth
+        let unload_distance = self.settings.view_distance + self.settings.chunk_size;
+        self.chunks.retain(|&coord, _| glm::distance(&settings.chunk_center(coord), &camera) <= unload_distance);
+    }
+
+    pub fn queue_all(&self, queue: &mut RenderQueue) {
+        for chunk in self.chunks.values() {
+            chunk.geometry.queue_all(queue);
+        }
+    }
+}
+
+// Answered by the height function, so it covers all of the terrain and not only the chunks that are loaded
+impl Ground for ChunkedTerrain {
+    fn height_at(&self, x: f32, z: f32) -> Option<f32> {
+        Some((self.height)(x, z))
+    }
+
+    /// Marches along the ray in steps of a level 0 quad, then bisects the step where it crosses the surface
+    fn raycast(&self, origin: &glm::Vec3, direction: &glm::Vec3, max_distance: f32) -> Option<RayHit> {
+        let direction = glm::normalize(direction);
+        let step = self.settings.chunk_size / self.settings.resolution as f32;
+        let above = |distance: f32| {
+            let p = origin + direction * distance;
+            p.y - (self.height)(p.x, p.z)
+        };
+
+        let start_above = above(0.0);
+        let mut previous = 0.0;
+        let mut distance = 0.0;
+        while distance < max_distance {
+            distance = (distance + step).min(max_distance);
+            if (above(distance) > 0.0) == (start_above > 0.0) {
+                previous = distance;
+                continue;
+            }
+
+            let (mut low, mut high) = (previous, distance);
+            for _ in 0..16 {
+                let middle = (low + high) * 0.5;
+                if (above(middle) > 0.0) == (start_above > 0.0) { low = middle } else { high = middle }
+            }
+            let point = origin + direction * high;
+            let slope = |dx: f32, dz: f32| ((self.height)(point.x + dx, point.z + dz) - (self.height)(point.x - dx, point.z - dz)) / (2.0 * step);
+            let normal = glm::normalize(&glm::vec3(-slope(step, 0.0), 1.0, -slope(0.0, step)));
+            return Some(RayHit {
+                distance: high,
+                point,
+                normal: if glm::dot(&normal, &direction) > 0.0 { -normal } else { normal },
+            });
+        }
+
+        None
+    }
+}
+
+impl Drop for ChunkedTerrain {
+    fn drop(&mut self) {
+        // Closing the channel stops the worker after the chunk it is on
+        self.requests = None;
+        if let Some(worker) = self.worker.take() {
+            if worker.join().is_err() {
+                eprintln!("Terrain worker panicked");
+            }
+        }
+    }
+}
+
+fn build_chunk(settings: &ChunkSettings, height: &(dyn Fn(f32, f32) -> f32 + Send + Sync), coord: ChunkCoord, lod: u32) -> Mesh {
+    let quads = (settings.resolution >> lod).max(1);
+    let center = settings.chunk_center(coord);
+    let mut mesh = TerrainBuilder::new()
+        .size(settings.chunk_size, settings.chunk_size)
+        .origin(center.x, center.y)
+        .resolution(quads + 1, quads + 1)
+        .color(settings.color)
+        .build_from_fn(height);
+    add_skirt(&mut mesh, quads + 1, settings.skirt_depth);
+    mesh
+}
+
+/// Hangs a strip of triangles from the border of a square grid mesh with side vertices per side.
+/// Neighbours at another level of detail only share some border vertices, the skirt covers the gaps in between
+fn add_skirt(mesh: &mut Mesh, side: usize, depth: f32) {
+    let index = |row: usize, column: usize| (row * side + column) as u32;
+    // Around the border in one direction, starting at the corner with the lowest x and z
+    let border: Vec<u32> = (0..side - 1).map(|c| index(0, c))
+        .chain((0..side - 1).map(|r| index(r, side - 1)))
+        .chain((1..side).rev().map(|c| index(side - 1, c)))
+        .chain((1..side).rev().map(|r| index(r, 0)))
+        .collect();
+
+    let first_lowered = (mesh.vertices.len() / 3) as u32;
+    for &i in &border {
+        let i = i as usize;
+        mesh.vertices.extend_from_slice(&[mesh.vertices[i * 3], mesh.vertices[i * 3 + 1] - depth, mesh.vertices[i * 3 + 2]]);
+        mesh.normals.extend_from_slice(&[mesh.normals[i * 3], mesh.normals[i * 3 + 1], mesh.normals[i * 3 + 2]]);
+        mesh.uvs.extend_from_slice(&[mesh.uvs[i * 2], mesh.uvs[i * 2 + 1]]);
+        if !mesh.tangents.is_empty() {
+            mesh.tangents.extend_from_slice(&[mesh.tangents[i * 4], mesh.tangents[i * 4 + 1], mesh.tangents[i * 4 + 2], mesh.tangents[i * 4 + 3]]);
+        }
+        mesh.colors.extend_from_slice(&[mesh.colors[i * 4], mesh.colors[i * 4 + 1], mesh.colors[i * 4 + 2], mesh.colors[i * 4 + 3]]);
+    }
+
+    // Facing outwards, the same winding as the surface seen from outside
+    for k in 0..border.len() {
+        let next = (k + 1) % border.len();
+        let (a, b) = (border[k], border[next]);
+        let (a_low, b_low) = (first_lowered + k as u32, first_lowered + next as u32);
+        mesh.indices.extend_from_slice(&[a, b, b_low, a, b_low, a_low]);
+    }
+    mesh.index_count = mesh.indices.len() as i32;
+}
@@ -0,0 +1,293 @@
+use gl;
+use gl::types::{GLsizei, GLuint};
+use rusttype::{point, Font, Scale};
+
+use std::{collections::HashMap, fmt, fs, io};
+
+use super::{helpers, shaders::{errors::ShaderProgramError, program::Program}, stream_buffer::StreamBuffer};
+
+/// Which point of the text block is placed at the given position
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Anchor {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+impl Anchor {
+    /// Fraction of the block size to move left and up
+    fn offset(self) -> (f32, f32) {
+        let x = match self {
+            Anchor::TopLeft | Anchor::Left | Anchor::BottomLeft => 0.0,
+            Anchor::Top | Anchor::Center | Anchor::Bottom => 0.5,
+            Anchor::TopRight | Anchor::Right | Anchor::BottomRight => 1.0,
+        };
+        let y = match self {
+            Anchor::TopLeft | Anchor::Top | Anchor::TopRight => 0.0,
+            Anchor::Left | Anchor::Center | Anchor::Right => 0.5,
+            Anchor::BottomLeft | Anchor::Bottom | Anchor::BottomRight => 1.0,
+        };
+
+        (x, y)
+    }
+}
+
+#[derive(Debug)]
+pub enum TextError {
+    Io(io::Error),
+    InvalidFont,
+    Shader(ShaderProgramError),
+}
+
+impl fmt::Display for TextError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TextError::Io(e) => e.fmt(f),
+            TextError::InvalidFont => write!(f, "not a TrueType or OpenType font"),
+            TextError::Shader(e) => e.fmt(f),
+        }
+    }
+}
+
+/// Where a glyph is in the atlas and how to place it, in pixels at the rasterized size
+#[derive(Debug, Clone, Copy)]
+struct Glyph {
+    /// Texture coordinates of the top left and bottom right corner
+    uv_min: (f32, f32),
+    uv_max: (f32, f32),
+    size: (f32, f32),
+    /// From the pen position on the baseline to the top left corner of the bitmap
+    offset: (f32, f32),
+    advance: f32,
+}
+
+/// Screen space text from a glyph atlas. Strings are collected from any pass and drawn in one call by draw, which also clears them.
+/// Positions are in pixels from the top left corner of the window
+pub struct TextRenderer {
+    program: Program,
+    texture_id: GLuint,
+    vao_id: GLuint,
+    buffer: StreamBuffer,
+    glyphs: HashMap<char, Glyph>,
+    ascent: f32,
+    line_height: f32,
+    /// Interleaved position, texture coordinate and color, 8 floats per vertex and 6 vertices per glyph
+    vertices: Vec<f32>,
+    screen_size: (f32, f32),
+}
+
+impl TextRenderer {
+    const FLOATS_PER_VERTEX: usize = 8;
+    const ATLAS_WIDTH: usize = 512;
+    /// Glyphs are padded so linear filtering does not bleed between neighbours in the atlas
+    const PADDING: usize = 2;
+    /// Printable ASCII, anything else is drawn as FALLBACK
+    const CHARACTERS: std::ops::RangeInclusive<char> = ' '..='~';
+    const FALLBACK: char = '?';
+
+    /// Rasterizes the font at pixel_height. The program must have a screen_size uniform, like assets/shaders/text.vert
+    pub fn load(path: &str, pixel_height: f32, mut program: Program) -> Result<Self, TextError> {
+        let bytes = fs::read(path).map_err(TextError::Io)?;
+        let font = Font::try_from_vec(bytes).ok_or(TextError::InvalidFont)?;
+        program.locate_uniform("screen_size").map_err(TextError::Shader)?;
+
+        let scale = Scale::uniform(pixel_height);
+        let v_metrics = font.v_metrics(scale);
+
+        // Pack the glyphs into rows, the atlas height is known once all rows are placed
+        let mut placed = Vec::new();
+        let (mut x, mut y, mut row_height) = (TextRenderer::PADDING, TextRenderer::PADDING, 0);
+        for c in TextRenderer::CHARACTERS {
+            let glyph = font.glyph(c).scaled(scale).positioned(point(0.0, 0.0));
+            let advance = glyph.unpositioned().h_metrics().advance_width;
+            let bounds = glyph.pixel_bounding_box();
+            let (width, height) = bounds.map_or((0, 0), |b| (b.width() as usize, b.height() as usize));
+
+            if x + width + TextRenderer::PADDING > TextRenderer::ATLAS_WIDTH {
+                x = TextRenderer::PADDING;
+                y += row_height + TextRenderer::PADDING;
+                row_height = 0;
+            }
+            placed.push((c, glyph, advance, bounds, x, y, width, height));
+            x += width + TextRenderer::PADDING;
+            row_height = row_height.max(height);
+        }
+        let atlas_height = (y + row_height + TextRenderer::PADDING).next_power_of_two();
+
+        let mut pixels = vec![0u8; TextRenderer::ATLAS_WIDTH * atlas_height];
+        let mut glyphs = HashMap::new();
+        let (atlas_width, atlas_height_f) = (TextRenderer::ATLAS_WIDTH as f32, atlas_height as f32);
+        for (c, glyph, advance, bounds, x, y, width, height) in placed {
+            glyph.draw(|gx, gy, coverage| {
+                pixels[(y + gy as usize) * TextRenderer::ATLAS_WIDTH + x + gx as usize] = (coverage * 255.0) as u8;
+            });
+            let offset = bounds.map_or((0.0, 0.0), |b| (b.min.x as f32, b.min.y as f32));
+            glyphs.insert(c, Glyph {
+                uv_min: (x as f32 / atlas_width, y as f32 / atlas_height_f),
+                uv_max: ((x + width) as f32 / atlas_width, (y + height) as f32 / atlas_height_f),
+                size: (width as f32, height as f32),
+                offset,
+                advance,
+            });
+        }
+
+        let mut texture_id = 0;
+        let mut vao_id = 0;
+        let buffer = StreamBuffer::new();
+        unsafe {
+            gl::GenTextures(1, &mut texture_id);
+            gl::BindTexture(gl::TEXTURE_2D, texture_id);
+            // Rows of a single channel texture are not 4 byte aligned
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+            gl::TexImage2D(
+                gl::TEXTURE_2D,
+                0,
+                gl::R8 as i32,
+                TextRenderer::ATLAS_WIDTH as GLsizei,
+                atlas_height as GLsizei,
+                0,
+                gl::RED,
+                gl::UNSIGNED_BYTE,
+                helpers::array_to_c_void(&pixels)
+            );
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+
+            gl::GenVertexArrays(1, &mut vao_id);
+            gl::BindVertexArray(vao_id);
+            gl::BindBuffer(gl::ARRAY_BUFFER, buffer.id());
+
+            let stride = (TextRenderer::FLOATS_PER_VERTEX * std::mem::size_of::<f32>()) as GLsizei;
+            gl::EnableVertexAttribArray(0);
+            gl::VertexAttribPointer(0, 2, gl::FLOAT, gl::FALSE, stride, helpers::offset::<f32>(0));
+            gl::EnableVertexAttribArray(1);
+            gl::VertexAttribPointer(1, 2, gl::FLOAT, gl::FALSE, stride, helpers::offset::<f32>(2));
+            gl::EnableVertexAttribArray(2);
+            gl::VertexAttribPointer(2, 4, gl::FLOAT, gl::FALSE, stride, helpers::offset::<f32>(4));
+
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+            gl::BindVertexArray(0);
+        }
+
+        Ok(Self {
+            program,
+            texture_id,
+            vao_id,
+            buffer,
+            glyphs,
+            ascent: v_metrics.ascent,
+            line_height: v_metrics.ascent - v_metrics.descent + v_metrics.line_gap,
+            vertices: Vec::new(),
+            screen_size: (1.0, 1.0),
+        })
+    }
+
+    /// Size of the window in pixels, has to be set before the first draw and whenever the window is resized
+    pub fn set_screen_size(&mut self, width: f32, height: f32) {
+        self.screen_size = (width, height);
+    }
+
+    fn glyph(&self, c: char) -> Option<&Glyph> {
+        self.glyphs.get(&c).or_else(|| self.glyphs.get(&TextRenderer::FALLBACK))
+    }
+
+    /// Width and height in pixels of text drawn at scale, lines are split on '\n'
+    pub fn measure(&self, text: &str, scale: f32) -> (f32, f32) {
+        let width = text.lines()
+            .map(|line| line.chars().filter_map(|c| self.glyph(c)).map(|g| g.advance).sum::<f32>())
+            .fold(0.0, f32::max);
+        let lines = text.lines().count().max(1);
+
+        (width * scale, lines as f32 * self.line_height * scale)
+    }
+
+    /// Queues text with anchor placed at position. A scale of 1 draws at the height the font was loaded at
+    pub fn text(&mut self, text: &str, position: (f32, f32), anchor: Anchor, scale: f32, color: [f32; 4]) {
+        let (width, height) = self.measure(text, scale);
+        let (ax, ay) = anchor.offset();
+        // Whole pixels keep the glyphs sharp at scale 1
+        let left = (position.0 - width * ax).round();
+        let top = (position.1 - height * ay).round();
+
+        for (row, line) in text.lines().enumerate() {
+            let baseline = top + (self.ascent + row as f32 * self.line_height) * scale;
+            let mut pen = left;
+            for c in line.chars() {
+                let glyph = match self.glyph(c) {
+                    Some(glyph) => *glyph,
+                    None => continue,
+                };
+
+                if glyph.size.0 > 0.0 {
+                    let x0 = pen + glyph.offset.0 * scale;
+                    let y0 = baseline + glyph.offset.1 * scale;
+                    let x1 = x0 + glyph.size.0 * scale;
+                    let y1 = y0 + glyph.size.1 * scale;
+                    let (u0, v0) = glyph.uv_min;
+                    let (u1, v1) = glyph.uv_max;
+                    for &(x, y, u, v) in &[(x0, y0, u0, v0), (x0, y1, u0, v1), (x1, y1, u1, v1), (x0, y0, u0, v0), (x1, y1, u1, v1), (x1, y0, u1, v0)] {
+                        self.vertices.extend_from_slice(&[x, y, u, v]);
+                        self.vertices.extend_from_slice(&color);
+                    }
+                }
+                pen += glyph.advance * scale;
+            }
+        }
+    }
+
+    /// Draws and clears all queued text on top of everything
+    pub fn draw(&mut self) {
+        let count = self.vertices.len() / TextRenderer::FLOATS_PER_VERTEX;
+        if count == 0 {
+            return;
+        }
+
+        let (width, height) = self.screen_size;
+        if let Err(e) = self.program.set_uniform1("screen_size", (width, height), |location, (w, h)| unsafe { gl::Uniform2f(location, w, h) }) {
+            eprintln!("Failed to set text screen size, e: {}", e);
+        }
+
+        self.buffer.upload(&self.vertices);
+        unsafe {
+            gl::Disable(gl::DEPTH_TEST);
+            gl::Disable(gl::CULL_FACE);
+            gl::Enable(gl::BLEND);
+            gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+
+            gl::UseProgram(self.program.program_id);
+            gl::ActiveTexture(gl::TEXTURE0);
+            gl::BindTexture(gl::TEXTURE_2D, self.texture_id);
+            gl::BindVertexArray(self.vao_id);
+            gl::DrawArrays(gl::TRIANGLES, 0, count as GLsizei);
+            gl::BindVertexArray(0);
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+            gl::UseProgram(0);
+
+            gl::Disable(gl::BLEND);
+            gl::Enable(gl::CULL_FACE);
+            gl::Enable(gl::DEPTH_TEST);
+        }
+
+        self.vertices.clear();
+    }
+}
+
+impl Drop for TextRenderer {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteTextures(1, &self.texture_id);
+            gl::DeleteVertexArrays(1, &self.vao_id);
+        }
+    }
+}
@@ -1,58 +1,269 @@
 use gl::types;
 
+/// Type of each component of a vertex attribute in the buffer
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ComponentFormat {
+    F32,
+    /// Half float, see f16_bits
+    F16,
+    I32,
+    U32,
+    I16,
+    U16,
+    I8,
+    U8,
+}
+
+impl ComponentFormat {
+    /// Bytes per component
+    pub fn size(self) -> usize {
+        match self {
+            ComponentFormat::F32 | ComponentFormat::I32 | ComponentFormat::U32 => 4,
+            ComponentFormat::F16 | ComponentFormat::I16 | ComponentFormat::U16 => 2,
+            ComponentFormat::I8 | ComponentFormat::U8 => 1,
+        }
+    }
+
+    pub fn gl_type(self) -> types::GLenum {
+        match self {
+            ComponentFormat::F32 => gl::FLOAT,
+            ComponentFormat::F16 => gl::HALF_FLOAT,
+            ComponentFormat::I32 => gl::INT,
+            ComponentFormat::U32 => gl::UNSIGNED_INT,
+            ComponentFormat::I16 => gl::SHORT,
+            ComponentFormat::U16 => gl::UNSIGNED_SHORT,
+            ComponentFormat::I8 => gl::BYTE,
+            ComponentFormat::U8 => gl::UNSIGNED_BYTE,
+        }
+    }
+
+    /// Appends value in this format. Normalized integers map -1..1, or 0..1 when unsigned, to their full range
+    fn write(self, value: f32, normalized: bool, out: &mut Vec<u8>) {
+        // Rounded and saturated to the range of the type
+        let scaled = |max: f32, min: f32| if normalized { (value * max).round().clamp(min, max) } else { value.round().clamp(min, max) };
+        match self {
+            ComponentFormat::F32 => out.extend_from_slice(&value.to_le_bytes()),
+            ComponentFormat::F16 => out.extend_from_slice(&f16_bits(value).to_le_bytes()),
+            ComponentFormat::I32 => out.extend_from_slice(&(scaled(i32::MAX as f32, i32::MIN as f32) as i32).to_le_bytes()),
+            ComponentFormat::U32 => out.extend_from_slice(&(scaled(u32::MAX as f32, 0.0) as u32).to_le_bytes()),
+            ComponentFormat::I16 => out.extend_from_slice(&(scaled(i16::MAX as f32, i16::MIN as f32) as i16).to_le_bytes()),
+            ComponentFormat::U16 => out.extend_from_slice(&(scaled(u16::MAX as f32, 0.0) as u16).to_le_bytes()),
+            ComponentFormat::I8 => out.push(scaled(i8::MAX as f32, i8::MIN as f32) as i8 as u8),
+            ComponentFormat::U8 => out.push(scaled(u8::MAX as f32, 0.0) as u8),
+        }
+    }
+}
+
+/// How the shader sees the components of an attribute
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Conversion {
+    /// Floats, or integers turned into floats as they are
+    Float,
+    /// Integers mapped to 0..1, or -1..1 when signed
+    Normalized,
+    /// Integers kept as they are, for ivec and uvec inputs
+    Integer,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct VertexAttribute {
+    /// The shader location, "layout (location = index)"
+    pub index: types::GLuint,
+    pub size: types::GLint,
+    pub format: ComponentFormat,
+    pub conversion: Conversion,
+    /// Bytes from the start of the vertex
+    pub offset: usize,
+}
+
+/// How vertices are laid out in a buffer. Attributes are placed in the order they are added and aligned like
+/// the fields of a #[repr(C)] struct, so a layout can describe a Vec of such structs as well as packed bytes
+#[derive(Debug, Clone)]
+pub struct VertexLayout {
+    attributes: Vec<VertexAttribute>,
+    /// End of the last attribute or padding
+    end: usize,
+    alignment: usize,
+}
+
+impl Default for VertexLayout {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VertexLayout {
+    pub fn new() -> Self {
+        Self {
+            attributes: Vec::new(),
+            end: 0,
+            alignment: 1,
+        }
+    }
+
+    #[must_use]
+    pub fn float(self, location: types::GLuint, components: types::GLint) -> Self {
+        self.attribute(location, components, ComponentFormat::F32, Conversion::Float)
+    }
+
+    #[allow(dead_code)]
+    #[must_use]
+    pub fn half(self, location: types::GLuint, components: types::GLint) -> Self {
+        self.attribute(location, components, ComponentFormat::F16, Conversion::Float)
+    }
+
+    /// Integers the shader reads as floats between 0 and 1, or -1 and 1 when signed. Like u8x4 colors or i16x3 normals
+    #[must_use]
+    pub fn normalized(self, location: types::GLuint, components: types::GLint, format: ComponentFormat) -> Self {
+        self.attribute(location, components, format, Conversion::Normalized)
+    }
+
+    /// Integers the shader reads as integers
+    #[allow(dead_code)]
+    #[must_use]
+    pub fn integer(self, location: types::GLuint, components: types::GLint, format: ComponentFormat) -> Self {
+        self.attribute(location, components, format, Conversion::Integer)
+    }
+
+    #[must_use]
+    pub fn attribute(self, location: types::GLuint, components: types::GLint, format: ComponentFormat, conversion: Conversion) -> Self {
+        let offset = align(self.end, format.size());
+        self.attribute_at(location, components, format, conversion, offset)
+    }
+
+    /// An attribute at an explicit offset in bytes, for layouts that aren't in attribute order
+    #[must_use]
+    pub fn attribute_at(mut self, location: types::GLuint, components: types::GLint, format: ComponentFormat, conversion: Conversion, offset: usize) -> Self {
+        self.attributes.push(VertexAttribute {
+            index: location,
+            size: components,
+            format,
+            conversion,
+            offset,
+        });
+        self.end = self.end.max(offset + components as usize * format.size());
+        self.alignment = self.alignment.max(format.size());
+
+        self
+    }
+
+    /// Skips bytes, for unused struct fields or to keep the next attribute 4 byte aligned
+    #[must_use]
+    pub fn padding(mut self, bytes: usize) -> Self {
+        self.end += bytes;
+
+        self
+    }
+
+    pub fn attributes(&self) -> &[VertexAttribute] {
+        &self.attributes
+    }
+
+    /// Bytes from one vertex to the next
+    pub fn stride(&self) -> usize {
+        align(self.end, self.alignment)
+    }
+
+    /// Interleaves one stream of floats per attribute, in the order they were added, into a buffer in this layout.
+    /// Streams shorter than vertex_count are padded with zeros
+    pub fn pack(&self, vertex_count: usize, streams: &[&[f32]]) -> Vec<u8> {
+        let stride = self.stride();
+        let mut out = vec![0; vertex_count * stride];
+        let mut bytes = Vec::with_capacity(4);
+        for vertex in 0..vertex_count {
+            for (attribute, stream) in self.attributes.iter().zip(streams) {
+                let components = attribute.size as usize;
+                for component in 0..components {
+                    let value = stream.get(vertex * components + component).cloned().unwrap_or(0.0);
+                    bytes.clear();
+                    attribute.format.write(value, attribute.conversion == Conversion::Normalized, &mut bytes);
+                    let at = vertex * stride + attribute.offset + component * attribute.format.size();
+                    out[at..at + bytes.len()].copy_from_slice(&bytes);
+                }
+            }
+        }
+
+        out
+    }
+}
+
+fn align(offset: usize, alignment: usize) -> usize {
+    offset.div_ceil(alignment) * alignment
+}
+
+/// Bits of the nearest half float, values too large for it become infinity
+pub fn f16_bits(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+
+    // Infinity and NaN
+    if exponent == 0xff {
+        return sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+
+    let exponent = exponent - 127 + 15;
+    if exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+    if exponent <= 0 {
+        // Subnormal, or too small and rounded to zero
+        if exponent < -10 {
+            return sign;
+        }
+        let mantissa = mantissa | 0x80_0000;
+        let shift = (14 - exponent) as u32;
+        let round = (mantissa >> (shift - 1)) & 1;
+        return sign | ((mantissa >> shift) + round) as u16;
+    }
+
+    // A carry from rounding moves into the exponent, which is still the right result
+    let round = (mantissa >> 12) & 1;
+    sign | ((((exponent as u32) << 10) | (mantissa >> 13)) + round) as u16
+}
+
 // TODO: not really a pair
-// TODO: having attributes with different T is not really supported in the same object ... 
 pub struct VerticesAttributesPair<T> {
     pub buffer_data: Vec::<T>,
-    pub attributes: Vec<VertexAttribute>,
-    pub data_type: types::GLenum // TODO: not sure if type is always the same for all attributes in the same buffer
+    pub layout: VertexLayout,
 }
 
 impl<T> VerticesAttributesPair<T> {
-    pub fn init(buffer_data: Vec::<T>, data_type: types::GLenum) -> Self {
+    /// buffer_data laid out as described by layout, T is typically a #[repr(C)] vertex struct or packed bytes
+    pub fn with_layout(buffer_data: Vec::<T>, layout: VertexLayout) -> Self {
         Self {
             buffer_data,
-            attributes: Vec::with_capacity(2),
-            data_type
+            layout,
         }
     }
-    
-    #[must_use = "Need atleast one attribute"]
-    pub fn add_attribute(
-        mut self, 
-        id: types::GLuint, 
-        index: types::GLuint, 
-        size: types::GLint,
-        offset: u32
-    ) -> Self {
-        self.attributes.push(
-            VertexAttribute::init(id, index, size, offset)
-        );
+}
 
-        self
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn f16_bits_rounds_to_the_nearest_half_float() {
+        assert_eq!(f16_bits(1.0), 0x3c00);
+        assert_eq!(f16_bits(-2.0), 0xc000);
+        // Largest finite half
+        assert_eq!(f16_bits(65504.0), 0x7bff);
+        // Smallest subnormal half
+        assert_eq!(f16_bits(5.96e-8), 0x0001);
+        assert_eq!(f16_bits(1e6), 0x7c00);
+        assert_eq!(f16_bits(f32::NAN), 0x7e00);
     }
-}
 
-pub struct VertexAttribute {
-    pub id: types::GLuint, // TODO: id and index can probably be merged
-    pub index: types::GLuint,
-    pub size: types::GLint,
-    pub offset: u32
-}
-
-impl VertexAttribute {
-    // TODO: i'm sure this is unnecessary boilerplate, find how to remove this code. 
-    fn init(
-        id: types::GLuint,
-        index: types::GLuint, 
-        size: types::GLint,
-        offset: u32
-    ) -> Self {
-        VertexAttribute {
-            id,
-            index,
-            size,
-            offset
-        }
+    #[test]
+    fn attributes_are_aligned_like_repr_c_fields() {
+        let layout = VertexLayout::new()
+            .normalized(0, 3, ComponentFormat::U8)
+            .float(1, 2);
+        let offsets: Vec<usize> = layout.attributes().iter().map(|a| a.offset).collect();
+        assert_eq!(offsets, [0, 4]);
+        assert_eq!(layout.stride(), 12);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,333 @@
+use std::collections::HashMap;
+
+use gl::types::{GLint, GLsizei, GLuint};
+use glutin::event::{ElementState, MouseButton, VirtualKeyCode};
+
+use crate::gl_utils::{helpers, profiler, shaders::{errors::ShaderProgramError, program::Program}};
+use crate::input::InputEvent;
+
+/// egui fed from the same events as the rest of the input and painted with OpenGL on top of the frame.
+/// Expects a program like assets/shaders/gui.vert with a screen_size uniform
+pub struct Gui {
+    context: egui::Context,
+    program: Program,
+    vao_id: GLuint,
+    vbo_id: GLuint,
+    ebo_id: GLuint,
+    textures: HashMap<egui::TextureId, GLuint>,
+    /// Collected since the last run
+    events: Vec<egui::Event>,
+    modifiers: egui::Modifiers,
+    /// Cursor position in points, button events need it
+    pointer: egui::Pos2,
+    pixels_per_point: f32,
+    /// Window size in physical pixels, from the last run
+    screen_size: (f32, f32),
+    /// What the last run wants painted
+    primitives: Vec<egui::ClippedPrimitive>,
+    textures_delta: egui::TexturesDelta,
+    wants_keyboard: bool,
+    wants_pointer: bool,
+}
+
+impl Gui {
+    pub fn new(mut program: Program, pixels_per_point: f32) -> Result<Self, ShaderProgramError> {
+        program.locate_uniform("screen_size")?;
+
+        let (mut vao_id, mut vbo_id, mut ebo_id) = (0, 0, 0);
+        unsafe {
+            gl::GenVertexArrays(1, &mut vao_id);
+            gl::BindVertexArray(vao_id);
+            gl::GenBuffers(1, &mut vbo_id);
+            gl::BindBuffer(gl::ARRAY_BUFFER, vbo_id);
+            gl::GenBuffers(1, &mut ebo_id);
+            gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, ebo_id);
+
+            // egui vertices are a position and uv in floats followed by an RGBA color in bytes
+            let stride = helpers::size_of::<egui::epaint::Vertex>();
+            gl::EnableVertexAttribArray(0);
+            gl::VertexAttribPointer(0, 2, gl::FLOAT, gl::FALSE, stride, helpers::offset::<f32>(0));
+            gl::EnableVertexAttribArray(1);
+            gl::VertexAttribPointer(1, 2, gl::FLOAT, gl::FALSE, stride, helpers::offset::<f32>(2));
+            gl::EnableVertexAttribArray(2);
+            gl::VertexAttribPointer(2, 4, gl::UNSIGNED_BYTE, gl::TRUE, stride, helpers::offset::<f32>(4));
+
+            gl::BindVertexArray(0);
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+            gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, 0);
+        }
+
+        Ok(Self {
+            context: egui::Context::default(),
+            program,
+            vao_id,
+            vbo_id,
+            ebo_id,
+            textures: HashMap::new(),
+            events: Vec::new(),
+            modifiers: egui::Modifiers::default(),
+            pointer: egui::Pos2::ZERO,
+            pixels_per_point,
+            screen_size: (1.0, 1.0),
+            primitives: Vec::new(),
+            textures_delta: egui::TexturesDelta::default(),
+            wants_keyboard: false,
+            wants_pointer: false,
+        })
+    }
+
+    /// Whether the event went to the GUI and should not also reach the game. Only presses are taken,
+    /// releases always pass through so nothing is left held down
+    pub fn consumes(&self, event: &InputEvent) -> bool {
+        match event {
+            InputEvent::Key(_, ElementState::Pressed) => self.wants_keyboard,
+            InputEvent::MouseButton(ElementState::Pressed, _) => self.wants_pointer,
+            _ => false,
+        }
+    }
+
+    pub fn handle_event(&mut self, event: &InputEvent) {
+        match *event {
+            InputEvent::Key(code, state) => {
+                let pressed = state == ElementState::Pressed;
+                match code {
+                    VirtualKeyCode::LShift | VirtualKeyCode::RShift => self.modifiers.shift = pressed,
+                    VirtualKeyCode::LControl | VirtualKeyCode::RControl => {
+                        self.modifiers.ctrl = pressed;
+                        self.modifiers.command = pressed;
+                    },
+                    VirtualKeyCode::LAlt | VirtualKeyCode::RAlt => self.modifiers.alt = pressed,
+                    _ => { },
+                }
+                if let Some(key) = egui_key(code) {
+                    self.events.push(egui::Event::Key {
+                        key,
+                        physical_key: None,
+                        pressed,
+                        repeat: false,
+                        modifiers: self.modifiers,
+                    });
+                }
+            },
+            InputEvent::Character(c) => {
+                if !c.is_control() {
+                    self.events.push(egui::Event::Text(c.to_string()));
+                }
+            },
+            InputEvent::Cursor((x, y)) => {
+                self.pointer = egui::pos2(x as f32 / self.pixels_per_point, y as f32 / self.pixels_per_point);
+                self.events.push(egui::Event::PointerMoved(self.pointer));
+            },
+            InputEvent::MouseButton(state, button) => {
+                let button = match button {
+                    MouseButton::Left => egui::PointerButton::Primary,
+                    MouseButton::Right => egui::PointerButton::Secondary,
+                    MouseButton::Middle => egui::PointerButton::Middle,
+                    MouseButton::Other(_) => return,
+                };
+                self.events.push(egui::Event::PointerButton {
+                    pos: self.pointer,
+                    button,
+                    pressed: state == ElementState::Pressed,
+                    modifiers: self.modifiers,
+                });
+            },
+            InputEvent::Scroll((x, y)) => {
+                self.events.push(egui::Event::MouseWheel {
+                    unit: egui::MouseWheelUnit::Line,
+                    delta: egui::vec2(x as f32, y as f32),
+                    modifiers: self.modifiers,
+                });
+            },
+            InputEvent::Mouse(_) | InputEvent::Gamepad(_) => { },
+        }
+    }
+
+    /// Builds this frame's GUI from the events since the last run. screen_size is in physical pixels and time in seconds
+    pub fn run(&mut self, screen_size: (f32, f32), time: f64, ui: impl FnMut(&egui::Context)) {
+        self.screen_size = screen_size;
+        let mut raw_input = egui::RawInput {
+            screen_rect: Some(egui::Rect::from_min_size(
+                egui::Pos2::ZERO,
+                egui::vec2(screen_size.0, screen_size.1) / self.pixels_per_point,
+            )),
+            time: Some(time),
+            modifiers: self.modifiers,
+            events: std::mem::take(&mut self.events),
+            ..Default::default()
+        };
+        raw_input.viewports.entry(egui::ViewportId::ROOT).or_default().native_pixels_per_point = Some(self.pixels_per_point);
+
+        let output = self.context.run(raw_input, ui);
+        self.primitives = self.context.tessellate(output.shapes, output.pixels_per_point);
+        self.pixels_per_point = output.pixels_per_point;
+        self.textures_delta.append(output.textures_delta);
+        self.wants_keyboard = self.context.wants_keyboard_input();
+        self.wants_pointer = self.context.wants_pointer_input();
+    }
+
+    /// Draws the output of the last run over whatever is in the framebuffer
+    pub fn paint(&mut self) {
+        let textures_delta = std::mem::take(&mut self.textures_delta);
+        for (id, delta) in &textures_delta.set {
+            self.update_texture(*id, delta);
+        }
+
+        if !self.primitives.is_empty() {
+            let (width, height) = self.screen_size;
+            let ppp = self.pixels_per_point;
+            if let Err(e) = self.program.set_uniform1("screen_size", (width / ppp, height / ppp), |location, (w, h)| unsafe { gl::Uniform2f(location, w, h) }) {
+                eprintln!("Failed to set GUI screen size, e: {}", e);
+            }
+
+            unsafe {
+                gl::Disable(gl::DEPTH_TEST);
+                gl::Disable(gl::CULL_FACE);
+                gl::Enable(gl::SCISSOR_TEST);
+                gl::Enable(gl::BLEND);
+                gl::BlendFunc(gl::ONE, gl::ONE_MINUS_SRC_ALPHA);
+                gl::UseProgram(self.program.program_id);
+                gl::ActiveTexture(gl::TEXTURE0);
+                gl::BindVertexArray(self.vao_id);
+                gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo_id);
+            }
+
+            for primitive in &self.primitives {
+                let mesh = match &primitive.primitive {
+                    egui::epaint::Primitive::Mesh(mesh) if !mesh.indices.is_empty() => mesh,
+                    _ => continue,
+                };
+                let texture_id = match self.textures.get(&mesh.texture_id) {
+                    Some(&texture_id) => texture_id,
+                    None => continue,
+                };
+
+                // Clip rectangles are in points from the top left, the scissor box is in pixels from the bottom left
+                let clip = primitive.clip_rect;
+                let min_x = (clip.min.x * ppp).round().clamp(0.0, width);
+                let max_x = (clip.max.x * ppp).round().clamp(min_x, width);
+                let min_y = (clip.min.y * ppp).round().clamp(0.0, height);
+                let max_y = (clip.max.y * ppp).round().clamp(min_y, height);
+                if max_x <= min_x || max_y <= min_y {
+                    continue;
+                }
+
+                unsafe {
+                    gl::Scissor(min_x as GLint, (height - max_y) as GLint, (max_x - min_x) as GLsizei, (max_y - min_y) as GLsizei);
+                    gl::BindTexture(gl::TEXTURE_2D, texture_id);
+                    gl::BufferData(gl::ARRAY_BUFFER, helpers::byte_size_of_array(&mesh.vertices), helpers::array_to_c_void(&mesh.vertices), gl::STREAM_DRAW);
+                    gl::BufferData(gl::ELEMENT_ARRAY_BUFFER, helpers::byte_size_of_array(&mesh.indices), helpers::array_to_c_void(&mesh.indices), gl::STREAM_DRAW);
+                    gl::DrawElements(gl::TRIANGLES, mesh.indices.len() as GLsizei, gl::UNSIGNED_INT, std::ptr::null());
+                }
+                profiler::record_upload((helpers::byte_size_of_array(&mesh.vertices) + helpers::byte_size_of_array(&mesh.indices)) as usize);
+            }
+
+            unsafe {
+                gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+                gl::BindVertexArray(0);
+                gl::BindTexture(gl::TEXTURE_2D, 0);
+                gl::UseProgram(0);
+                gl::Disable(gl::BLEND);
+                gl::Disable(gl::SCISSOR_TEST);
+                gl::Enable(gl::CULL_FACE);
+                gl::Enable(gl::DEPTH_TEST);
+            }
+        }
+
+        for id in &textures_delta.free {
+            if let Some(texture_id) = self.textures.remove(id) {
+                unsafe {
+                    gl::DeleteTextures(1, &texture_id);
+                }
+            }
+        }
+    }
+
+    /// Creates a texture, replaces it or updates part of it
+    fn update_texture(&mut self, id: egui::TextureId, delta: &egui::epaint::ImageDelta) {
+        let pixels: Vec<u8> = match &delta.image {
+            egui::ImageData::Color(image) => image.pixels.iter().flat_map(|c| c.to_array()).collect(),
+            egui::ImageData::Font(image) => image.srgba_pixels(None).flat_map(|c| c.to_array()).collect(),
+        };
+        let [width, height] = delta.image.size();
+        let filter = |filter| match filter {
+            egui::TextureFilter::Nearest => gl::NEAREST as GLint,
+            egui::TextureFilter::Linear => gl::LINEAR as GLint,
+        };
+
+        unsafe {
+            let texture_id = *self.textures.entry(id).or_insert_with(|| {
+                let mut texture_id = 0;
+                gl::GenTextures(1, &mut texture_id);
+                texture_id
+            });
+            gl::BindTexture(gl::TEXTURE_2D, texture_id);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, filter(delta.options.magnification));
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, filter(delta.options.minification));
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
+
+            match delta.pos {
+                Some([x, y]) => gl::TexSubImage2D(
+                    gl::TEXTURE_2D, 0,
+                    x as GLint, y as GLint, width as GLsizei, height as GLsizei,
+                    gl::RGBA, gl::UNSIGNED_BYTE, helpers::array_to_c_void(&pixels)
+                ),
+                None => gl::TexImage2D(
+                    gl::TEXTURE_2D, 0, gl::RGBA8 as GLint,
+                    width as GLsizei, height as GLsizei, 0,
+                    gl::RGBA, gl::UNSIGNED_BYTE, helpers::array_to_c_void(&pixels)
+                ),
+            }
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+        }
+        profiler::record_upload(pixels.len());
+    }
+}
+
+impl Drop for Gui {
+    fn drop(&mut self) {
+        unsafe {
+            for texture_id in self.textures.values() {
+                gl::DeleteTextures(1, texture_id);
+            }
+            gl::DeleteBuffers(1, &self.ebo_id);
+            gl::DeleteBuffers(1, &self.vbo_id);
+            gl::DeleteVertexArrays(1, &self.vao_id);
+        }
+    }
+}
+
+/// Keys egui uses for navigating and editing, the rest only matter to the game
+fn egui_key(code: VirtualKeyCode) -> Option<egui::Key> {
+    use VirtualKeyCode::*;
+
+    Some(match code {
+        Up => egui::Key::ArrowUp,
+        Down => egui::Key::ArrowDown,
+        Left => egui::Key::ArrowLeft,
+        Right => egui::Key::ArrowRight,
+        Escape => egui::Key::Escape,
+        Tab => egui::Key::Tab,
+        Back => egui::Key::Backspace,
+        Return | NumpadEnter => egui::Key::Enter,
+        Space => egui::Key::Space,
+        Insert => egui::Key::Insert,
+        Delete => egui::Key::Delete,
+        Home => egui::Key::Home,
+        End => egui::Key::End,
+        PageUp => egui::Key::PageUp,
+        PageDown => egui::Key::PageDown,
+        Minus | Subtract => egui::Key::Minus,
+        Period | Decimal => egui::Key::Period,
+        Comma => egui::Key::Comma,
+        // Letters are named the same in both
+        _ => {
+            let name = format!("{:?}", code);
+            if name.len() != 1 {
+                return None;
+            }
+            egui::Key::from_name(&name)?
+        },
+    })
+}
@@ -0,0 +1,494 @@
+use glutin::event::{ElementState, MouseButton, VirtualKeyCode};
+
+use crate::{gamepad::{GamepadAxis, GamepadButton, GamepadEvent}, gl_utils::parsing};
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    fs,
+    io,
+    str::FromStr
+};
+
+/// Raw input forwarded from the event loop to the render thread
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputEvent {
+    Key(VirtualKeyCode, ElementState),
+    Mouse((f64, f64)),
+    /// Cursor position in physical pixels from the top left corner of the window
+    Cursor((f64, f64)),
+    /// Mouse wheel, in lines
+    Scroll((f64, f64)),
+    /// Typed text, after the keyboard layout has been applied
+    Character(char),
+    MouseButton(ElementState, MouseButton),
+    Gamepad(GamepadEvent),
+}
+
+/// Named actions that game logic reacts to, independent of which physical input triggers them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    MoveForward,
+    MoveBackward,
+    MoveLeft,
+    MoveRight,
+    MoveUp,
+    MoveDown,
+    /// Axis, horizontal turn
+    TurnX,
+    /// Axis, vertical turn
+    TurnY,
+    ToggleMouseLook,
+    SpawnHelicopter,
+    DespawnHelicopter,
+    /// Opens or closes the doors of every helicopter
+    ToggleDoors,
+    /// Take control of the helicopter closest to the camera, or let go of it
+    Possess,
+    /// Tail rotor pedals while flying a helicopter
+    YawLeft,
+    YawRight,
+    TogglePause,
+    /// Halves the simulation time scale
+    SlowDown,
+    /// Doubles the simulation time scale
+    SpeedUp,
+    /// Advance a paused simulation by a single tick
+    StepSimulation,
+    /// Select the helicopter under the cursor, or under the screen centre while mouse look is on
+    Select,
+    /// Show node axes, pivots and collision bounds
+    ToggleDebugDraw,
+    /// Show the inspector window, the cursor is freed while it is open
+    ToggleInspector,
+}
+
+impl Action {
+    const ALL: [Action; 22] = [
+        Action::MoveForward,
+        Action::MoveBackward,
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::MoveUp,
+        Action::MoveDown,
+        Action::TurnX,
+        Action::TurnY,
+        Action::ToggleMouseLook,
+        Action::SpawnHelicopter,
+        Action::DespawnHelicopter,
+        Action::ToggleDoors,
+        Action::Possess,
+        Action::YawLeft,
+        Action::YawRight,
+        Action::TogglePause,
+        Action::SlowDown,
+        Action::SpeedUp,
+        Action::StepSimulation,
+        Action::Select,
+        Action::ToggleDebugDraw,
+        Action::ToggleInspector,
+    ];
+}
+
+impl FromStr for Action {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parsing::from_debug_name(&Action::ALL, s).ok_or(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MouseAxis {
+    X,
+    Y,
+}
+
+/// Which part of a gamepad axis a binding reacts to, written as a +/- suffix in bindings files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AxisDirection {
+    Both,
+    Positive,
+    Negative,
+}
+
+impl AxisDirection {
+    const ALL: [AxisDirection; 3] = [AxisDirection::Both, AxisDirection::Positive, AxisDirection::Negative];
+
+    /// How far the axis is pushed in this direction, Both keeps the sign
+    fn apply(&self, value: f32) -> f32 {
+        match self {
+            AxisDirection::Both => value,
+            AxisDirection::Positive => value.max(0.0),
+            AxisDirection::Negative => (-value).max(0.0),
+        }
+    }
+}
+
+/// A physical input source that can be bound to an action
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Binding {
+    Key(VirtualKeyCode),
+    MouseButton(MouseButton),
+    MouseAxis(MouseAxis),
+    GamepadButton(GamepadButton),
+    GamepadAxis(GamepadAxis, AxisDirection),
+}
+
+// Keys that can be named in a bindings file, the name is the same as the VirtualKeyCode variant
+const BINDABLE_KEYS: [VirtualKeyCode; 67] = {
+    use VirtualKeyCode::*;
+    [
+        Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9, Key0,
+        A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+        F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12,
+        Left, Up, Right, Down,
+        Space, Return, Back, Tab, Escape,
+        LShift, RShift, LControl, RControl, LAlt, RAlt,
+        Comma, Period, Minus, Slash,
+    ]
+};
+
+/// Inverse of the Debug name of a key, only keys that can be bound are supported
+pub fn key_from_name(name: &str) -> Option<VirtualKeyCode> {
+    parsing::from_debug_name(&BINDABLE_KEYS, name)
+}
+
+impl FromStr for Binding {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "MouseLeft" => Ok(Binding::MouseButton(MouseButton::Left)),
+            "MouseRight" => Ok(Binding::MouseButton(MouseButton::Right)),
+            "MouseMiddle" => Ok(Binding::MouseButton(MouseButton::Middle)),
+            "MouseX" => Ok(Binding::MouseAxis(MouseAxis::X)),
+            "MouseY" => Ok(Binding::MouseAxis(MouseAxis::Y)),
+            s if s.parse::<GamepadButton>().is_ok() => s.parse().map(Binding::GamepadButton),
+            s if s.ends_with('+') => s[..s.len() - 1].parse().map(|a| Binding::GamepadAxis(a, AxisDirection::Positive)),
+            s if s.ends_with('-') => s[..s.len() - 1].parse().map(|a| Binding::GamepadAxis(a, AxisDirection::Negative)),
+            s if s.parse::<GamepadAxis>().is_ok() => s.parse().map(|a| Binding::GamepadAxis(a, AxisDirection::Both)),
+            key => key_from_name(key).map(Binding::Key).ok_or(()),
+        }
+    }
+}
+
+pub enum InputConfigError {
+    Io(io::Error),
+    Syntax(usize),
+    UnknownAction(usize, String),
+    UnknownBinding(usize, String),
+    InvalidValue(usize, String),
+}
+
+impl fmt::Display for InputConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InputConfigError::Io(e) => e.fmt(f),
+            InputConfigError::Syntax(line) => write!(f, "line {}: expected 'Action = Binding, Binding'", line),
+            InputConfigError::UnknownAction(line, name) => write!(f, "line {}: unknown action '{}'", line, name),
+            InputConfigError::UnknownBinding(line, name) => write!(f, "line {}: unknown binding '{}'", line, name),
+            InputConfigError::InvalidValue(line, value) => write!(f, "line {}: invalid value '{}'", line, value),
+        }
+    }
+}
+
+/// Maps physical inputs to actions, one input can trigger several actions
+pub struct InputMap {
+    bindings: HashMap<Binding, Vec<Action>>,
+    /// Gamepad axis values below this are treated as 0
+    pub gamepad_deadzone: f32,
+    /// How far a gamepad axis must be pushed to count as a press when bound to a button action
+    pub gamepad_press_threshold: f32,
+    /// Axis actions get the same units as mouse movement, a fully pushed stick equals this many mouse counts per second
+    pub gamepad_axis_speed: f32,
+}
+
+impl Default for InputMap {
+    fn default() -> Self {
+        use VirtualKeyCode::*;
+
+        let mut map = InputMap::empty();
+        map.bind(Binding::Key(W), Action::MoveForward);
+        map.bind(Binding::Key(S), Action::MoveBackward);
+        map.bind(Binding::Key(A), Action::MoveLeft);
+        map.bind(Binding::Key(D), Action::MoveRight);
+        map.bind(Binding::Key(Space), Action::MoveUp);
+        map.bind(Binding::Key(LControl), Action::MoveDown);
+        map.bind(Binding::MouseAxis(MouseAxis::X), Action::TurnX);
+        map.bind(Binding::MouseAxis(MouseAxis::Y), Action::TurnY);
+        map.bind(Binding::Key(R), Action::ToggleMouseLook);
+        map.bind(Binding::Key(H), Action::SpawnHelicopter);
+        map.bind(Binding::Key(J), Action::DespawnHelicopter);
+        map.bind(Binding::Key(O), Action::ToggleDoors);
+        map.bind(Binding::Key(F), Action::Possess);
+        map.bind(Binding::Key(Q), Action::YawLeft);
+        map.bind(Binding::Key(E), Action::YawRight);
+        map.bind(Binding::Key(P), Action::TogglePause);
+        map.bind(Binding::Key(Comma), Action::SlowDown);
+        map.bind(Binding::Key(Period), Action::SpeedUp);
+        map.bind(Binding::Key(N), Action::StepSimulation);
+        map.bind(Binding::MouseButton(MouseButton::Left), Action::Select);
+        map.bind(Binding::Key(F3), Action::ToggleDebugDraw);
+        map.bind(Binding::Key(F1), Action::ToggleInspector);
+
+        map.bind(Binding::GamepadAxis(GamepadAxis::LeftStickY, AxisDirection::Negative), Action::MoveForward);
+        map.bind(Binding::GamepadAxis(GamepadAxis::LeftStickY, AxisDirection::Positive), Action::MoveBackward);
+        map.bind(Binding::GamepadAxis(GamepadAxis::LeftStickX, AxisDirection::Negative), Action::MoveLeft);
+        map.bind(Binding::GamepadAxis(GamepadAxis::LeftStickX, AxisDirection::Positive), Action::MoveRight);
+        map.bind(Binding::GamepadAxis(GamepadAxis::RightTrigger, AxisDirection::Positive), Action::MoveUp);
+        map.bind(Binding::GamepadAxis(GamepadAxis::LeftTrigger, AxisDirection::Positive), Action::MoveDown);
+        map.bind(Binding::GamepadAxis(GamepadAxis::RightStickX, AxisDirection::Both), Action::TurnX);
+        map.bind(Binding::GamepadAxis(GamepadAxis::RightStickY, AxisDirection::Both), Action::TurnY);
+        map.bind(Binding::GamepadButton(GamepadButton::North), Action::ToggleMouseLook);
+        map.bind(Binding::GamepadButton(GamepadButton::RightBumper), Action::SpawnHelicopter);
+        map.bind(Binding::GamepadButton(GamepadButton::LeftBumper), Action::DespawnHelicopter);
+        map.bind(Binding::GamepadButton(GamepadButton::West), Action::ToggleDoors);
+        map.bind(Binding::GamepadButton(GamepadButton::East), Action::Possess);
+        map.bind(Binding::GamepadAxis(GamepadAxis::RightStickX, AxisDirection::Negative), Action::YawLeft);
+        map.bind(Binding::GamepadAxis(GamepadAxis::RightStickX, AxisDirection::Positive), Action::YawRight);
+        map.bind(Binding::GamepadButton(GamepadButton::Start), Action::TogglePause);
+        map.bind(Binding::GamepadButton(GamepadButton::Select), Action::StepSimulation);
+        map.bind(Binding::GamepadButton(GamepadButton::South), Action::Select);
+        map.bind(Binding::GamepadButton(GamepadButton::LeftStick), Action::ToggleDebugDraw);
+
+        map
+    }
+}
+
+impl InputMap {
+    pub fn empty() -> Self {
+        Self {
+            bindings: HashMap::new(),
+            gamepad_deadzone: 0.15,
+            gamepad_press_threshold: 0.5,
+            gamepad_axis_speed: 800.0,
+        }
+    }
+
+    /// Parse a bindings file where each line is 'Action = Binding, Binding' or 'Setting = value', lines starting with # are ignored
+    pub fn load(path: &str) -> Result<Self, InputConfigError> {
+        let src = fs::read_to_string(path).map_err(InputConfigError::Io)?;
+        Self::parse(&src)
+    }
+
+    pub fn parse(src: &str) -> Result<Self, InputConfigError> {
+        let mut map = InputMap::empty();
+        for line in parsing::lines(src) {
+            let line_number = line.number;
+            let (action, bindings) = match line.text.split_once('=') {
+                Some((action, bindings)) => (action.trim(), bindings),
+                None => return Err(InputConfigError::Syntax(line_number)),
+            };
+
+            let setting = match action {
+                "GamepadDeadzone" => Some(&mut map.gamepad_deadzone),
+                "GamepadPressThreshold" => Some(&mut map.gamepad_press_threshold),
+                "GamepadAxisSpeed" => Some(&mut map.gamepad_axis_speed),
+                _ => None,
+            };
+            if let Some(setting) = setting {
+                let value = bindings.trim();
+                let invalid = || InputConfigError::InvalidValue(line_number, value.to_string());
+                let parsed = value.parse::<f32>().map_err(|_| invalid())?;
+                // Axis values past the deadzone are divided by 1 - deadzone
+                if action == "GamepadDeadzone" && !(0.0..1.0).contains(&parsed) {
+                    return Err(invalid());
+                }
+                *setting = parsed;
+                continue;
+            }
+
+            let action = action.parse::<Action>()
+                .map_err(|_| InputConfigError::UnknownAction(line_number, action.to_string()))?;
+
+            for binding in bindings.split(',').map(str::trim).filter(|b| !b.is_empty()) {
+                let binding = binding.parse::<Binding>()
+                    .map_err(|_| InputConfigError::UnknownBinding(line_number, binding.to_string()))?;
+                map.bind(binding, action);
+            }
+        }
+
+        Ok(map)
+    }
+
+    pub fn bind(&mut self, binding: Binding, action: Action) {
+        let actions = self.bindings.entry(binding).or_default();
+        if !actions.contains(&action) {
+            actions.push(action);
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn unbind(&mut self, binding: Binding) {
+        self.bindings.remove(&binding);
+    }
+
+    fn actions(&self, binding: &Binding) -> &[Action] {
+        self.bindings.get(binding).map_or(&[], |a| &a[..])
+    }
+}
+
+/// Action state with edge detection. Call begin_frame before feeding the frame's events
+pub struct Input {
+    pub map: InputMap,
+    held_bindings: HashSet<Binding>,
+    held: HashSet<Action>,
+    pressed: HashSet<Action>,
+    released: HashSet<Action>,
+    axes: HashMap<Action, f32>,
+    /// Current value of each gamepad axis with the deadzone applied
+    gamepad_axes: HashMap<GamepadAxis, f32>,
+    cursor: (f64, f64),
+    delta_time: f32,
+}
+
+impl Input {
+    pub fn new(map: InputMap) -> Self {
+        Self {
+            map,
+            held_bindings: HashSet::new(),
+            held: HashSet::new(),
+            pressed: HashSet::new(),
+            released: HashSet::new(),
+            axes: HashMap::new(),
+            gamepad_axes: HashMap::new(),
+            cursor: (0.0, 0.0),
+            delta_time: 0.0,
+        }
+    }
+
+    /// Clears edges and axis values from the last frame, delta_time scales gamepad axes
+    pub fn begin_frame(&mut self, delta_time: f32) {
+        self.delta_time = delta_time;
+        self.pressed.clear();
+        self.released.clear();
+        self.axes.clear();
+    }
+
+    pub fn handle_event(&mut self, event: &InputEvent) {
+        match event {
+            InputEvent::Key(code, state) => self.set_binding_state(Binding::Key(*code), *state),
+            InputEvent::MouseButton(state, button) => self.set_binding_state(Binding::MouseButton(*button), *state),
+            InputEvent::Mouse((x, y)) => {
+                self.add_axis(Binding::MouseAxis(MouseAxis::X), *x as f32);
+                self.add_axis(Binding::MouseAxis(MouseAxis::Y), *y as f32);
+            },
+            InputEvent::Cursor(position) => self.cursor = *position,
+            InputEvent::Scroll(_) | InputEvent::Character(_) => { },
+            InputEvent::Gamepad(GamepadEvent::Button(button, state)) => self.set_binding_state(Binding::GamepadButton(*button), *state),
+            InputEvent::Gamepad(GamepadEvent::Axis(axis, value)) => self.set_gamepad_axis(*axis, *value),
+            InputEvent::Gamepad(GamepadEvent::Disconnected) => {
+                for button in GamepadButton::ALL.iter() {
+                    self.set_binding_state(Binding::GamepadButton(*button), ElementState::Released);
+                }
+                for axis in GamepadAxis::ALL.iter() {
+                    self.set_gamepad_axis(*axis, 0.0);
+                }
+            },
+        }
+    }
+
+    /// Action went from released to held this frame
+    pub fn pressed(&self, action: Action) -> bool {
+        self.pressed.contains(&action)
+    }
+
+    pub fn held(&self, action: Action) -> bool {
+        self.held.contains(&action)
+    }
+
+    /// Action went from held to released this frame
+    #[allow(dead_code)]
+    pub fn released(&self, action: Action) -> bool {
+        self.released.contains(&action)
+    }
+
+    /// Last known cursor position in physical pixels
+    pub fn cursor_position(&self) -> (f64, f64) {
+        self.cursor
+    }
+
+    /// Sum of all axis input this frame, in mouse counts
+    pub fn axis(&self, action: Action) -> f32 {
+        let gamepad: f32 = self.gamepad_axes.iter()
+            .flat_map(|(axis, value)| AxisDirection::ALL.iter().map(move |d| (Binding::GamepadAxis(*axis, *d), d.apply(*value))))
+            .filter(|(binding, _)| self.map.actions(binding).contains(&action))
+            .map(|(_, value)| value)
+            .sum();
+
+        self.axes.get(&action).cloned().unwrap_or(0.0) + gamepad * self.map.gamepad_axis_speed * self.delta_time
+    }
+
+    fn set_gamepad_axis(&mut self, axis: GamepadAxis, raw: f32) {
+        let deadzone = self.map.gamepad_deadzone;
+        let value = if raw.abs() < deadzone {
+            0.0
+        } else {
+            // Rescale so the output starts at 0 on the edge of the deadzone
+            raw.signum() * (raw.abs() - deadzone) / (1.0 - deadzone)
+        };
+        self.gamepad_axes.insert(axis, value);
+
+        // Axes bound to button actions act as buttons past the press threshold
+        for direction in AxisDirection::ALL.iter() {
+            let binding = Binding::GamepadAxis(axis, *direction);
+            let state = if direction.apply(value).abs() > self.map.gamepad_press_threshold {
+                ElementState::Pressed
+            } else {
+                ElementState::Released
+            };
+            self.set_binding_state(binding, state);
+        }
+    }
+
+    fn set_binding_state(&mut self, binding: Binding, state: ElementState) {
+        match state {
+            ElementState::Pressed => {
+                // Ignore key repeats
+                if !self.held_bindings.insert(binding) {
+                    return;
+                }
+
+                for &action in self.map.actions(&binding) {
+                    if self.held.insert(action) {
+                        self.pressed.insert(action);
+                    }
+                }
+            },
+            ElementState::Released => {
+                if !self.held_bindings.remove(&binding) {
+                    return;
+                }
+
+                for &action in self.map.actions(&binding) {
+                    let still_held = self.held_bindings.iter()
+                        .any(|b| self.map.actions(b).contains(&action));
+                    if !still_held && self.held.remove(&action) {
+                        self.released.insert(action);
+                    }
+                }
+            },
+        }
+    }
+
+    fn add_axis(&mut self, binding: Binding, value: f32) {
+        for &action in self.map.actions(&binding) {
+            *self.axes.entry(action).or_insert(0.0) += value;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deadzone_outside_zero_to_one_is_rejected() {
+        for value in ["-0.1", "1.0", "NaN"] {
+            let result = InputMap::parse(&format!("GamepadDeadzone = {}", value));
+            assert!(matches!(result, Err(InputConfigError::InvalidValue(1, _))), "deadzone {}", value);
+        }
+        assert!(matches!(InputMap::parse("GamepadDeadzone = 0.25"), Ok(map) if map.gamepad_deadzone == 0.25));
+    }
+}
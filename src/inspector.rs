@@ -0,0 +1,139 @@
+extern crate nalgebra_glm as glm;
+
+use crate::gl_utils::{camera::Camera, scene_graph::SceneNode, shaders::program::Program};
+
+/// Parts of the frame that can be switched off from the inspector
+pub struct RenderSettings {
+    pub terrain: bool,
+    pub wireframe: bool,
+    /// Outline around the selected helicopter
+    pub outline: bool,
+    pub debug_draw: bool,
+    /// Text in the corners of the screen
+    pub overlay: bool,
+    /// Batch draws with glMultiDrawElementsIndirect where GL 4.3 is available
+    pub multi_draw_indirect: bool,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            terrain: true,
+            wireframe: false,
+            outline: true,
+            debug_draw: false,
+            overlay: true,
+            multi_draw_indirect: true,
+        }
+    }
+}
+
+/// Inspector window for the scene graph, the camera, shader uniforms and render settings.
+/// Returns true when a node was edited, its transformation then needs to be recomputed
+pub fn show(ctx: &egui::Context, scene_graph: &mut SceneNode, camera: &mut Camera, settings: &mut RenderSettings) -> bool {
+    let mut nodes_changed = false;
+
+    egui::Window::new("Inspector")
+        .default_pos((8.0, 80.0))
+        .default_width(320.0)
+        .vscroll(true)
+        .show(ctx, |ui| {
+            egui::CollapsingHeader::new("Render").default_open(true).show(ui, |ui| {
+                ui.checkbox(&mut settings.terrain, "terrain");
+                ui.checkbox(&mut settings.wireframe, "wireframe");
+                ui.checkbox(&mut settings.outline, "selection outline");
+                ui.checkbox(&mut settings.debug_draw, "debug draw");
+                ui.checkbox(&mut settings.overlay, "text overlay");
+                ui.checkbox(&mut settings.multi_draw_indirect, "multi draw indirect");
+            });
+
+            egui::CollapsingHeader::new("Camera").default_open(true).show(ui, |ui| {
+                let p = camera.position();
+                ui.label(format!("position {:.1} {:.1} {:.1}", p.x, p.y, p.z));
+                ui.add(egui::Slider::new(&mut camera.move_speed, 1.0..=200.0).logarithmic(true).text("move speed"));
+                ui.add(egui::Slider::new(&mut camera.turn_sensitivity, 0.01..=2.0).logarithmic(true).text("turn sensitivity"));
+            });
+
+            egui::CollapsingHeader::new("Scene graph").show(ui, |ui| {
+                nodes_changed = node_ui(ui, scene_graph);
+            });
+
+            egui::CollapsingHeader::new("Shaders").show(ui, |ui| {
+                for program in camera.programs() {
+                    program_ui(ui, program);
+                }
+            });
+        });
+
+    nodes_changed
+}
+
+/// A node and, folded inside it, its children
+fn node_ui(ui: &mut egui::Ui, node: &mut SceneNode) -> bool {
+    let name = if node.name.is_empty() { "node" } else { &node.name };
+    let title = match node.children.len() {
+        0 => name.to_string(),
+        n => format!("{} ({})", name, n),
+    };
+
+    // Names repeat between helicopters, the address tells the nodes apart
+    egui::CollapsingHeader::new(title)
+        .id_salt(node as *const SceneNode as usize)
+        .show(ui, |ui| {
+            let mut changed = false;
+            egui::Grid::new("transform").num_columns(4).show(ui, |ui| {
+                changed |= vec3_row(ui, "position", &mut node.position, 0.1);
+                changed |= vec3_row(ui, "rotation", &mut node.rotation, 0.01);
+                changed |= vec3_row(ui, "scale", &mut node.scale, 0.01);
+                changed |= vec3_row(ui, "pivot", &mut node.reference_point, 0.1);
+            });
+
+            unsafe {
+                for &child in &node.children {
+                    changed |= node_ui(ui, &mut *child);
+                }
+            }
+            changed
+        })
+        .body_returned
+        .unwrap_or(false)
+}
+
+fn vec3_row(ui: &mut egui::Ui, label: &str, value: &mut glm::Vec3, speed: f32) -> bool {
+    ui.label(label);
+    let mut changed = false;
+    for i in 0..3 {
+        changed |= ui.add(egui::DragValue::new(&mut value[i]).speed(speed).max_decimals(3)).changed();
+    }
+    ui.end_row();
+    changed
+}
+
+/// Every active uniform of a program, floats and vectors can be edited while matrices and samplers are only shown
+fn program_ui(ui: &mut egui::Ui, program: &Program) {
+    egui::CollapsingHeader::new(format!("program {}", program.program_id))
+        .id_salt(program.program_id)
+        .show(ui, |ui| {
+            for uniform in program.active_uniforms() {
+                ui.horizontal(|ui| {
+                    ui.label(format!("{} {}", uniform.type_name(), uniform.name));
+                    let mut values = match program.uniform_values(&uniform) {
+                        Some(values) => values,
+                        None => return,
+                    };
+
+                    if values.len() > 4 {
+                        ui.label(format!("{} values", values.len()));
+                        return;
+                    }
+                    let mut changed = false;
+                    for value in &mut values {
+                        changed |= ui.add(egui::DragValue::new(value).speed(0.01).max_decimals(3)).changed();
+                    }
+                    if changed {
+                        program.set_uniform_values(&uniform, &values);
+                    }
+                });
+            }
+        });
+}
@@ -2,12 +2,14 @@ extern crate nalgebra_glm as glm;
 extern crate gl;
 extern crate tobj;
 
-use glutin::event::KeyboardInput;
-use my_helicopter::{HelicopterNode, MyHelicopter};
+use my_helicopter::{HelicopterNode, MyHelicopter, NodeOwners};
 use std::{
+    fs,
+    rc::Rc,
     thread,
     ptr,
-    env, 
+    env,
+    time::Duration,
     sync::{Arc, RwLock, mpsc}
 };
 
@@ -15,56 +17,203 @@ use std::{
 mod util;
 mod gl_utils;
 mod my_helicopter;
-
-use gl_utils::{camera::{VecDir, CameraBuilder}, mesh::{Helicopter, Terrain}, scene_graph::SceneNode, shaders::program::ProgramBuilder, toolbox::simple_heading_animation};
+mod input;
+mod gamepad;
+mod replay;
+mod game_loop;
+mod flight_model;
+mod gui;
+mod inspector;
+
+use input::{Action, Input, InputEvent, InputMap};
+use gamepad::{GamepadDevice, ScriptedGamepad};
+use replay::{FrameInput, InputRecorder, InputReplay};
+use game_loop::GameLoop;
+use gui::Gui;
+use inspector::RenderSettings;
+use gl_utils::{camera::{VecDir, CameraBuilder}, collision::{self, CollisionTracker}, debug_draw::{self, DebugDraw}, skybox::{Cubemap, Skybox}, text::{Anchor, TextRenderer}, height_grid::{Ground, HeightGrid}, heightmap::{self, Heightmap, NoiseSettings, TerrainBuilder}, mesh::Terrain, mesh_cache, terrain_chunks::{ChunkSettings, ChunkedTerrain, HeightFn}, outline, profiler::Profiler, render_queue::RenderQueue, path::Path, scene_graph::SceneNode, shaders::program::ProgramBuilder};
 
 use glutin::event::{
-    Event, 
-    WindowEvent, 
-    ElementState::{Pressed, Released}, 
-    VirtualKeyCode::{self, *}, 
-    DeviceEvent
+    Event,
+    WindowEvent,
+    VirtualKeyCode::*,
+    DeviceEvent,
+    MouseScrollDelta
 };
 
 use glutin::{window::Fullscreen, event_loop::ControlFlow};
 
-enum InputEvent {
-    Key(KeyboardInput),
-    Mouse((f64, f64))
+const LUNAR_SURFACE: &str = "assets/objs/lunarsurface.obj";
+
+/// Where the terrain comes from, picked on the command line
+enum TerrainSource {
+    Default,
+    /// An .obj model or a heightmap image
+    File(String),
+    Seed(u64),
+    /// Generated from a seed without bounds, in chunks around the camera
+    Streamed(u64),
 }
 
-fn main() {
-    // Set up the necessary objects to deal with windows and event handling
-    let el = glutin::event_loop::EventLoop::new();
-    
-    let wb = {
-        let mut wb  = glutin::window::WindowBuilder::new()
-            .with_title("Gloom-rs")
-            .with_resizable(false)
-            .with_always_on_top(true);
-
-        let args: Vec<String> = env::args().collect();
-        for arg in args.iter().skip(1) {
+/// Command line options
+struct Args {
+    fullscreen: bool,
+    trace_path: Option<String>,
+    bindings_path: String,
+    gamepad_script: Option<String>,
+    record_path: Option<String>,
+    replay_path: Option<String>,
+    skybox_path: Option<String>,
+    terrain_source: TerrainSource,
+}
+
+impl Default for Args {
+    fn default() -> Self {
+        Self {
+            fullscreen: false,
+            trace_path: None,
+            bindings_path: String::from("assets/config/bindings.cfg"),
+            gamepad_script: None,
+            record_path: None,
+            replay_path: None,
+            skybox_path: None,
+            terrain_source: TerrainSource::Default,
+        }
+    }
+}
+
+impl Args {
+    /// None when the help was asked for
+    fn parse(mut args: impl Iterator<Item = String>) -> Option<Self> {
+        let mut parsed = Args::default();
+        let mut streaming_terrain = false;
+        while let Some(arg) = args.next() {
+            let mut path = || {
+                let path = args.next();
+                if path.is_none() {
+                    eprintln!("Missing path for '{}'", arg);
+                }
+                path
+            };
             match &arg[..] {
-                "-f" | "-F" => {
-                    wb = wb.with_maximized(true)
-                    .with_fullscreen(Some(Fullscreen::Borderless(el.primary_monitor())));
-                },
-                "-h" => {
-                    let h_command = "\n-h => 'display this information'";
-                    let f_command = "\n-f | -F => 'fullscreen mode'"; // TODO: fov and mouse sense should be connected to this somehow
-                    println!("Rendering toy code{}{}", h_command, f_command);
-                    return;
+                // TODO: fov and mouse sense should be connected to this somehow
+                "-f" | "-F" => parsed.fullscreen = true,
+                "-t" | "--trace" => parsed.trace_path = path(),
+                "-b" | "--bindings" => if let Some(path) = path() { parsed.bindings_path = path },
+                "-g" | "--gamepad-script" => parsed.gamepad_script = path(),
+                "--record" => parsed.record_path = path(),
+                "--replay" => parsed.replay_path = path(),
+                "--skybox" => parsed.skybox_path = path(),
+                "--terrain" => if let Some(path) = path() { parsed.terrain_source = TerrainSource::File(path) },
+                "--terrain-seed" => {
+                    match args.next().map(|seed| seed.parse::<u64>()) {
+                        Some(Ok(seed)) => parsed.terrain_source = TerrainSource::Seed(seed),
+                        Some(Err(e)) => eprintln!("Invalid seed for '{}', e: {}", arg, e),
+                        None => eprintln!("Missing seed for '{}'", arg),
+                    }
                 },
+                "--streaming-terrain" => streaming_terrain = true,
+                "-h" => return None,
                 c => eprintln!("Unknown command '{}'", c)
             }
         }
 
-        wb
+        if streaming_terrain {
+            parsed.terrain_source = match parsed.terrain_source {
+                TerrainSource::File(path) => {
+                    eprintln!("Only generated terrain can be streamed, ignoring {}", path);
+                    TerrainSource::Streamed(1)
+                },
+                TerrainSource::Seed(seed) => TerrainSource::Streamed(seed),
+                _ => TerrainSource::Streamed(1),
+            };
+        }
+
+        Some(parsed)
+    }
+}
+
+/// Every option with its description, for -h
+const OPTIONS: [(&str, &str); 12] = [
+    ("-h", "display this information"),
+    ("-f | -F", "fullscreen mode"),
+    ("-t | --trace <file>", "write per frame profiling data to a csv file"),
+    ("-b | --bindings <file>", "load key bindings from file"),
+    ("-g | --gamepad-script <file>", "use a scripted virtual gamepad instead of a connected one"),
+    ("--record <file>", "record all input and frame times to file"),
+    ("--replay <file>", "replay a recording instead of using live input, exits when done"),
+    ("--skybox <path>", "sky from a directory of six cube faces or a panorama image, like an .hdr"),
+    ("--terrain <file>", "terrain from an .obj model or a grayscale heightmap image"),
+    ("--terrain-seed <number>", "generate the terrain from fractal noise with this seed"),
+    ("--streaming-terrain", "endless generated terrain, loaded in chunks around the camera"),
+    ("convert [paths]", "write .mesh caches for .obj files and directories of them, assets/objs when none are given"),
+];
+
+fn print_help() {
+    println!("Rendering toy code");
+    for (option, description) in &OPTIONS {
+        println!("{} => '{}'", option, description);
+    }
+}
+
+/// Streamed terrain goes on forever, so it is asked through its height function instead of a grid over part of it
+fn ground<'a>(grid: &'a Option<HeightGrid>, chunks: &'a Option<ChunkedTerrain>) -> &'a dyn Ground {
+    match (grid, chunks) {
+        (_, Some(chunks)) => chunks,
+        (Some(grid), None) => grid,
+        (None, None) => panic!("Terrain has neither a mesh nor chunks"),
+    }
+}
+
+/// Writes the mesh cache of every .obj file given, directories are searched recursively
+fn convert_assets(paths: Vec<String>) {
+    let mut pending = if paths.is_empty() { vec![std::path::PathBuf::from("assets/objs")] } else { paths.into_iter().map(std::path::PathBuf::from).collect() };
+    while let Some(path) = pending.pop() {
+        if path.is_dir() {
+            match fs::read_dir(&path) {
+                Ok(entries) => pending.extend(entries.filter_map(|entry| entry.ok()).map(|entry| entry.path())),
+                Err(e) => eprintln!("Failed to read directory {}, e: {}", path.display(), e),
+            }
+        } else if path.extension().is_some_and(|extension| extension == "obj") {
+            let before = std::time::Instant::now();
+            match mesh_cache::convert(&path.to_string_lossy()) {
+                Ok(cache) => println!("Wrote {} in {:.3}ms.", cache.display(), before.elapsed().as_micros() as f32 / 1e3),
+                Err(e) => eprintln!("Failed to convert {}, e: {}", path.display(), e),
+            }
+        }
+    }
+}
+
+fn main() {
+    // Converting assets needs no window
+    if env::args().nth(1).as_deref() == Some("convert") {
+        convert_assets(env::args().skip(2).collect());
+        return;
+    }
+
+    let Args { fullscreen, trace_path, bindings_path, gamepad_script, record_path, replay_path, skybox_path, terrain_source } = match Args::parse(env::args().skip(1)) {
+        Some(args) => args,
+        None => {
+            print_help();
+            return;
+        },
     };
 
+    // Set up the necessary objects to deal with windows and event handling
+    let el = glutin::event_loop::EventLoop::new();
+
+    let mut wb = glutin::window::WindowBuilder::new()
+        .with_title("Gloom-rs")
+        .with_resizable(false)
+        .with_always_on_top(true);
+    if fullscreen {
+        wb = wb.with_maximized(true)
+            .with_fullscreen(Some(Fullscreen::Borderless(el.primary_monitor())));
+    }
+
     let cb = glutin::ContextBuilder::new()
-        .with_vsync(true);
+        .with_vsync(true)
+        .with_stencil_buffer(8);
 
     let windowed_context = cb.build_windowed(wb, &el).unwrap();
     if let Err(e) = windowed_context.window().set_cursor_grab(true) {
@@ -74,6 +223,20 @@ fn main() {
   
     let (tx, rx) = mpsc::channel::<InputEvent>();
 
+    let gamepad = match &gamepad_script {
+        Some(path) => match ScriptedGamepad::load(path) {
+            Ok(script) => Some(Box::new(script) as Box<dyn GamepadDevice>),
+            Err(e) => {
+                eprintln!("Failed to load gamepad script {}, e: {}", path, e);
+                None
+            }
+        },
+        None => gamepad::open_default_device(),
+    };
+    if let Some(device) = gamepad {
+        gamepad::spawn_gamepad_thread(device, tx.clone());
+    }
+
     // Spawn a separate thread for rendering, so event handling doesn't block rendering
     let render_thread = thread::spawn(move || {
         let sf = windowed_context.window().scale_factor();
@@ -95,8 +258,7 @@ fn main() {
 
             gl::Enable(gl::CULL_FACE);
             gl::Disable(gl::MULTISAMPLE);
-            gl::Enable(gl::BLEND);
-            // gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+            // Blending is enabled per material by the render queue
             gl::Enable(gl::DEBUG_OUTPUT_SYNCHRONOUS);
             gl::DebugMessageCallback(Some(util::debug_callback), ptr::null());
         }
@@ -107,31 +269,129 @@ fn main() {
             .attach_file("assets/shaders/main.frag")
             .link();
 
+        // Selected helicopters are outlined by drawing a grown copy of them behind a stencil mask
+        let mut outline_program = ProgramBuilder::new()
+            .attach_file("assets/shaders/outline.vert")
+            .attach_file("assets/shaders/outline.frag")
+            .link();
+        let outline_program_id = outline_program.program_id;
+        if let Err(e) = outline_program.locate_uniform("outline_width")
+            .and_then(|_| outline_program.set_uniform1("outline_width", 0.15, gl::Uniform1f))
+            .and_then(|_| outline_program.locate_uniform("outline_color"))
+            .and_then(|_| outline_program.set_uniform1("outline_color", [1.0f32, 0.75, 0.1, 1.0], |location, c| unsafe { gl::Uniform4f(location, c[0], c[1], c[2], c[3]) })) {
+            eprintln!("Failed to set up outline shader, e: {}", e);
+        }
+
+        let debug_program = ProgramBuilder::new()
+            .attach_file("assets/shaders/debug.vert")
+            .attach_file("assets/shaders/debug.frag")
+            .link();
+        let mut debug_draw = DebugDraw::new(debug_program.program_id);
+
+        let text_program = ProgramBuilder::new()
+            .attach_file("assets/shaders/text.vert")
+            .attach_file("assets/shaders/text.frag")
+            .link();
+        let mut text = match TextRenderer::load("assets/fonts/DejaVuSansMono.ttf", 18.0, text_program) {
+            Ok(text) => text,
+            Err(e) => panic!("Failed to load font, e: {}", e),
+        };
+
+        let skybox = Skybox::new(ProgramBuilder::new()
+            .attach_file("assets/shaders/skybox.vert")
+            .attach_file("assets/shaders/skybox.frag")
+            .link());
+        // Without a sky image the old flat background color becomes a gradient
+        let sky_gradient = || Cubemap::gradient(64, glm::vec3(0.02, 0.02, 0.2), glm::vec3(0.15, 0.15, 0.45), glm::vec3(0.05, 0.05, 0.1));
+        let cubemap = match &skybox_path {
+            Some(path) => Cubemap::load(path, 512).unwrap_or_else(|e| {
+                eprintln!("Failed to load skybox {}, e: {}", path, e);
+                sky_gradient()
+            }),
+            None => sky_gradient(),
+        };
+
+        let gui_program = ProgramBuilder::new()
+            .attach_file("assets/shaders/gui.vert")
+            .attach_file("assets/shaders/gui.frag")
+            .link();
+        let mut gui = match Gui::new(gui_program, sf as f32) {
+            Ok(gui) => gui,
+            Err(e) => panic!("Failed to set up GUI shader, e: {}", e),
+        };
+
         let single_instance = vec![glm::Mat4::identity()];
 
-        let terrain_geometry = {
+        let mut terrain_chunks = None;
+        let terrain = {
             // TODO: utility in mesh to convert to attrib_pair vec
-            let terrain = Terrain::load("assets/objs/lunarsurface.obj");
-            terrain.into_geomtric_object(program.program_id, &single_instance)
+            let generate = |seed: u64| {
+                let heightmap = Heightmap::fractal(257, 257, seed, &NoiseSettings::default());
+                TerrainBuilder::new().size(600.0, 600.0).height(30.0).build(&heightmap)
+            };
+            match terrain_source {
+                TerrainSource::File(path) if path.ends_with(".obj") => Some(Terrain::load(&path)),
+                TerrainSource::File(path) => match Heightmap::load(&path) {
+                    Ok(heightmap) => Some(TerrainBuilder::new().size(600.0, 600.0).height(30.0).build(&heightmap)),
+                    Err(e) => panic!("Failed to load heightmap {}, e: {}", path, e),
+                },
+                TerrainSource::Seed(seed) => Some(generate(seed)),
+                // The lunar surface model is not part of the repository, without it there is generated terrain
+                TerrainSource::Default if std::path::Path::new(LUNAR_SURFACE).exists() => Some(Terrain::load(LUNAR_SURFACE)),
+                TerrainSource::Default => Some(generate(1)),
+                TerrainSource::Streamed(seed) => {
+                    let settings = NoiseSettings::default();
+                    let height: HeightFn = Arc::new(move |x, z| (heightmap::fractal_noise(x / 600.0, z / 600.0, seed, &settings) * 0.5 + 0.5) * 30.0);
+                    terrain_chunks = Some(ChunkedTerrain::new(program.program_id, ChunkSettings::default(), height));
+                    None
+                },
+            }
         };
+        // Streamed terrain has no mesh, it is drawn as chunks and queried through its height function
+        let terrain_grid = terrain.as_ref().map(|terrain| HeightGrid::from_mesh(terrain, 4.0));
+        let terrain_geometry = terrain.map(|terrain| terrain.into_geomtric_object(program.program_id, &single_instance));
 
         
         let mut scene_graph = SceneNode::new();
+        scene_graph.name = "root".to_string();
         
-        let terrain_instance = terrain_geometry.create_geometric_instance(0).expect("failed to create terrain instance");
-        let mut terrain_node = SceneNode::from_vao(terrain_instance);
+        let mut terrain_node = match &terrain_geometry {
+            Some(geometry) => SceneNode::from_vao(geometry.create_geometric_instance(0).expect("failed to create terrain instance")),
+            // Chunks are drawn on their own, the node only carries the helicopters
+            None => SceneNode::new(),
+        };
+        terrain_node.name = "terrain".to_string();
         scene_graph.add_child(&terrain_node);
         
         let instance_count = 121; // 11 * 11
         let mut my_helicopter = MyHelicopter::init(program.program_id, instance_count);
+        // Helicopters take turns picking a route, every .path file is a route besides the original figure eight
+        let mut routes = vec![Rc::new(Path::figure_eight(15.0))];
+        let mut route_files: Vec<_> = fs::read_dir("assets/paths")
+            .map(|entries| entries.filter_map(|e| e.ok()).map(|e| e.path()).collect())
+            .unwrap_or_default();
+        route_files.sort(); // Keeps route assignment the same between runs, replays depend on it
+        for file in route_files.iter().filter(|f| f.extension().is_some_and(|e| e == "path")) {
+            let file = file.to_string_lossy();
+            match Path::load(&file) {
+                Ok(path) => routes.push(Rc::new(path)),
+                Err(e) => eprintln!("Failed to load route {}, e: {}", file, e),
+            }
+        }
+        let helicopter_speed = 25.0;
+
         let mut helicopter_nodes = Vec::<HelicopterNode>::new();
+        let mut node_owners = NodeOwners::default();
         for i in 0..11 {
             for j in 0..11 {
                 let x_offset = i as f32 * 10.0  + (i * 10) as f32;
                 let z_offset = j as f32 * 10.0 + (j * 10) as f32;
                 let pos_offset = glm::vec3(x_offset, 40.0, z_offset);
-                let h = my_helicopter.create_helicopter_node(0.0, pos_offset).expect("something went wrong when creating helicopter node");
+                let route = &routes[helicopter_nodes.len() % routes.len()];
+                let start = (helicopter_nodes.len() as f32 * 10.0) % route.length();
+                let h = my_helicopter.create_helicopter_node(Rc::clone(route), helicopter_speed, start, pos_offset);
                 terrain_node.add_child(&h.root_node);
+                node_owners.insert(&h, helicopter_nodes.len());
                 helicopter_nodes.push(h);
             }
         }
@@ -141,82 +401,382 @@ fn main() {
             .translation(&glm::vec3(0.0, 0.0, 0.0))
             .move_speed(14.0)
             .turn_sensitivity(0.2)
-            .build_and_attach_to_programs(vec![program]);
+            .build_and_attach_to_programs(vec![program, outline_program, debug_program]);
+
+        let mut render_queue = RenderQueue::new();
+        let mut profiler = Profiler::new(Duration::from_secs(5));
+        if let Some(path) = &trace_path {
+            if let Err(e) = profiler.trace_to_csv(path) {
+                eprintln!("Failed to create trace file {}, e: {}", path, e);
+            }
+        }
+
+        let mut recorder = record_path.and_then(|path| match InputRecorder::create(&path) {
+            Ok(recorder) => Some(recorder),
+            Err(e) => {
+                eprintln!("Failed to create input recording {}, e: {}", path, e);
+                None
+            }
+        });
+
+        let mut replay = replay_path.map(|path| match InputReplay::load(&path) {
+            Ok(replay) => {
+                println!("Replaying {} frames from {}", replay.remaining(), path);
+                replay
+            },
+            Err(e) => panic!("Failed to load input replay {}, e: {}", path, e),
+        });
 
         let first_frame_time = std::time::Instant::now();
         let mut last_frame_time = first_frame_time;
 
-        // TODO: Virtual input abstraction for runtime settings
-        // TODO: This can be an array instead of a Vec
-        let mut pressed_keys = Vec::<VirtualKeyCode>::with_capacity(10);    
+        let mut input = Input::new(match InputMap::load(&bindings_path) {
+            Ok(map) => map,
+            Err(e) => {
+                eprintln!("Failed to load bindings from {}, using defaults. e: {}", bindings_path, e);
+                InputMap::default()
+            }
+        });
         let mut disable_turn = false;
-        
-        let mut drawn_vaos = Vec::<u32>::new();
+        let mut inspector_open = false;
+        let mut render_settings = RenderSettings::default();
+        // Mouse look keeps the cursor grabbed and hidden, it is freed while mouse look is off or the inspector is open
+        let grab_cursor = |grab: bool| {
+            if let Err(e) = context.window().set_cursor_grab(grab) {
+                eprintln!("Failed to change cursor grab, e: {}", e);
+            }
+            context.window().set_cursor_visible(!grab);
+        };
+        // Smoothed for the overlay, so the number can be read
+        let mut frame_time = 1.0 / 60.0;
+        let mut game_loop = GameLoop::new(60.0);
+        // Index into helicopter_nodes of the helicopter being flown, the camera follows it
+        let mut possessed: Option<usize> = None;
+        // Index into helicopter_nodes of the helicopter picked with the mouse, it is drawn with an outline
+        let mut selected: Option<usize> = None;
+        // Pairs of indices into helicopter_nodes that are touching
+        let mut collision_tracker = CollisionTracker::<usize>::new();
+
         // The main rendering loop
         loop {
-            let now = std::time::Instant::now();
-            let elapsed = now.duration_since(first_frame_time).as_secs_f32();
-            let delta_time = now.duration_since(last_frame_time).as_secs_f32();
-            last_frame_time = now;
-
-            for h in &mut helicopter_nodes {
-                h.update(delta_time, elapsed);
-            }
-
-            scene_graph.update_node_transformations(&glm::identity());
-
-            // Handle changes in keyboard state
-            rx.try_iter().for_each(|input_event| {
-                match input_event {
-                    InputEvent::Key(key_input) => {
-                        match key_input.state {
-                            Pressed => {
-                                if let Some(code) = key_input.virtual_keycode {
-                                    if let None = pressed_keys.iter().position(|x| *x == code) {
-                                        pressed_keys.push(code);
-                                    }
-                                }
-                            }
-                            Released => {
-                                if let Some(code) = key_input.virtual_keycode {
-                                    if let Some(i) = pressed_keys.iter().position(|x| *x == code) {
-                                        pressed_keys.swap_remove(i);
-                                    }
-                                }
-                            }
+            // Frame times and input either come live or from a recording, the rest of the loop can't tell the difference
+            let frame_input = match &mut replay {
+                Some(replay) => {
+                    // Live input is ignored while replaying
+                    rx.try_iter().for_each(drop);
+                    match replay.next_frame() {
+                        Some(frame_input) => frame_input,
+                        None => {
+                            println!("Replay finished");
+                            break;
                         }
                     }
-                    InputEvent::Mouse(mouse_input) => {
-                        if !disable_turn {
-                            camera.turn(mouse_input, delta_time);
+                },
+                None => {
+                    let now = std::time::Instant::now();
+                    let frame_input = FrameInput {
+                        elapsed: now.duration_since(first_frame_time).as_secs_f32(),
+                        delta_time: now.duration_since(last_frame_time).as_secs_f32(),
+                        events: rx.try_iter().collect(),
+                    };
+                    last_frame_time = now;
+                    frame_input
+                }
+            };
+
+            if let Some(r) = &mut recorder {
+                if let Err(e) = r.record_frame(&frame_input) {
+                    eprintln!("Failed to record input, recording stopped. e: {}", e);
+                    recorder = None;
+                }
+            }
+
+            let delta_time = frame_input.delta_time;
+            profiler.begin_frame();
+
+            // Handle changes in input state
+            input.begin_frame(delta_time);
+            for input_event in &frame_input.events {
+                // The GUI sees everything, the game only what the GUI did not take
+                if !gui.consumes(input_event) {
+                    input.handle_event(input_event);
+                }
+                gui.handle_event(input_event);
+            }
+
+            if input.pressed(Action::TogglePause) {
+                game_loop.toggle_pause();
+            }
+            if input.pressed(Action::StepSimulation) {
+                game_loop.step();
+            }
+            if input.pressed(Action::SlowDown) {
+                game_loop.scale_time(0.5);
+            }
+            if input.pressed(Action::SpeedUp) {
+                game_loop.scale_time(2.0);
+            }
+
+            if input.pressed(Action::ToggleMouseLook) {
+                disable_turn = !disable_turn;
+                // Free the cursor while mouse look is off so it can be used to pick helicopters
+                grab_cursor(!disable_turn && !inspector_open);
+            }
+
+            if input.pressed(Action::ToggleInspector) {
+                inspector_open = !inspector_open;
+                grab_cursor(!disable_turn && !inspector_open);
+            }
+
+            if input.pressed(Action::ToggleDebugDraw) {
+                render_settings.debug_draw = !render_settings.debug_draw;
+            }
+
+            if input.pressed(Action::SpawnHelicopter) {
+                let i = helicopter_nodes.len();
+                let pos_offset = glm::vec3((i / 11) as f32 * 20.0, 40.0, (i % 11) as f32 * 20.0);
+                let route = &routes[i % routes.len()];
+                let mut h = my_helicopter.create_helicopter_node(Rc::clone(route), helicopter_speed, 0.0, pos_offset);
+                terrain_node.add_child(&h.root_node);
+                // Place it right away, otherwise it sits at the origin until the next tick which may be a while when paused
+                h.update(0.0, ground(&terrain_grid, &terrain_chunks));
+                h.root_node.step_node_transformations(&terrain_node.current_transformation_matrix);
+                node_owners.insert(&h, i);
+                helicopter_nodes.push(h);
+            }
+
+            if input.pressed(Action::ToggleDoors) {
+                helicopter_nodes.iter_mut().for_each(HelicopterNode::toggle_door);
+            }
+
+            if input.pressed(Action::Possess) {
+                match possessed.take() {
+                    Some(i) => helicopter_nodes[i].release_control(),
+                    None => {
+                        // The selected helicopter if there is one, otherwise the closest
+                        let camera_position = camera.position();
+                        possessed = selected.or_else(|| helicopter_nodes.iter()
+                            .map(|h| glm::distance2(&h.root_node.position, &camera_position))
+                            .enumerate()
+                            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+                            .map(|(i, _)| i));
+                        if let Some(i) = possessed {
+                            helicopter_nodes[i].take_control();
                         }
                     }
                 }
-            });
+            }
+
+            // Flying a helicopter, movement actions become the controls
+            if let Some(i) = possessed {
+                let held = |action| if input.held(action) { 1.0 } else { 0.0 };
+                let controls = &mut helicopter_nodes[i].controls;
+                controls.collective = (controls.collective + (held(Action::MoveUp) - held(Action::MoveDown)) * 0.5 * delta_time).clamp(0.0, 1.0);
+                controls.cyclic = glm::vec2(held(Action::MoveRight) - held(Action::MoveLeft), held(Action::MoveForward) - held(Action::MoveBackward));
+                controls.pedals = held(Action::YawRight) - held(Action::YawLeft);
+            }
+
+            if input.pressed(Action::DespawnHelicopter) {
+                if let Some(h) = helicopter_nodes.pop() {
+                    if possessed == Some(helicopter_nodes.len()) {
+                        possessed = None;
+                    }
+                    if selected == Some(helicopter_nodes.len()) {
+                        selected = None;
+                    }
+                    terrain_node.remove_child(&h.root_node);
+                    node_owners.remove(&h);
+                    my_helicopter.destroy_helicopter_node(h);
+                }
+            }
 
-            // Handle keyboard input
-            pressed_keys.iter().for_each(|key| {
-                match key {
-                    VirtualKeyCode::W => camera.move_in_dir(VecDir::Forward, delta_time),
-                    VirtualKeyCode::S => camera.move_in_dir(VecDir::Backward, delta_time),
-                    VirtualKeyCode::A => camera.move_in_dir(VecDir::Left, delta_time),
-                    VirtualKeyCode::D => camera.move_in_dir(VecDir::Right, delta_time),
-                    VirtualKeyCode::R => disable_turn = !disable_turn,
-                    VirtualKeyCode::Space => camera.move_in_dir(VecDir::Up, delta_time),
-                    VirtualKeyCode::LControl => camera.move_in_dir(VecDir::Down, delta_time),
-                    _ => { }
+            let size = context.window().inner_size();
+            let mut nodes_changed = false;
+            gui.run((size.width as f32, size.height as f32), frame_input.elapsed as f64, |ctx| {
+                if inspector_open {
+                    nodes_changed = inspector::show(ctx, &mut scene_graph, &mut camera, &mut render_settings);
                 }
             });
+            // Edits show right away, also while paused
+            if nodes_changed {
+                scene_graph.snap_node_transformations(&glm::identity());
+            }
+
+            // The simulation runs in fixed ticks, independent of the frame rate
+            for _ in 0..game_loop.advance(delta_time) {
+                let tick = game_loop.tick_length();
+                for h in &mut helicopter_nodes {
+                    h.update(tick, ground(&terrain_grid, &terrain_chunks));
+                }
+
+                scene_graph.step_node_transformations(&glm::identity());
+
+                // Parts of the same helicopter always overlap, so only contacts between different helicopters count
+                let colliders = scene_graph.colliders();
+                let collisions = collision::find_collisions(&colliders, |a, b| node_owners.owner(a) == node_owners.owner(b));
+                let pairs = collisions.iter().filter_map(|c| Some((node_owners.owner(c.a)?, node_owners.owner(c.b)?)));
+                for event in collision_tracker.update(pairs) {
+                    HelicopterNode::handle_collision(&mut helicopter_nodes, &event);
+                }
+            }
+            scene_graph.interpolate_node_transformations(game_loop.alpha());
+
+            // The camera is not part of the simulation, it uses real frame time so it can fly around a paused or slowed scene
+            if let Some(flight) = possessed.and_then(|i| helicopter_nodes[i].flight.as_ref()) {
+                // Chase camera behind and above the nose, eased so the fixed simulation ticks don't show as jitter
+                let nose = glm::vec3(-flight.yaw.sin(), 0.0, -flight.yaw.cos());
+                let target = flight.position - nose * 25.0 + glm::vec3(0.0, 8.0, 0.0);
+                // Pull in in front of hills that block the view
+                let target = match ground(&terrain_grid, &terrain_chunks).intersect_segment(&flight.position, &target) {
+                    Some(hit) => hit.point + hit.normal,
+                    None => target,
+                };
+                let position = glm::lerp(&camera.position(), &target, 1.0 - (-5.0 * delta_time).exp());
+                camera.set_pose(&position, -flight.yaw, 0.25);
+            } else {
+                let turn = (input.axis(Action::TurnX) as f64, input.axis(Action::TurnY) as f64);
+                if !disable_turn && !inspector_open && turn != (0.0, 0.0) {
+                    camera.turn(turn, delta_time);
+                }
+
+                let movement = [
+                    (Action::MoveForward, VecDir::Forward),
+                    (Action::MoveBackward, VecDir::Backward),
+                    (Action::MoveLeft, VecDir::Left),
+                    (Action::MoveRight, VecDir::Right),
+                    (Action::MoveUp, VecDir::Up),
+                    (Action::MoveDown, VecDir::Down),
+                ];
+                for (action, direction) in movement {
+                    if input.held(action) {
+                        camera.move_in_dir(direction, delta_time);
+                    }
+                }
+            }
+
+            // Keep the camera above the ground
+            let mut camera_position = camera.position();
+            if let Some(ground) = ground(&terrain_grid, &terrain_chunks).height_at(camera_position.x, camera_position.z) {
+                if camera_position.y < ground + 1.5 {
+                    camera_position.y = ground + 1.5;
+                    camera.set_position(&camera_position);
+                }
+            }
+
+            if input.pressed(Action::Select) {
+                let size = context.window().inner_size();
+                let screen_size = (size.width as f64, size.height as f64);
+                // The cursor is hidden while mouse look is on, then we pick whatever is in the middle of the screen
+                let cursor = if disable_turn || inspector_open { input.cursor_position() } else { (screen_size.0 / 2.0, screen_size.1 / 2.0) };
+                let (origin, direction) = camera.screen_ray(cursor, screen_size);
+                selected = scene_graph.pick(&origin, &direction)
+                    // The terrain is not pickable, but it still hides helicopters behind hills
+                    .filter(|hit| ground(&terrain_grid, &terrain_chunks).raycast(&origin, &direction, hit.distance).is_none())
+                    .and_then(|hit| helicopter_nodes.iter().position(|h| h.contains_node(hit.node)));
+            }
 
             unsafe {
                 gl::ClearColor(0.05, 0.05, 0.3, 1.0);
                 gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT | gl::STENCIL_BUFFER_BIT);
                 
-                scene_graph.draw(&camera, &mut drawn_vaos);
+                render_queue.view_position = camera.position();
+                render_queue.set_multi_draw_indirect(render_settings.multi_draw_indirect);
+                if let Some(chunks) = &mut terrain_chunks {
+                    chunks.update(&render_queue.view_position);
+                }
+
+                profiler.begin_pass("sky");
+                skybox.draw(&camera, &cubemap);
+                // The main shader reflects it
+                cubemap.bind(1);
+                profiler.end_pass();
+
+                if render_settings.wireframe {
+                    gl::PolygonMode(gl::FRONT_AND_BACK, gl::LINE);
+                }
+
+                profiler.begin_pass("scene");
+                if let Some(terrain) = &terrain_node.geometric_instance {
+                    terrain.set_visible(render_settings.terrain);
+                }
+                if let Some(chunks) = terrain_chunks.as_ref().filter(|_| render_settings.terrain) {
+                    chunks.queue_all(&mut render_queue);
+                }
+                scene_graph.queue_draw_items(&mut render_queue);
+                profiler.record_submit(&render_queue.submit());
+                profiler.end_pass();
+
+                gl::PolygonMode(gl::FRONT_AND_BACK, gl::FILL);
+
+                if let Some(i) = selected.filter(|_| render_settings.outline) {
+                    profiler.begin_pass("outline");
+                    outline::draw_outlined(&helicopter_nodes[i].geometric_instances(), outline_program_id);
+                    profiler.end_pass();
+                }
+
+                if render_settings.debug_draw {
+                    profiler.begin_pass("debug");
+                    debug_draw.scene_graph(&scene_graph, 2.0);
+                    for collider in scene_graph.colliders() {
+                        let touching = node_owners.owner(collider.node).is_some_and(|i| !helicopter_nodes[i].touching.is_empty());
+                        debug_draw.aabb(&collider.aabb, if touching { debug_draw::RED } else { debug_draw::GREEN });
+                    }
+                    if let Some(flight) = possessed.and_then(|i| helicopter_nodes[i].flight.as_ref()) {
+                        debug_draw.arrow(&flight.position, &(flight.position + flight.velocity), debug_draw::WHITE);
+                    }
+                    debug_draw.draw();
+                    profiler.end_pass();
+                }
+
+                profiler.begin_pass("overlay");
+                let size = context.window().inner_size();
+                text.set_screen_size(size.width as f32, size.height as f32);
+                let white = [1.0, 1.0, 1.0, 1.0];
+
+                frame_time += (delta_time - frame_time) * 0.05;
+                if render_settings.overlay {
+                    let p = camera.position();
+                    let mut status = format!("{:.0} fps\ncamera {:.1} {:.1} {:.1}", 1.0 / frame_time, p.x, p.y, p.z);
+                    if let Some(chunks) = &terrain_chunks {
+                        status += &format!("\nterrain chunks {} loaded, {} pending", chunks.loaded_chunks(), chunks.pending_chunks());
+                    }
+                    text.text(&status, (8.0, 8.0), Anchor::TopLeft, 1.0, white);
+
+                    if game_loop.is_paused() {
+                        text.text("PAUSED", (size.width as f32 / 2.0, 8.0), Anchor::Top, 1.5, [1.0, 0.8, 0.2, 1.0]);
+                    } else if game_loop.time_scale() != 1.0 {
+                        text.text(&format!("time x{}", game_loop.time_scale()), (size.width as f32 / 2.0, 8.0), Anchor::Top, 1.0, white);
+                    }
+
+                    if let Some(i) = selected {
+                        let h = &helicopter_nodes[i];
+                        let (mode, speed) = match &h.flight {
+                            Some(flight) => (if possessed == Some(i) { "flown" } else { "hovering" }, glm::length(&flight.velocity)),
+                            None => ("following path", h.follower.speed.abs()),
+                        };
+                        let p = h.root_node.position;
+                        let ground = ground(&terrain_grid, &terrain_chunks).height_at(p.x, p.z).unwrap_or(0.0);
+                        let mut info = format!("helicopter {}, {}\nposition {:.1} {:.1} {:.1}\naltitude {:.1}  speed {:.1}", i, mode, p.x, p.y, p.z, p.y - ground, speed);
+                        if !h.touching.is_empty() {
+                            info += &format!("\ntouching {:?}", h.touching);
+                        }
+                        text.text(
+                            &info,
+                            (8.0, size.height as f32 - 8.0), Anchor::BottomLeft, 1.0, white
+                        );
+                    }
+                }
+                text.draw();
+                profiler.end_pass();
+
+                profiler.begin_pass("gui");
+                gui.paint();
+                profiler.end_pass();
             }
             
+            // The swap waits for vsync, which is not cpu time of the frame
+            profiler.end_frame();
             context.swap_buffers().unwrap();
-            drawn_vaos.clear();
         }
     });
 
@@ -224,11 +784,13 @@ fn main() {
     let render_thread_healthy = Arc::new(RwLock::new(true));
     let render_thread_watchdog = Arc::clone(&render_thread_healthy);
     thread::spawn(move || {
-        if !render_thread.join().is_ok() {
-            if let Ok(mut health) = render_thread_watchdog.write() {
+        // The render thread only stops by itself when it panics or a replay is done, either way we shut down
+        let result = render_thread.join();
+        if let Ok(mut health) = render_thread_watchdog.write() {
+            if result.is_err() {
                 println!("Render thread panicked!");
-                *health = false;
             }
+            *health = false;
         }
     });
 
@@ -238,7 +800,7 @@ fn main() {
 
         // Terminate program if render thread panics
         if let Ok(health) = render_thread_healthy.read() {
-            if *health == false {
+            if !*health {
                 *control_flow = ControlFlow::Exit;
             }
         }
@@ -252,23 +814,47 @@ fn main() {
                 // }
                 // Send event to rendering thread
                 WindowEvent::KeyboardInput { input, ..} => {
-                    if let Err(e) = tx.send(InputEvent::Key(input)) {
-                        eprintln!("Seems reciever has died, e: {}", e);
+                    if let Some(code) = input.virtual_keycode {
+                        if let Err(e) = tx.send(InputEvent::Key(code, input.state)) {
+                            eprintln!("Seems reciever has died, e: {}", e);
+                        }
                     }
 
                     if let Some(Escape) = input.virtual_keycode {
                         *control_flow = ControlFlow::Exit;
                     }
                 },
-                _ => (),
-            }
-            Event::DeviceEvent { event, .. } => match event {
-                DeviceEvent::MouseMotion {delta} => {
-                    if let Err(e) = tx.send(InputEvent::Mouse(delta)) {
+                WindowEvent::CursorMoved { position, .. } => {
+                    if let Err(e) = tx.send(InputEvent::Cursor((position.x, position.y))) {
+                        eprintln!("Seems reciever has died, e: {}", e);
+                    }
+                },
+                WindowEvent::ReceivedCharacter(c) => {
+                    if let Err(e) = tx.send(InputEvent::Character(c)) {
+                        eprintln!("Seems reciever has died, e: {}", e);
+                    }
+                },
+                WindowEvent::MouseWheel { delta, .. } => {
+                    // Touchpads scroll in pixels, roughly a line for every 20 of them
+                    let lines = match delta {
+                        MouseScrollDelta::LineDelta(x, y) => (x as f64, y as f64),
+                        MouseScrollDelta::PixelDelta(p) => (p.x / 20.0, p.y / 20.0),
+                    };
+                    if let Err(e) = tx.send(InputEvent::Scroll(lines)) {
+                        eprintln!("Seems reciever has died, e: {}", e);
+                    }
+                },
+                WindowEvent::MouseInput { state, button, .. } => {
+                    if let Err(e) = tx.send(InputEvent::MouseButton(state, button)) {
                         eprintln!("Seems reciever has died, e: {}", e);
                     }
                 },
-                _ => { }
+                _ => (),
+            }
+            Event::DeviceEvent { event: DeviceEvent::MouseMotion { delta }, .. } => {
+                if let Err(e) = tx.send(InputEvent::Mouse(delta)) {
+                    eprintln!("Seems reciever has died, e: {}", e);
+                }
             }
             _ => { }
         }
@@ -1,4 +1,7 @@
-use crate::gl_utils::{geometric_object::GeometricObject, mesh::Helicopter, scene_graph::Node, scene_graph::SceneNode, toolbox::Heading, toolbox::simple_heading_animation};
+use std::{collections::HashMap, mem::ManuallyDrop, rc::Rc};
+
+use crate::flight_model::{FlightControls, FlightModel};
+use crate::gl_utils::{animation::{AnimationClip, Animator}, collision::{Aabb, CollisionEvent}, geometric_object::{GeometricInstance, GeometricObject}, height_grid::Ground, material::{BlendMode, Material}, mesh::Helicopter, path::{Path, PathFollower}, picking::PickMesh, scene_graph::Node, scene_graph::SceneNode};
 
 pub struct HelicopterNode {
     pub root_node: Node,
@@ -6,29 +9,155 @@ pub struct HelicopterNode {
     pub main_rotor_node: Node,
     pub tail_rotor_node: Node,
     pub door_node: Node,
-    pub heading: Heading,
-    pub heading_offset: f32,
-    pub pos_offset: glm::Vec3,
+    pub main_rotor_animator: Animator,
+    pub tail_rotor_animator: Animator,
+    pub door_animator: Animator,
+    pub follower: PathFollower,
+    /// Set once the helicopter has been taken over, it then never returns to its path
+    pub flight: Option<FlightModel>,
+    pub controls: FlightControls,
+    /// Helicopters this one is in contact with, kept up to date by handle_collision
+    pub touching: Vec<usize>,
 }
 
 impl HelicopterNode {
-    const PI: f32 = 3.14159265359;
-    const TWO_PI: f32 = 2.0 * HelicopterNode::PI;
+    /// Path heights are above the terrain, so path followers keep a fixed altitude over the ground
+    pub fn update(&mut self, delta_time: f32, terrain: &dyn Ground) {
+        let heading = match &mut self.flight {
+            Some(flight) => {
+                flight.ground_height = terrain.height_at(flight.position.x, flight.position.z).unwrap_or(f32::MIN);
+                flight.step(&self.controls, delta_time);
+                // The spin clips are authored at nominal rotor speed
+                self.main_rotor_animator.speed = flight.rotor_rpm;
+                self.tail_rotor_animator.speed = flight.rotor_rpm;
+                flight.heading()
+            },
+            None => {
+                self.follower.update(delta_time);
+                let mut heading = self.follower.heading();
+                heading.y += terrain.height_at(heading.x, heading.z).unwrap_or(0.0);
+                heading
+            }
+        };
+
+        self.main_rotor_animator.update(delta_time);
+        self.main_rotor_animator.apply(&mut self.main_rotor_node);
+        self.tail_rotor_animator.update(delta_time);
+        self.tail_rotor_animator.apply(&mut self.tail_rotor_node);
+        self.door_animator.update(delta_time);
+        self.door_animator.apply(&mut self.door_node);
+
+        self.root_node.position = glm::vec3(heading.x, heading.y, heading.z);
+        self.body_node.rotation.x = heading.pitch;
+        self.body_node.rotation.y = heading.yaw;
+        self.body_node.rotation.z = heading.roll;
+    }
+
+    /// Switch from following the path to the flight model, starting from the current position and velocity
+    pub fn take_control(&mut self) {
+        if self.flight.is_none() {
+            // The path height is relative to the ground, the flight model works in terrain space
+            let mut heading = self.follower.heading();
+            heading.y = self.root_node.position.y;
+            self.flight = Some(FlightModel::new(&heading, self.follower.velocity()));
+        }
+        self.controls = FlightControls::hover();
+    }
+
+    /// Nobody is flying it anymore, it holds a hover and drifts to a stop
+    pub fn release_control(&mut self) {
+        self.controls = FlightControls::hover();
+    }
+
+    /// Applies a collision event between two helicopters to both of them
+    pub fn handle_collision(helicopters: &mut [HelicopterNode], event: &CollisionEvent<usize>) {
+        match *event {
+            CollisionEvent::Began(a, b) => {
+                helicopters[a].touching.push(b);
+                helicopters[b].touching.push(a);
+            },
+            // One of them may have been despawned since
+            CollisionEvent::Ended(a, b) => {
+                for (i, other) in [(a, b), (b, a)] {
+                    if let Some(h) = helicopters.get_mut(i) {
+                        h.touching.retain(|&t| t != other);
+                    }
+                }
+            },
+        }
+    }
+
+    pub fn nodes(&self) -> [&SceneNode; 5] {
+        [&self.root_node, &self.body_node, &self.main_rotor_node, &self.tail_rotor_node, &self.door_node]
+    }
+
+    /// Whether node is one of the nodes of this helicopter
+    pub fn contains_node(&self, node: &SceneNode) -> bool {
+        self.nodes().iter().any(|&n| std::ptr::eq(n, node))
+    }
+
+    pub fn geometric_instances(&self) -> Vec<&GeometricInstance> {
+        [&self.body_node, &self.main_rotor_node, &self.tail_rotor_node, &self.door_node].iter()
+            .filter_map(|n| n.geometric_instance.as_ref())
+            .collect()
+    }
+
+    /// Opens a closed door and closes an open one, also works halfway through
+    pub fn toggle_door(&mut self) {
+        self.door_animator.reverse();
+    }
+}
+
+/// Which helicopter a scene node belongs to, updated as helicopters are spawned and despawned
+#[derive(Default)]
+pub struct NodeOwners {
+    owners: HashMap<*const SceneNode, usize>,
+}
+
+impl NodeOwners {
+    pub fn insert(&mut self, helicopter: &HelicopterNode, index: usize) {
+        for node in IntoIterator::into_iter(helicopter.nodes()) {
+            self.owners.insert(node, index);
+        }
+    }
 
-    pub fn update(&mut self, delta_time: f32, elapsed: f32) {
-        self.main_rotor_node.rotation.y = self.main_rotor_node.rotation.y % HelicopterNode::TWO_PI; // Avoid floating point errors
-        self.main_rotor_node.rotation.y += delta_time * HelicopterNode::TWO_PI * 3.0; // 3 times each second
+    pub fn remove(&mut self, helicopter: &HelicopterNode) {
+        for node in IntoIterator::into_iter(helicopter.nodes()) {
+            self.owners.remove(&(node as *const SceneNode));
+        }
+    }
 
-        self.tail_rotor_node.rotation.x = self.tail_rotor_node.rotation.x % HelicopterNode::TWO_PI;
-        self.tail_rotor_node.rotation.x += delta_time * HelicopterNode::TWO_PI * 3.0;
+    pub fn owner(&self, node: &SceneNode) -> Option<usize> {
+        self.owners.get(&(node as *const SceneNode)).cloned()
+    }
+}
+
+/// Clips shared by every helicopter
+struct HelicopterClips {
+    main_rotor_spin_up: Rc<AnimationClip>,
+    main_rotor_spin: Rc<AnimationClip>,
+    tail_rotor_spin_up: Rc<AnimationClip>,
+    tail_rotor_spin: Rc<AnimationClip>,
+    door_open: Rc<AnimationClip>,
+}
 
-        self.heading.update(elapsed + self.heading_offset);
-        self.root_node.position.x = self.heading.x + self.pos_offset.x;
-        self.root_node.position.y = self.pos_offset.y;
-        self.root_node.position.z = self.heading.z + self.pos_offset.z;
-        self.body_node.rotation.x = self.heading.pitch;
-        self.body_node.rotation.y = self.heading.yaw;
-        self.body_node.rotation.z = self.heading.roll;
+impl HelicopterClips {
+    fn load(directory: &str) -> Self {
+        let load = |name: &str| {
+            let path = format!("{}/{}.anim", directory, name);
+            match AnimationClip::load(&path) {
+                Ok(clip) => Rc::new(clip),
+                Err(e) => panic!("Failed to load animation {}, e: {}", path, e),
+            }
+        };
+
+        Self {
+            main_rotor_spin_up: load("main_rotor_spin_up"),
+            main_rotor_spin: load("main_rotor_spin"),
+            tail_rotor_spin_up: load("tail_rotor_spin_up"),
+            tail_rotor_spin: load("tail_rotor_spin"),
+            door_open: load("door_open"),
+        }
     }
 }
 
@@ -37,69 +166,114 @@ pub struct MyHelicopter {
     main_rotor_geometry: GeometricObject,
     tail_rotor_geometry: GeometricObject,
     door_geometry: GeometricObject,
-    max_instance: usize,
-    last_instance: usize
+    clips: HelicopterClips,
 }
 
 impl MyHelicopter {
+    /// Instance buffers grow on demand, count is only the initial capacity
     pub fn init(program_id: u32, count: usize) -> Self {
-        let transforms: Vec<glm::Mat4> = vec![glm::identity(); count];
         let (body_geometry, main_rotor_geometry, tail_rotor_geometry, door_geometry) = {
             let h = Helicopter::load("assets/objs/helicopter.obj");
-            // We dissect helicopter to make it easier to take ownership of each mesh
+            // We dissect helicopter to make it easier to take ownership of each mesh, the pick meshes are copied out first
+            let (body_pick, main_rotor_pick) = (PickMesh::from_mesh(&h.body), PickMesh::from_mesh(&h.main_rotor));
+            let (tail_rotor_pick, door_pick) = (PickMesh::from_mesh(&h.tail_rotor), PickMesh::from_mesh(&h.door));
+            // The door sits inside the body's bounds, so it does not need its own
+            let (body_bounds, main_rotor_bounds, tail_rotor_bounds) = (Aabb::from_mesh(&h.body), Aabb::from_mesh(&h.main_rotor), Aabb::from_mesh(&h.tail_rotor));
             (
-                h.body.into_geomtric_object(program_id, &transforms), 
-                h.main_rotor.into_geomtric_object(program_id, &transforms), 
-                h.tail_rotor.into_geomtric_object(program_id, &transforms), 
-                h.door.into_geomtric_object(program_id, &transforms)
+                h.body.into_geomtric_object(program_id, &[]).with_material(Material::new(2, BlendMode::Opaque).with_reflectivity(0.3)).with_pick_mesh(body_pick).with_bounds(body_bounds),
+                h.main_rotor.into_geomtric_object(program_id, &[]).with_pick_mesh(main_rotor_pick).with_bounds(main_rotor_bounds),
+                h.tail_rotor.into_geomtric_object(program_id, &[]).with_pick_mesh(tail_rotor_pick).with_bounds(tail_rotor_bounds),
+                h.door.into_geomtric_object(program_id, &[]).with_material(Material::new(1, BlendMode::Alpha)).with_pick_mesh(door_pick)
             )
         };
 
-        Self {
+        let helicopter = Self {
             body_geometry,
             main_rotor_geometry,
             tail_rotor_geometry,
             door_geometry,
-            max_instance: count,
-            last_instance: 0
-        }
+            clips: HelicopterClips::load("assets/animations"),
+        };
+        helicopter.reserve(count);
+
+        helicopter
     }
 
-    // TODO: error not option
-    pub fn create_helicopter_node(&mut self, heading_offset: f32, pos_offset: glm::Vec3) -> Option<HelicopterNode> {
-        if self.last_instance >= self.max_instance {
-            return None;
-        }
+    /// Make sure there is room for count helicopters without growing the instance buffers
+    pub fn reserve(&self, count: usize) {
+        self.body_geometry.reserve_instances(count);
+        self.main_rotor_geometry.reserve_instances(count);
+        self.tail_rotor_geometry.reserve_instances(count);
+        self.door_geometry.reserve_instances(count);
+    }
 
+    /// The helicopter flies path at speed, starting start_distance along it. pos_offset moves the whole path
+    pub fn create_helicopter_node(&mut self, path: Rc<Path>, speed: f32, start_distance: f32, pos_offset: glm::Vec3) -> HelicopterNode {
         let mut root_node = SceneNode::new();
-        let body_instance = self.body_geometry.create_geometric_instance(self.last_instance).expect("failed to create body instance");
+        root_node.name = "helicopter".to_string();
+        let body_instance = self.body_geometry.allocate_geometric_instance();
         let mut body_node = SceneNode::from_vao(body_instance);
+        body_node.name = "body".to_string();
         root_node.add_child(&body_node);
-            
-        let main_rotor_instance = self.main_rotor_geometry.create_geometric_instance(self.last_instance).expect("failed to create main rotor instance");
+
+        let main_rotor_instance = self.main_rotor_geometry.allocate_geometric_instance();
         let mut main_rotor_node = SceneNode::from_vao(main_rotor_instance);
+        main_rotor_node.name = "main rotor".to_string();
         body_node.add_child(&main_rotor_node);
 
-        let tail_rot_instance = self.tail_rotor_geometry.create_geometric_instance(self.last_instance).expect("failed to create tail rotor instance");
+        let tail_rot_instance = self.tail_rotor_geometry.allocate_geometric_instance();
         let mut tail_rotor_node = SceneNode::from_vao(tail_rot_instance);
+        tail_rotor_node.name = "tail rotor".to_string();
         tail_rotor_node.set_reference_point(glm::vec3(0.35,2.3,10.4));
         body_node.add_child(&tail_rotor_node);
-        
-        let door_instance = self.door_geometry.create_geometric_instance(self.last_instance).expect("failed to create door instance");
-        let door_node = SceneNode::from_vao(door_instance);
+
+        let door_instance = self.door_geometry.allocate_geometric_instance();
+        let mut door_node = SceneNode::from_vao(door_instance);
+        door_node.name = "door".to_string();
         body_node.add_child(&door_node);
 
-        self.last_instance += 1;
+        // Rotors spin up when the helicopter appears, then keep spinning
+        let mut main_rotor_animator = Animator::new(Rc::clone(&self.clips.main_rotor_spin_up));
+        main_rotor_animator.then(Rc::clone(&self.clips.main_rotor_spin));
+        let mut tail_rotor_animator = Animator::new(Rc::clone(&self.clips.tail_rotor_spin_up));
+        tail_rotor_animator.then(Rc::clone(&self.clips.tail_rotor_spin));
+
+        // Playing backwards keeps the door closed until it is toggled
+        let mut door_animator = Animator::new(Rc::clone(&self.clips.door_open));
+        door_animator.speed = -1.0;
 
-        Some(HelicopterNode {
+        HelicopterNode {
             root_node,
             body_node,
             main_rotor_node,
             tail_rotor_node,
             door_node,
-            heading: Heading::new(),
-            heading_offset,
-            pos_offset
-        })
+            main_rotor_animator,
+            tail_rotor_animator,
+            door_animator,
+            follower: PathFollower::new(path, speed).start_at(start_distance).offset(pos_offset),
+            flight: None,
+            controls: FlightControls::hover(),
+            touching: Vec::new(),
+        }
+    }
+
+    /// Frees the instance slots of the helicopter so they can be reused by the next created helicopter.
+    /// The caller is responsible for removing the root node from its parent first
+    pub fn destroy_helicopter_node(&mut self, helicopter: HelicopterNode) {
+        let nodes = vec![
+            helicopter.root_node,
+            helicopter.body_node,
+            helicopter.main_rotor_node,
+            helicopter.tail_rotor_node,
+            helicopter.door_node,
+        ];
+
+        for node in nodes {
+            let mut node = ManuallyDrop::into_inner(node);
+            if let Some(g) = node.geometric_instance.take() {
+                g.free();
+            }
+        }
     }
-}
\ No newline at end of file
+}
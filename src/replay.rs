@@ -0,0 +1,264 @@
+use glutin::event::{ElementState, MouseButton};
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    fs::{self, File},
+    io::{self, BufWriter, Write}
+};
+
+use crate::{gamepad::GamepadEvent, gl_utils::parsing, input::{self, InputEvent}};
+
+// Text format, one record per line:
+//   gloom-input-recording <version>
+//   frame <elapsed> <delta_time>
+//   key <VirtualKeyCode> pressed|released
+//   mouse <dx> <dy>
+//   cursor <x> <y>
+//   scroll <dx> <dy>
+//   character <unicode scalar value>
+//   mouse_button <Left|Right|Middle|n> pressed|released
+//   gamepad_button <GamepadButton> pressed|released
+//   gamepad_axis <GamepadAxis> <value>
+//   gamepad_disconnected
+// Events belong to the last frame line before them. Floats are written with Debug formatting,
+// which round trips exactly, so a replay reproduces the recorded session bit for bit
+const HEADER: &str = "gloom-input-recording";
+const VERSION: u32 = 1;
+
+/// Everything the simulation consumes in one frame
+pub struct FrameInput {
+    pub elapsed: f32,
+    pub delta_time: f32,
+    pub events: Vec<InputEvent>,
+}
+
+pub struct InputRecorder {
+    writer: BufWriter<File>,
+}
+
+impl Drop for InputRecorder {
+    fn drop(&mut self) {
+        if let Err(e) = self.writer.flush() {
+            eprintln!("Failed to flush input recording, e: {}", e);
+        }
+    }
+}
+
+impl InputRecorder {
+    pub fn create(path: &str) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "{} {}", HEADER, VERSION)?;
+
+        Ok(Self {
+            writer
+        })
+    }
+
+    pub fn record_frame(&mut self, frame: &FrameInput) -> io::Result<()> {
+        writeln!(self.writer, "frame {:?} {:?}", frame.elapsed, frame.delta_time)?;
+        for event in &frame.events {
+            match event {
+                InputEvent::Key(code, state) => {
+                    // Keys that can't be bound can't affect the simulation, so there is no need to store them
+                    let name = format!("{:?}", code);
+                    if input::key_from_name(&name).is_some() {
+                        writeln!(self.writer, "key {} {}", name, state_name(*state))?
+                    }
+                },
+                InputEvent::Mouse((dx, dy)) => writeln!(self.writer, "mouse {:?} {:?}", dx, dy)?,
+                InputEvent::Cursor((x, y)) => writeln!(self.writer, "cursor {:?} {:?}", x, y)?,
+                InputEvent::Scroll((dx, dy)) => writeln!(self.writer, "scroll {:?} {:?}", dx, dy)?,
+                InputEvent::Character(c) => writeln!(self.writer, "character {}", *c as u32)?,
+                InputEvent::MouseButton(state, button) => {
+                    let button = match button {
+                        MouseButton::Left => "Left".to_string(),
+                        MouseButton::Right => "Right".to_string(),
+                        MouseButton::Middle => "Middle".to_string(),
+                        MouseButton::Other(n) => n.to_string(),
+                    };
+                    writeln!(self.writer, "mouse_button {} {}", button, state_name(*state))?
+                },
+                InputEvent::Gamepad(GamepadEvent::Button(button, state)) => writeln!(self.writer, "gamepad_button {:?} {}", button, state_name(*state))?,
+                InputEvent::Gamepad(GamepadEvent::Axis(axis, value)) => writeln!(self.writer, "gamepad_axis {:?} {:?}", axis, value)?,
+                InputEvent::Gamepad(GamepadEvent::Disconnected) => writeln!(self.writer, "gamepad_disconnected")?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn state_name(state: ElementState) -> &'static str {
+    match state {
+        ElementState::Pressed => "pressed",
+        ElementState::Released => "released",
+    }
+}
+
+pub enum ReplayError {
+    Io(io::Error),
+    Header,
+    Parse(usize, String),
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplayError::Io(e) => e.fmt(f),
+            ReplayError::Header => write!(f, "not a {} version {} file", HEADER, VERSION),
+            ReplayError::Parse(line, content) => write!(f, "line {}: failed to parse '{}'", line, content),
+        }
+    }
+}
+
+/// Recorded frames, fed to the render loop instead of live input
+pub struct InputReplay {
+    frames: VecDeque<FrameInput>,
+}
+
+impl InputReplay {
+    pub fn load(path: &str) -> Result<Self, ReplayError> {
+        let src = fs::read_to_string(path).map_err(ReplayError::Io)?;
+        Self::parse(&src)
+    }
+
+    pub fn parse(src: &str) -> Result<Self, ReplayError> {
+        let mut lines = parsing::lines(src);
+        match lines.next() {
+            Some(header) if header.text == format!("{} {}", HEADER, VERSION) => { },
+            _ => return Err(ReplayError::Header),
+        }
+
+        let mut frames = VecDeque::new();
+        for line in lines {
+            let error = || ReplayError::Parse(line.number, line.text.to_string());
+            let parts = &line.words;
+            if parts[0] == "frame" {
+                if parts.len() != 3 {
+                    return Err(error());
+                }
+
+                frames.push_back(FrameInput {
+                    elapsed: parts[1].parse().map_err(|_| error())?,
+                    delta_time: parts[2].parse().map_err(|_| error())?,
+                    events: Vec::new(),
+                });
+                continue;
+            }
+
+            let event = parse_event(parts).ok_or_else(error)?;
+            match frames.back_mut() {
+                Some(frame) => frame.events.push(event),
+                None => return Err(error()),
+            }
+        }
+
+        Ok(Self {
+            frames
+        })
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn next_frame(&mut self) -> Option<FrameInput> {
+        self.frames.pop_front()
+    }
+}
+
+fn parse_state(s: &str) -> Option<ElementState> {
+    match s {
+        "pressed" => Some(ElementState::Pressed),
+        "released" => Some(ElementState::Released),
+        _ => None,
+    }
+}
+
+fn parse_event(parts: &[&str]) -> Option<InputEvent> {
+    let event = match parts {
+        ["key", code, state] => InputEvent::Key(input::key_from_name(code)?, parse_state(state)?),
+        ["mouse", dx, dy] => InputEvent::Mouse((dx.parse().ok()?, dy.parse().ok()?)),
+        ["cursor", x, y] => InputEvent::Cursor((x.parse().ok()?, y.parse().ok()?)),
+        ["scroll", dx, dy] => InputEvent::Scroll((dx.parse().ok()?, dy.parse().ok()?)),
+        ["character", c] => InputEvent::Character(char::from_u32(c.parse().ok()?)?),
+        ["mouse_button", button, state] => {
+            let button = match *button {
+                "Left" => MouseButton::Left,
+                "Right" => MouseButton::Right,
+                "Middle" => MouseButton::Middle,
+                n => MouseButton::Other(n.parse().ok()?),
+            };
+            InputEvent::MouseButton(parse_state(state)?, button)
+        },
+        ["gamepad_button", button, state] => InputEvent::Gamepad(GamepadEvent::Button(button.parse().ok()?, parse_state(state)?)),
+        ["gamepad_axis", axis, value] => InputEvent::Gamepad(GamepadEvent::Axis(axis.parse().ok()?, value.parse().ok()?)),
+        ["gamepad_disconnected"] => InputEvent::Gamepad(GamepadEvent::Disconnected),
+        _ => return None,
+    };
+
+    Some(event)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gamepad::{GamepadAxis, GamepadButton};
+    use glutin::event::VirtualKeyCode;
+
+    #[test]
+    fn recorded_frames_replay_exactly() {
+        let frames = vec![
+            FrameInput {
+                elapsed: 0.016_667,
+                delta_time: 0.016_667,
+                events: vec![
+                    InputEvent::Key(VirtualKeyCode::W, ElementState::Pressed),
+                    InputEvent::Mouse((0.1, -3.25)),
+                    InputEvent::Cursor((640.5, 360.0)),
+                    InputEvent::Scroll((0.0, 1.0)),
+                    InputEvent::Character('é'),
+                    InputEvent::MouseButton(ElementState::Released, MouseButton::Other(4)),
+                ],
+            },
+            FrameInput { elapsed: 0.05, delta_time: 0.033_333, events: Vec::new() },
+            FrameInput {
+                elapsed: 0.1 + f32::EPSILON,
+                delta_time: 1.0 / 3.0,
+                events: vec![
+                    InputEvent::Gamepad(GamepadEvent::Button(GamepadButton::South, ElementState::Pressed)),
+                    InputEvent::Gamepad(GamepadEvent::Axis(GamepadAxis::LeftTrigger, 0.123_456_79)),
+                    InputEvent::Gamepad(GamepadEvent::Disconnected),
+                ],
+            },
+        ];
+
+        let path = std::env::temp_dir().join(format!("gloom-replay-{}.txt", std::process::id()));
+        let path = path.to_str().unwrap();
+        {
+            let mut recorder = InputRecorder::create(path).unwrap();
+            for frame in &frames {
+                recorder.record_frame(frame).unwrap();
+            }
+        }
+        let replay = InputReplay::load(path);
+        fs::remove_file(path).unwrap();
+        let mut replay = replay.unwrap_or_else(|e| panic!("{}", e));
+
+        assert_eq!(replay.remaining(), frames.len());
+        for frame in &frames {
+            let replayed = replay.next_frame().unwrap();
+            assert_eq!(replayed.elapsed.to_bits(), frame.elapsed.to_bits());
+            assert_eq!(replayed.delta_time.to_bits(), frame.delta_time.to_bits());
+            assert_eq!(replayed.events, frame.events);
+        }
+    }
+
+    #[test]
+    fn events_before_the_first_frame_are_an_error() {
+        let src = format!("{} {}\nkey W pressed\nframe 0.0 0.1\n", HEADER, VERSION);
+        assert!(matches!(InputReplay::parse(&src), Err(ReplayError::Parse(2, _))));
+        assert!(matches!(InputReplay::parse("frame 0.0 0.1\n"), Err(ReplayError::Header)));
+    }
+}